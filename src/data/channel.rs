@@ -1,12 +1,17 @@
-use super::password_hash;
+use super::{decode_with_defaults, mask_matches, password_hash};
+use std::ascii::AsciiExt;
 use std::borrow::ToOwned;
-use std::fs::{File, create_dir_all};
+use std::fs::{File, create_dir_all, read_dir};
 use std::io::{Error, ErrorKind, Result};
 use std::io::prelude::*;
 use std::path::Path;
-use rustc_serialize::json::{decode, encode};
+use rustc_serialize::json::encode;
+use data::mode::ModeLock;
+use data::role::{Role, RoleAssignment};
+use data::settings::ChannelSettings;
+use data::suspension::Suspension;
 
-#[derive(RustcEncodable, RustcDecodable, Debug, PartialEq)]
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug, PartialEq)]
 pub struct Channel {
     pub name: String,
     pub password: String,
@@ -15,7 +20,14 @@ pub struct Channel {
     pub opers: Vec<String>,
     pub voice: Vec<String>,
     pub topic: String,
+    pub topic_setter: Option<String>,
+    pub topic_time: Option<i64>,
     pub mode: String,
+    pub mlock: Option<ModeLock>,
+    pub settings: ChannelSettings,
+    pub bans: Option<Vec<Ban>>,
+    pub suspension: Option<Suspension>,
+    pub roles: Option<Vec<RoleAssignment>>,
 }
 
 impl Channel {
@@ -26,14 +38,152 @@ impl Channel {
             owner: owner.to_owned(),
             admins: Vec::new(), opers: Vec::new(), voice: Vec::new(),
             topic: String::new(),
+            topic_setter: None,
+            topic_time: None,
             mode: String::new(),
+            mlock: None,
+            settings: ChannelSettings::new(),
+            bans: None,
+            suspension: None,
+            roles: None,
         })
     }
 
+    pub fn active_suspension(&self, now: i64) -> Option<&Suspension> {
+        self.suspension.as_ref().and_then(|s| if s.is_active(now) { Some(s) } else { None })
+    }
+
     pub fn is_password(&self, password: &str) -> Result<bool> {
         Ok(self.password == try!(password_hash(password)))
     }
 
+    /// Returns the access recorded for the given account, regardless of whether it's online.
+    pub fn access_of(&self, user: &str) -> AccessLevel {
+        let listed = self.listed_access_of(user);
+        let role = self.role_of(user).and_then(|r| Role::load(r).ok()).map(|r| r.level);
+        match role {
+            Some(level) if level > listed => level,
+            _ => listed,
+        }
+    }
+
+    /// Returns the roles assigned in this channel.
+    pub fn roles(&self) -> &[RoleAssignment] {
+        match self.roles {
+            Some(ref roles) => &roles[..],
+            None => &[],
+        }
+    }
+
+    pub fn role_of(&self, user: &str) -> Option<&str> {
+        self.roles().iter().find(|r| &r.account[..] == user).map(|r| &r.role[..])
+    }
+
+    /// Assigns a role to an account, or removes its role when `role` is `None`.
+    pub fn assign_role(&mut self, user: &str, role: Option<&str>) {
+        let mut roles = self.roles.take().unwrap_or(Vec::new());
+        roles.retain(|r| &r.account[..] != user);
+        if let Some(role) = role {
+            roles.push(RoleAssignment::new(user, role));
+        }
+        self.roles = Some(roles);
+    }
+
+    /// Drops every assignment of a role, returning true if there were any.
+    pub fn unassign_role(&mut self, role: &str) -> bool {
+        let before = self.roles().len();
+        if let Some(ref mut roles) = self.roles {
+            roles.retain(|r| !r.role.eq_ignore_ascii_case(role));
+        }
+        self.roles().len() != before
+    }
+
+    fn listed_access_of(&self, user: &str) -> AccessLevel {
+        if &self.owner[..] == user {
+            AccessLevel::Owner
+        } else if self.admins.iter().any(|u| &u[..] == user) {
+            AccessLevel::Admin
+        } else if self.opers.iter().any(|u| &u[..] == user) {
+            AccessLevel::Oper
+        } else if self.voice.iter().any(|u| &u[..] == user) {
+            AccessLevel::Voice
+        } else {
+            AccessLevel::None
+        }
+    }
+
+    /// Replaces every access entry for `old` with `new`, returning true if anything changed.
+    pub fn rename_user(&mut self, old: &str, new: &str) -> bool {
+        let mut changed = false;
+        if &self.owner[..] == old {
+            self.owner = new.to_owned();
+            changed = true;
+        }
+        for list in [&mut self.admins, &mut self.opers, &mut self.voice].iter_mut() {
+            for user in list.iter_mut() {
+                if &user[..] == old {
+                    *user = new.to_owned();
+                    changed = true;
+                }
+            }
+        }
+        if let Some(ref mut roles) = self.roles {
+            for assignment in roles.iter_mut().filter(|r| &r.account[..] == old) {
+                assignment.account = new.to_owned();
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Returns the bans set through services.
+    pub fn bans(&self) -> &[Ban] {
+        match self.bans {
+            Some(ref bans) => &bans[..],
+            None => &[],
+        }
+    }
+
+    /// Records a ban set through services, replacing any earlier record for the same mask.
+    pub fn add_ban(&mut self, ban: Ban) {
+        let mut bans = self.bans.take().unwrap_or(Vec::new());
+        bans.retain(|b| b.mask != ban.mask);
+        bans.push(ban);
+        self.bans = Some(bans);
+    }
+
+    /// Removes the recorded ban on a mask, returning true if there was one.
+    pub fn remove_ban(&mut self, mask: &str) -> bool {
+        let before = self.bans().len();
+        if let Some(ref mut bans) = self.bans {
+            bans.retain(|b| &b.mask[..] != mask);
+        }
+        self.bans().len() != before
+    }
+
+    /// Removes and returns every recorded ban matching the given `nick!user@host`.
+    pub fn remove_bans_matching(&mut self, hostmask: &str) -> Vec<Ban> {
+        let (removed, kept): (Vec<Ban>, Vec<Ban>) = self.bans().iter().cloned().partition(|b| {
+            mask_matches(&b.mask, hostmask)
+        });
+        self.bans = Some(kept);
+        removed
+    }
+
+    pub fn list() -> Result<Vec<String>> {
+        let mut ret = Vec::new();
+        for entry in try!(read_dir(Path::new("data/chanserv/"))) {
+            let path = try!(entry).path();
+            if path.extension().map_or(false, |e| e == "json") {
+                if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+                    ret.push(name.to_owned());
+                }
+            }
+        }
+        ret.sort();
+        Ok(ret)
+    }
+
     pub fn exists(name: &str) -> bool {
         Path::new(&format!("data/chanserv/{}.json", name)).exists()
     }
@@ -43,7 +193,8 @@ impl Channel {
         let mut file = try!(File::open(Path::new(&path)));
         let mut data = String::new();
         try!(file.read_to_string(&mut data));
-        decode(&data).map_err(|_| Error::new(
+        let defaults = try!(Channel::new(name, "", ""));
+        decode_with_defaults(&data, &defaults).ok_or(Error::new(
             ErrorKind::InvalidInput, "Failed to decode channel data."
         ))
     }
@@ -61,12 +212,64 @@ impl Channel {
     }
 }
 
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug, PartialEq)]
+pub struct Ban {
+    pub mask: String,
+    pub setter: String,
+    pub reason: Option<String>,
+    pub time: i64,
+    pub expires: Option<i64>,
+}
+
+impl Ban {
+    pub fn new(mask: &str, setter: &str, reason: Option<&str>, time: i64, duration: Option<i64>)
+        -> Ban {
+        Ban {
+            mask: mask.to_owned(),
+            setter: setter.to_owned(),
+            reason: reason.map(|r| r.to_owned()),
+            time: time,
+            expires: duration.map(|d| time + d),
+        }
+    }
+}
+
+#[derive(RustcEncodable, RustcDecodable, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AccessLevel {
+    None,
+    Voice,
+    Oper,
+    Admin,
+    Owner,
+}
+
+impl AccessLevel {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            AccessLevel::None => "no",
+            AccessLevel::Voice => "voice",
+            AccessLevel::Oper => "oper",
+            AccessLevel::Admin => "admin",
+            AccessLevel::Owner => "owner",
+        }
+    }
+}
+
+/// Checks that a channel name is a plain `#` channel that is also safe to use as a file name.
+pub fn is_valid_channel_name(name: &str) -> bool {
+    name.starts_with("#") && name.len() > 1 && !name.contains("..") &&
+        !name.chars().any(|c| c.is_whitespace() || c.is_control() || c == ',' || c == '/')
+}
+
 #[cfg(test)]
 mod test {
     use super::super::password_hash;
-    use super::Channel;
+    use super::{AccessLevel, Ban, Channel, is_valid_channel_name};
+    use data::role::Role;
+    use data::settings::ChannelSettings;
     use std::borrow::ToOwned;
-    use std::fs::remove_file;
+    use std::fs::{File, create_dir_all, remove_file};
+    use std::io::prelude::*;
     use std::path::Path;
 
     #[test]
@@ -77,7 +280,14 @@ mod test {
             owner: "test".to_owned(),
             admins: Vec::new(), opers: Vec::new(), voice: Vec::new(),
             topic: "".to_owned(),
+            topic_setter: None,
+            topic_time: None,
             mode: "".to_owned(),
+            mlock: None,
+            settings: ChannelSettings::new(),
+            bans: None,
+            suspension: None,
+            roles: None,
         });
     }
 
@@ -90,6 +300,17 @@ mod test {
         assert!(Channel::exists("#test2"));
     }
 
+    #[test]
+    fn valid_channel_name() {
+        assert!(is_valid_channel_name("#test"));
+        assert!(is_valid_channel_name("#c++"));
+        assert!(!is_valid_channel_name("#"));
+        assert!(!is_valid_channel_name("test"));
+        assert!(!is_valid_channel_name("&test"));
+        assert!(!is_valid_channel_name("#../nickserv/test"));
+        assert!(!is_valid_channel_name("#a,#b"));
+    }
+
     #[test]
     fn save() {
         let ch = Channel::new("#test", "test", "test").unwrap();
@@ -104,4 +325,55 @@ mod test {
         assert!(ld.is_ok());
         assert_eq!(ld.unwrap(), ch);
     }
+
+    #[test]
+    fn load_baseline_record() {
+        let _ = create_dir_all(Path::new("data/chanserv/"));
+        let mut f = File::create(Path::new("data/chanserv/#test64.json")).unwrap();
+        f.write_all(b"{\"name\":\"#test64\",\"password\":\"x\",\"owner\":\"test\",\"admins\":[],\
+                      \"opers\":[],\"voice\":[],\"topic\":\"\",\"mode\":\"\"}").unwrap();
+        let ch = Channel::load("#test64").unwrap();
+        assert_eq!(&ch.owner[..], "test");
+        assert!(ch.mlock.is_none());
+        assert_eq!(ch.settings, ChannelSettings::new());
+    }
+
+    #[test]
+    fn access_of() {
+        let mut ch = Channel::new("#test", "test", "test").unwrap();
+        ch.opers.push("test2".to_owned());
+        assert_eq!(ch.access_of("test"), AccessLevel::Owner);
+        assert_eq!(ch.access_of("test2"), AccessLevel::Oper);
+        assert_eq!(ch.access_of("test3"), AccessLevel::None);
+        assert!(AccessLevel::Admin > AccessLevel::Oper);
+    }
+
+    #[test]
+    fn access_of_role() {
+        Role::new("tester2", "test", AccessLevel::Admin).save().unwrap();
+        let mut ch = Channel::new("#test", "test", "test").unwrap();
+        ch.voice.push("test2".to_owned());
+        ch.assign_role("test2", Some("Tester2"));
+        assert_eq!(ch.role_of("test2"), Some("tester2"));
+        assert_eq!(ch.access_of("test2"), AccessLevel::Admin);
+        ch.assign_role("test2", None);
+        assert_eq!(ch.access_of("test2"), AccessLevel::Voice);
+        ch.assign_role("test2", Some("tester2"));
+        assert!(ch.unassign_role("Tester2"));
+        assert_eq!(ch.role_of("test2"), None);
+    }
+
+    #[test]
+    fn bans() {
+        let mut ch = Channel::new("#test", "test", "test").unwrap();
+        ch.add_ban(Ban::new("*!*@bad.example.com", "test", None, 0, Some(60)));
+        ch.add_ban(Ban::new("troll!*@*", "test", Some("trolling"), 0, None));
+        ch.add_ban(Ban::new("*!*@bad.example.com", "test", None, 10, None));
+        assert_eq!(ch.bans().len(), 2);
+        let removed = ch.remove_bans_matching("someone!user@bad.example.com");
+        assert_eq!(removed, vec![Ban::new("*!*@bad.example.com", "test", None, 10, None)]);
+        assert_eq!(&ch.bans()[0].mask[..], "troll!*@*");
+        assert!(ch.remove_ban("troll!*@*"));
+        assert!(!ch.remove_ban("troll!*@*"));
+    }
 }
@@ -79,6 +79,12 @@ impl Democracy {
         }
     }
 
+    /// Drops a proposal without a result, returning false if it was already decided.
+    pub fn expire(&mut self, proposal_id: u8) -> bool {
+        self.delete_all_votes(proposal_id);
+        self.proposals.remove(&proposal_id).is_some()
+    }
+
     pub fn get_active_proposals(&self) -> Vec<String> {
         let mut ret = Vec::new();
         for (id, proposal)  in self.proposals.iter() {
@@ -89,6 +95,17 @@ impl Democracy {
         ret
     }
 
+    pub fn get_votes_by(&self, user: &str) -> Vec<String> {
+        self.votes.get(user).map_or(Vec::new(), |votes| votes.iter().map(|vote| {
+            let choice = match vote { &Vote::Yea(_) => "yea", &Vote::Nay(_) => "nay" };
+            match self.proposals.get(&vote.id()) {
+                Some(proposal) => format!("{} on proposal ({}) to {}", choice, vote.id(),
+                                          proposal.display()),
+                None => format!("{} on proposal ({})", choice, vote.id()),
+            }
+        }).collect())
+    }
+
     pub fn is_full_vote(&self, proposal_id: u8) -> bool {
         self.proposals.get(&proposal_id).map(|p| p.is_full_vote()).unwrap_or(false)
     }
@@ -0,0 +1,110 @@
+use std::borrow::ToOwned;
+use data::channel::{AccessLevel, Channel};
+use data::memo::{Memo, MemoBox};
+use data::settings::Settings;
+use data::suspension::Suspension;
+use data::user::{HostRecord, User};
+use rustc_serialize::json::encode;
+
+#[derive(RustcEncodable, Debug, PartialEq)]
+pub struct ChannelAccess {
+    pub channel: String,
+    pub access: String,
+}
+
+/// Everything stored about an account, minus password hashes and two-factor secrets.
+#[derive(RustcEncodable, Debug, PartialEq)]
+pub struct AccountExport {
+    pub nickname: String,
+    pub email: Option<String>,
+    pub certfps: Vec<String>,
+    pub access: Vec<String>,
+    pub access_identifies: bool,
+    pub totp_enabled: bool,
+    pub suspension: Option<Suspension>,
+    pub history: Vec<HostRecord>,
+    pub vhost: Option<String>,
+    pub settings: Settings,
+    pub channels: Vec<ChannelAccess>,
+    pub memos: Vec<Memo>,
+    pub votes: Vec<String>,
+    pub games: Vec<String>,
+}
+
+impl AccountExport {
+    pub fn new(user: &User, votes: Vec<String>, games: Vec<String>) -> AccountExport {
+        let channels = Channel::list().unwrap_or(Vec::new()).iter().filter_map(|name| {
+            Channel::load(name).ok().map(|chan| chan.access_of(&user.nickname)).and_then(|a| {
+                if a == AccessLevel::None {
+                    None
+                } else {
+                    Some(ChannelAccess { channel: name.clone(), access: a.name().to_owned() })
+                }
+            })
+        }).collect();
+        AccountExport {
+            nickname: user.nickname.clone(),
+            email: user.email.clone(),
+            certfps: user.certfps.clone(),
+            access: user.access.clone(),
+            access_identifies: user.access_identifies,
+            totp_enabled: user.has_totp(),
+            suspension: user.suspension.clone(),
+            history: user.history.clone(),
+            vhost: user.vhost.clone(),
+            settings: user.settings.clone(),
+            channels: channels,
+            memos: MemoBox::load(&user.nickname).map(|m| m.memos).unwrap_or(Vec::new()),
+            votes: votes,
+            games: games,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        encode(self).unwrap_or("{}".to_owned())
+    }
+}
+
+/// Splits a document into pieces no longer than `size` bytes, respecting char boundaries.
+pub fn paginate(document: &str, size: usize) -> Vec<String> {
+    let mut ret = Vec::new();
+    let mut current = String::new();
+    for c in document.chars() {
+        if current.len() + c.len_utf8() > size {
+            ret.push(current);
+            current = String::new();
+        }
+        current.push(c);
+    }
+    if !current.is_empty() {
+        ret.push(current);
+    }
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AccountExport, paginate};
+    use std::borrow::ToOwned;
+    use data::user::User;
+
+    #[test]
+    fn excludes_secrets() {
+        let mut u = User::new("test63", "hunter2", Some("test63@example.com")).unwrap();
+        u.enable_totp("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ", &["recovery".to_owned()]).unwrap();
+        let export = AccountExport::new(&u, Vec::new(), Vec::new());
+        assert!(export.totp_enabled);
+        let json = export.to_json();
+        assert!(json.contains("test63@example.com"));
+        assert!(!json.contains(&u.password));
+        assert!(!json.contains("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"));
+    }
+
+    #[test]
+    fn paginate_document() {
+        assert_eq!(paginate("abcdefg", 3), vec!["abc".to_owned(), "def".to_owned(),
+                                                "g".to_owned()]);
+        assert_eq!(paginate("ééé", 3), vec!["é".to_owned(), "é".to_owned(), "é".to_owned()]);
+        assert!(paginate("", 3).is_empty());
+    }
+}
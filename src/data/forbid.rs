@@ -0,0 +1,82 @@
+use super::mask_matches;
+use std::ascii::AsciiExt;
+use std::borrow::ToOwned;
+use std::fs::{File, create_dir_all};
+use std::io::{Error, ErrorKind, Result};
+use std::io::prelude::*;
+use std::path::Path;
+use rustc_serialize::json::{decode, encode};
+
+#[derive(RustcEncodable, RustcDecodable, Debug, PartialEq)]
+pub struct ForbidList {
+    pub name: String,
+    pub patterns: Vec<String>,
+}
+
+impl ForbidList {
+    pub fn new(name: &str) -> ForbidList {
+        ForbidList { name: name.to_owned(), patterns: Vec::new() }
+    }
+
+    pub fn add(&mut self, pattern: &str) -> bool {
+        if self.patterns.iter().any(|p| p.eq_ignore_ascii_case(pattern)) {
+            false
+        } else {
+            self.patterns.push(pattern.to_owned());
+            true
+        }
+    }
+
+    pub fn remove(&mut self, pattern: &str) -> bool {
+        let len = self.patterns.len();
+        self.patterns.retain(|p| !p.eq_ignore_ascii_case(pattern));
+        self.patterns.len() != len
+    }
+
+    pub fn matches(&self, string: &str) -> bool {
+        self.patterns.iter().any(|p| mask_matches(p, string))
+    }
+
+    pub fn is_forbidden(name: &str, string: &str) -> bool {
+        ForbidList::load(name).map(|l| l.matches(string)).unwrap_or(false)
+    }
+
+    pub fn load(name: &str) -> Result<ForbidList> {
+        let path = format!("data/operserv/forbid-{}.json", name);
+        if !Path::new(&path).exists() {
+            return Ok(ForbidList::new(name))
+        }
+        let mut file = try!(File::open(Path::new(&path)));
+        let mut data = String::new();
+        try!(file.read_to_string(&mut data));
+        decode(&data).map_err(|_| Error::new(
+            ErrorKind::InvalidInput, "Failed to decode forbid data."
+        ))
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let _ = create_dir_all(Path::new("data/operserv/"));
+        let path = format!("data/operserv/forbid-{}.json", self.name);
+        let mut f = try!(File::create(Path::new(&path)));
+        try!(f.write_all(try!(encode(self).map_err(|_| Error::new(
+            ErrorKind::InvalidInput, "Failed to encode forbid data."
+        ))).as_bytes()));
+        f.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ForbidList;
+
+    #[test]
+    fn patterns() {
+        let mut list = ForbidList::new("test");
+        assert!(list.add("*Serv"));
+        assert!(!list.add("*serv"));
+        assert!(list.matches("NickServ"));
+        assert!(!list.matches("server"));
+        assert!(list.remove("*SERV"));
+        assert!(!list.matches("NickServ"));
+    }
+}
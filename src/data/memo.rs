@@ -0,0 +1,110 @@
+use std::borrow::ToOwned;
+use std::fs::{File, create_dir_all};
+use std::io::{Error, ErrorKind, Result};
+use std::io::prelude::*;
+use std::path::Path;
+use rustc_serialize::json::{decode, encode};
+
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug, PartialEq)]
+pub struct Memo {
+    pub from: String,
+    pub text: String,
+    pub time: i64,
+    pub read: bool,
+}
+
+impl Memo {
+    pub fn new(from: &str, text: &str, time: i64) -> Memo {
+        Memo { from: from.to_owned(), text: text.to_owned(), time: time, read: false }
+    }
+}
+
+#[derive(RustcEncodable, RustcDecodable, Debug, PartialEq)]
+pub struct MemoBox {
+    pub owner: String,
+    pub memos: Vec<Memo>,
+}
+
+impl MemoBox {
+    pub fn new(owner: &str) -> MemoBox {
+        MemoBox { owner: owner.to_owned(), memos: Vec::new() }
+    }
+
+    /// Adds a memo, returning false if the box already holds `quota` memos.
+    pub fn deliver(&mut self, memo: Memo, quota: usize) -> bool {
+        if self.memos.len() >= quota {
+            false
+        } else {
+            self.memos.push(memo);
+            true
+        }
+    }
+
+    pub fn unread(&self) -> usize {
+        self.memos.iter().filter(|m| !m.read).count()
+    }
+
+    /// Looks up a memo by its one-based index as shown to users.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Memo> {
+        if index == 0 { None } else { self.memos.get_mut(index - 1) }
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Memo> {
+        if index == 0 || index > self.memos.len() {
+            None
+        } else {
+            Some(self.memos.remove(index - 1))
+        }
+    }
+
+    pub fn load(owner: &str) -> Result<MemoBox> {
+        let path = format!("data/memoserv/{}.json", owner);
+        if !Path::new(&path).exists() {
+            return Ok(MemoBox::new(owner))
+        }
+        let mut file = try!(File::open(Path::new(&path)));
+        let mut data = String::new();
+        try!(file.read_to_string(&mut data));
+        decode(&data).map_err(|_| Error::new(
+            ErrorKind::InvalidInput, "Failed to decode memo data."
+        ))
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let _ = create_dir_all(Path::new("data/memoserv/"));
+        let path = format!("data/memoserv/{}.json", self.owner);
+        let mut f = try!(File::create(Path::new(&path)));
+        try!(f.write_all(try!(encode(self).map_err(|_| Error::new(
+            ErrorKind::InvalidInput, "Failed to encode memo data."
+        ))).as_bytes()));
+        f.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Memo, MemoBox};
+
+    #[test]
+    fn deliver() {
+        let mut memos = MemoBox::new("test");
+        assert!(memos.deliver(Memo::new("test", "one", 0), 2));
+        assert!(memos.deliver(Memo::new("test", "two", 0), 2));
+        assert!(!memos.deliver(Memo::new("test", "three", 0), 2));
+        assert_eq!(memos.unread(), 2);
+        memos.get_mut(1).unwrap().read = true;
+        assert_eq!(memos.unread(), 1);
+        assert!(memos.get_mut(0).is_none());
+        assert_eq!(&memos.remove(2).unwrap().text[..], "two");
+        assert!(memos.remove(2).is_none());
+    }
+
+    #[test]
+    fn save_and_load() {
+        let mut memos = MemoBox::new("test");
+        memos.deliver(Memo::new("test2", "hello", 5), 20);
+        memos.save().unwrap();
+        assert_eq!(MemoBox::load("test").unwrap(), memos);
+        assert!(MemoBox::load("nobody-has-memos").unwrap().memos.is_empty());
+    }
+}
@@ -1,14 +1,31 @@
+use std::borrow::ToOwned;
 use std::io::Result as IoResult;
 use std::io::Write;
 use openssl::crypto::hash::{Type, Hasher};
+use regex::Regex;
+use rustc_serialize::{Decodable, Encodable};
 use rustc_serialize::hex::ToHex;
+use rustc_serialize::json::{self, Json};
+use time;
 
 pub mod channel;
 #[cfg(feature = "democracy")] pub mod democracy;
 #[cfg(feature = "derp")] pub mod derp;
+pub mod export;
+pub mod forbid;
+pub mod memo;
+pub mod mode;
 #[cfg(feature = "resistance")] pub mod resistance;
+pub mod policy;
+pub mod role;
+pub mod scheduler;
+pub mod settings;
 pub mod state;
+pub mod suspension;
+pub mod throttle;
+pub mod totp;
 pub mod user;
+pub mod vhost;
 
 pub type BotResult<T> = Result<T, String>;
 
@@ -18,6 +35,114 @@ pub fn password_hash(password: &str) -> IoResult<String> {
     Ok(hasher.finish().to_hex())
 }
 
+pub fn now() -> i64 {
+    time::get_time().sec
+}
+
+pub fn format_time(time: i64) -> String {
+    format!("{}", time::at_utc(time::Timespec::new(time, 0)).rfc3339())
+}
+
+/// Decodes a saved record, filling in any fields it lacks, such as ones added since it was
+/// written, from `defaults`.
+pub fn decode_with_defaults<T: Decodable + Encodable>(data: &str, defaults: &T) -> Option<T> {
+    let record = match Json::from_str(data) {
+        Ok(record) => record,
+        Err(_) => return None,
+    };
+    let defaults = match json::encode(defaults).ok().and_then(|d| Json::from_str(&d).ok()) {
+        Some(defaults) => defaults,
+        None => return None,
+    };
+    Decodable::decode(&mut json::Decoder::new(merge_defaults(record, defaults))).ok()
+}
+
+fn merge_defaults(record: Json, defaults: Json) -> Json {
+    match (record, defaults) {
+        (Json::Object(mut record), Json::Object(defaults)) => {
+            for (key, default) in defaults.into_iter() {
+                let value = match record.remove(&key) {
+                    Some(value) => merge_defaults(value, default),
+                    None => default,
+                };
+                record.insert(key, value);
+            }
+            Json::Object(record)
+        },
+        (record, _) => record,
+    }
+}
+
+/// Parses a duration such as `90`, `30m`, `12h` or `7d` into seconds. Zero means permanent.
+pub fn parse_duration(duration: &str) -> BotResult<Option<i64>> {
+    let (number, unit) = match duration.chars().last() {
+        Some(c) if c.is_alphabetic() => {
+            (&duration[..duration.len() - c.len_utf8()], c.to_lowercase().next())
+        },
+        _ => (duration, None),
+    };
+    let multiplier = match unit {
+        None | Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 60 * 60,
+        Some('d') => 24 * 60 * 60,
+        Some('w') => 7 * 24 * 60 * 60,
+        _ => return Err(format!("{} is not a valid duration.", duration)),
+    };
+    match number.parse::<i64>() {
+        Ok(0) => Ok(None),
+        Ok(n) if n > 0 => Ok(Some(n * multiplier)),
+        _ => Err(format!("{} is not a valid duration.", duration)),
+    }
+}
+
+pub fn mask_matches(mask: &str, string: &str) -> bool {
+    let mask: Vec<char> = mask.chars().flat_map(|c| c.to_lowercase()).collect();
+    let string: Vec<char> = string.chars().flat_map(|c| c.to_lowercase()).collect();
+    let (mut m, mut s) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while s < string.len() {
+        if m < mask.len() && (mask[m] == '?' || mask[m] == string[s]) {
+            m += 1;
+            s += 1;
+        } else if m < mask.len() && mask[m] == '*' {
+            backtrack = Some((m, s));
+            m += 1;
+        } else if let Some((bm, bs)) = backtrack {
+            backtrack = Some((bm, bs + 1));
+            m = bm + 1;
+            s = bs + 1;
+        } else {
+            return false
+        }
+    }
+    mask[m..].iter().all(|c| *c == '*')
+}
+
+/// A search pattern: either a `*`/`?` wildcard mask or a `/regex/`.
+pub enum Pattern {
+    Mask(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    pub fn parse(pattern: &str) -> BotResult<Pattern> {
+        if pattern.len() > 2 && pattern.starts_with("/") && pattern.ends_with("/") {
+            Regex::new(&format!("(?i){}", &pattern[1..pattern.len() - 1])).map(Pattern::Regex)
+                  .map_err(|_| format!("{} is not a valid regular expression.", pattern))
+        } else {
+            Ok(Pattern::Mask(pattern.to_owned()))
+        }
+    }
+
+    pub fn matches(&self, string: &str) -> bool {
+        match *self {
+            Pattern::Mask(ref mask) => mask_matches(mask, string),
+            Pattern::Regex(ref regex) => regex.is_match(string),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     #[test]
@@ -26,4 +151,35 @@ mod test {
         8980772e473f8819a5d4940e0db27ac185f8a0e1d5f84f88bc887fd67b143732c304cc5fa9ad8e6f57f50028a8\
         ff");
     }
+
+    #[test]
+    fn mask_matches() {
+        assert!(super::mask_matches("*@*.example.com", "user@host.example.com"));
+        assert!(super::mask_matches("us?r@HOST", "user@host"));
+        assert!(super::mask_matches("*", ""));
+        assert!(!super::mask_matches("*@*.example.com", "user@example.org"));
+        assert!(!super::mask_matches("user@host", "user@host2"));
+    }
+
+    #[test]
+    fn pattern() {
+        let mask = super::Pattern::parse("test*").unwrap();
+        assert!(mask.matches("TESTER"));
+        assert!(!mask.matches("atest"));
+        let regex = super::Pattern::parse("/^te.t[0-9]+$/").unwrap();
+        assert!(regex.matches("Test12"));
+        assert!(!regex.matches("test"));
+        assert!(super::Pattern::parse("/(/").is_err());
+    }
+
+    #[test]
+    fn parse_duration() {
+        assert_eq!(super::parse_duration("90"), Ok(Some(90)));
+        assert_eq!(super::parse_duration("30m"), Ok(Some(1800)));
+        assert_eq!(super::parse_duration("2D"), Ok(Some(172800)));
+        assert_eq!(super::parse_duration("0"), Ok(None));
+        assert!(super::parse_duration("-5m").is_err());
+        assert!(super::parse_duration("5y").is_err());
+        assert!(super::parse_duration("").is_err());
+    }
 }
@@ -0,0 +1,260 @@
+use std::borrow::ToOwned;
+use data::BotResult;
+
+/// Describes which channel modes take parameters, as advertised by ISUPPORT.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModeSpec {
+    pub list: String,
+    pub always: String,
+    pub on_set: String,
+    pub flags: String,
+    pub prefixes: String,
+    pub prefix_symbols: String,
+}
+
+impl ModeSpec {
+    pub fn new() -> ModeSpec {
+        ModeSpec {
+            list: "beI".to_owned(),
+            always: "k".to_owned(),
+            on_set: "l".to_owned(),
+            flags: "imnpst".to_owned(),
+            prefixes: "qaohv".to_owned(),
+            prefix_symbols: "~&@%+".to_owned(),
+        }
+    }
+
+    /// Updates the spec from the CHANMODES and PREFIX tokens of an RPL_ISUPPORT line.
+    pub fn update(&mut self, tokens: &[&str]) {
+        for token in tokens.iter() {
+            if token.starts_with("CHANMODES=") {
+                let classes: Vec<_> = token[10..].split(',').collect();
+                if classes.len() >= 4 {
+                    self.list = classes[0].to_owned();
+                    self.always = classes[1].to_owned();
+                    self.on_set = classes[2].to_owned();
+                    self.flags = classes[3].to_owned();
+                }
+            } else if token.starts_with("PREFIX=(") {
+                if let Some(end) = token.find(')') {
+                    self.prefixes = token[8..end].to_owned();
+                    self.prefix_symbols = token[end + 1..].to_owned();
+                }
+            }
+        }
+    }
+
+    pub fn takes_param(&self, mode: char, adding: bool) -> bool {
+        self.list.contains(mode) || self.always.contains(mode) || self.prefixes.contains(mode) ||
+        (adding && self.on_set.contains(mode))
+    }
+
+    /// Translates the symbols in front of a NAMES entry, such as `@+`, into prefix modes.
+    pub fn prefix_modes(&self, name: &str) -> (String, String) {
+        let split = name.find(|c: char| !self.prefix_symbols.contains(c)).unwrap_or(name.len());
+        let modes = name[..split].chars().filter_map(|c| {
+            self.prefix_symbols.find(c).and_then(|i| self.prefixes.chars().nth(i))
+        }).collect();
+        (modes, name[split..].to_owned())
+    }
+
+    /// Determines whether a mode describes channel state rather than a list or a member.
+    pub fn is_lockable(&self, mode: char) -> bool {
+        self.always.contains(mode) || self.on_set.contains(mode) || self.flags.contains(mode)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModeChange {
+    pub adding: bool,
+    pub mode: char,
+    pub param: Option<String>,
+}
+
+impl ModeChange {
+    pub fn new(adding: bool, mode: char, param: Option<&str>) -> ModeChange {
+        ModeChange { adding: adding, mode: mode, param: param.map(|s| s.to_owned()) }
+    }
+}
+
+/// Splits a mode string and its parameters into individual changes.
+pub fn parse(spec: &ModeSpec, modes: &str, params: &[&str]) -> Vec<ModeChange> {
+    let mut changes = Vec::new();
+    let mut adding = true;
+    let mut params = params.iter();
+    for mode in modes.chars() {
+        match mode {
+            '+' => adding = true,
+            '-' => adding = false,
+            _ => {
+                let param = if spec.takes_param(mode, adding) { params.next().map(|p| *p) }
+                            else { None };
+                changes.push(ModeChange::new(adding, mode, param));
+            }
+        }
+    }
+    changes
+}
+
+/// Builds a mode string and its parameter list from a series of changes.
+pub fn format(changes: &[ModeChange]) -> (String, Vec<String>) {
+    let mut modes = String::new();
+    let mut params = Vec::new();
+    let mut adding = None;
+    for change in changes.iter() {
+        if adding != Some(change.adding) {
+            modes.push(if change.adding { '+' } else { '-' });
+            adding = Some(change.adding);
+        }
+        modes.push(change.mode);
+        if let Some(ref param) = change.param {
+            params.push(param.clone());
+        }
+    }
+    (modes, params)
+}
+
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug, PartialEq)]
+pub struct ModeLock {
+    pub on: String,
+    pub off: String,
+    pub key: Option<String>,
+    pub limit: Option<u32>,
+}
+
+impl ModeLock {
+    pub fn from_changes(spec: &ModeSpec, changes: &[ModeChange]) -> BotResult<ModeLock> {
+        let mut lock = ModeLock { on: String::new(), off: String::new(), key: None, limit: None };
+        for change in changes.iter() {
+            if !spec.is_lockable(change.mode) {
+                return Err(format!("Mode {} cannot be locked.", change.mode))
+            }
+            lock.on = lock.on.chars().filter(|m| *m != change.mode).collect();
+            lock.off = lock.off.chars().filter(|m| *m != change.mode).collect();
+            if !change.adding {
+                lock.off.push(change.mode);
+                continue
+            }
+            match (change.mode, change.param.as_ref()) {
+                ('k', Some(key)) => lock.key = Some(key.clone()),
+                ('l', Some(limit)) => lock.limit = Some(try!(limit.parse().map_err(|_| {
+                    format!("{} is not a valid limit.", limit)
+                }))),
+                (_, None) if spec.takes_param(change.mode, true) => {
+                    return Err(format!("Mode {} requires a parameter.", change.mode))
+                },
+                (_, Some(_)) => return Err(format!("Mode {} cannot be locked.", change.mode)),
+                _ => (),
+            }
+            lock.on.push(change.mode);
+        }
+        Ok(lock)
+    }
+
+    fn locked_param(&self, mode: char) -> Option<String> {
+        match mode {
+            'k' => self.key.clone(),
+            'l' => self.limit.map(|l| l.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Lists the changes that put a channel into the locked state.
+    pub fn changes(&self, spec: &ModeSpec) -> Vec<ModeChange> {
+        let mut changes: Vec<_> = self.on.chars().map(|m| ModeChange {
+            adding: true, mode: m, param: self.locked_param(m)
+        }).collect();
+        changes.extend(self.off.chars().map(|m| ModeChange {
+            adding: false, mode: m, param: if spec.takes_param(m, false) {
+                Some("*".to_owned())
+            } else {
+                None
+            }
+        }));
+        changes
+    }
+
+    /// Computes the changes needed to undo anything in `changes` that violates the lock.
+    pub fn reverts(&self, spec: &ModeSpec, changes: &[ModeChange]) -> Vec<ModeChange> {
+        let mut reverts: Vec<ModeChange> = Vec::new();
+        for change in changes.iter() {
+            let revert = if change.adding && self.off.contains(change.mode) {
+                let param = if spec.takes_param(change.mode, false) {
+                    change.param.clone().or(Some("*".to_owned()))
+                } else {
+                    None
+                };
+                ModeChange { adding: false, mode: change.mode, param: param }
+            } else if self.on.contains(change.mode) &&
+                      (!change.adding || change.param != self.locked_param(change.mode)) {
+                ModeChange { adding: true, mode: change.mode, param: self.locked_param(change.mode) }
+            } else {
+                continue
+            };
+            reverts.retain(|r| r.mode != revert.mode);
+            reverts.push(revert);
+        }
+        reverts
+    }
+
+    pub fn describe(&self, spec: &ModeSpec) -> String {
+        let (modes, params) = format(&self.changes(spec));
+        let params: Vec<_> = params.into_iter().filter(|p| &p[..] != "*").collect();
+        if params.is_empty() { modes } else { format!("{} {}", modes, params.join(" ")) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ModeChange, ModeLock, ModeSpec, format, parse};
+    use std::borrow::ToOwned;
+
+    #[test]
+    fn update() {
+        let mut spec = ModeSpec::new();
+        spec.update(&["test", "CHANMODES=beIq,k,lf,cimnt", "PREFIX=(Yov)!@+", "are supported"]);
+        assert_eq!(&spec.list[..], "beIq");
+        assert_eq!(&spec.on_set[..], "lf");
+        assert_eq!(&spec.prefixes[..], "Yov");
+        assert_eq!(spec.prefix_modes("!@test"), ("Yo".to_owned(), "test".to_owned()));
+        spec.update(&["test", "PREFIX=(o)@+", "are supported"]);
+        assert_eq!(spec.prefix_modes("@+test"), ("o".to_owned(), "test".to_owned()));
+        assert!(spec.takes_param('f', true));
+        assert!(!spec.takes_param('f', false));
+    }
+
+    #[test]
+    fn parse_and_format() {
+        let spec = ModeSpec::new();
+        let changes = parse(&spec, "+ntk-l+bv", &["secret", "*!*@bad", "test"]);
+        assert_eq!(changes, vec![
+            ModeChange::new(true, 'n', None), ModeChange::new(true, 't', None),
+            ModeChange::new(true, 'k', Some("secret")), ModeChange::new(false, 'l', None),
+            ModeChange::new(true, 'b', Some("*!*@bad")), ModeChange::new(true, 'v', Some("test")),
+        ]);
+        let (modes, params) = format(&changes);
+        assert_eq!(&modes[..], "+ntk-l+bv");
+        assert_eq!(params, vec!["secret".to_owned(), "*!*@bad".to_owned(), "test".to_owned()]);
+    }
+
+    #[test]
+    fn lock_reverts() {
+        let spec = ModeSpec::new();
+        let lock = ModeLock::from_changes(&spec, &parse(&spec, "+ntl-s", &["10"])).unwrap();
+        assert_eq!(&lock.describe(&spec)[..], "+ntl-s 10");
+        let reverts = lock.reverts(&spec, &parse(&spec, "-t+sl+m", &["20"]));
+        assert_eq!(reverts, vec![
+            ModeChange::new(true, 't', None), ModeChange::new(false, 's', None),
+            ModeChange::new(true, 'l', Some("10")),
+        ]);
+        assert!(lock.reverts(&spec, &parse(&spec, "+nl", &["10"])).is_empty());
+    }
+
+    #[test]
+    fn lock_failed() {
+        let spec = ModeSpec::new();
+        assert!(ModeLock::from_changes(&spec, &parse(&spec, "+b", &["*!*@*"])).is_err());
+        assert!(ModeLock::from_changes(&spec, &parse(&spec, "+k", &[])).is_err());
+        assert!(ModeLock::from_changes(&spec, &parse(&spec, "+l", &["many"])).is_err());
+    }
+}
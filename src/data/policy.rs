@@ -0,0 +1,143 @@
+use std::ascii::AsciiExt;
+use std::borrow::ToOwned;
+use std::fs::File;
+use std::io::Result;
+use std::io::prelude::*;
+use std::path::Path;
+use data::{BotResult, mask_matches};
+
+const COMMON_PASSWORDS: &'static [&'static str] = &[
+    "123456", "1234567", "12345678", "123456789", "1234567890", "password", "password1",
+    "qwerty", "qwertyuiop", "abc123", "111111", "000000", "letmein", "welcome", "monkey",
+    "dragon", "iloveyou", "sunshine", "princess", "football", "baseball", "master", "shadow",
+    "trustno1", "passw0rd", "changeme", "admin", "secret",
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Policy {
+    pub min_length: usize,
+    pub min_classes: usize,
+    pub forbidden_nicks: Vec<String>,
+    pub require_email: bool,
+    pub common_passwords: Vec<String>,
+}
+
+impl Policy {
+    pub fn new(min_length: usize, min_classes: usize, forbidden_nicks: &str, require_email: bool)
+        -> Policy {
+        Policy {
+            min_length: min_length,
+            min_classes: min_classes,
+            forbidden_nicks: forbidden_nicks.split(',').map(|s| s.trim()).filter(|s| !s.is_empty())
+                                            .map(|s| s.to_owned()).collect(),
+            require_email: require_email,
+            common_passwords: COMMON_PASSWORDS.iter().map(|p| (*p).to_owned()).collect(),
+        }
+    }
+
+    /// Replaces the built-in list of common passwords with one read from a file, one password
+    /// per line. The built-in list is kept if the file can't be read.
+    pub fn load_common_passwords(&mut self, path: &str) -> Result<()> {
+        let mut file = try!(File::open(Path::new(path)));
+        let mut data = String::new();
+        try!(file.read_to_string(&mut data));
+        self.common_passwords = data.lines().map(|l| l.trim()).filter(|l| {
+            !l.is_empty() && !l.starts_with("#")
+        }).map(|l| l.to_owned()).collect();
+        Ok(())
+    }
+
+    pub fn check_registration(&self, nickname: &str, password: &str, email: Option<&str>)
+        -> BotResult<()> {
+        try!(self.check_nickname(nickname));
+        try!(self.check_password(nickname, password));
+        if self.require_email && email.is_none() {
+            return Err("An email address is required to register.".to_owned())
+        }
+        Ok(())
+    }
+
+    pub fn check_nickname(&self, nickname: &str) -> BotResult<()> {
+        if self.forbidden_nicks.iter().any(|mask| mask_matches(mask, nickname)) {
+            Err(format!("Nickname {} may not be registered.", nickname))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn check_password(&self, nickname: &str, password: &str) -> BotResult<()> {
+        if password.chars().count() < self.min_length {
+            Err(format!("Passwords must be at least {} characters long.", self.min_length))
+        } else if character_classes(password) < self.min_classes {
+            Err(format!("Passwords must use at least {} of: lowercase letters, uppercase \
+                         letters, digits, symbols.", self.min_classes))
+        } else if password.eq_ignore_ascii_case(nickname) {
+            Err("Your password may not be the same as your nickname.".to_owned())
+        } else if self.common_passwords.iter().any(|p| password.eq_ignore_ascii_case(p)) {
+            Err("That password is too common. Please choose another.".to_owned())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn character_classes(password: &str) -> usize {
+    let classes = [
+        password.chars().any(|c| c.is_lowercase()),
+        password.chars().any(|c| c.is_uppercase()),
+        password.chars().any(|c| c.is_numeric()),
+        password.chars().any(|c| !c.is_alphanumeric()),
+    ];
+    classes.iter().filter(|c| **c).count()
+}
+
+#[cfg(test)]
+mod test {
+    use super::Policy;
+    use std::borrow::ToOwned;
+    use std::fs::{File, create_dir_all};
+    use std::io::prelude::*;
+    use std::path::Path;
+
+    #[test]
+    fn check_password() {
+        let policy = Policy::new(8, 2, "", false);
+        assert!(policy.check_password("test", "Sh0rt").is_err());
+        assert!(policy.check_password("test", "lowercaseonly").is_err());
+        assert_eq!(policy.check_password("Testing123", "testing123"),
+                   Err("Your password may not be the same as your nickname.".to_owned()));
+        assert_eq!(policy.check_password("test", "Password1"),
+                   Err("That password is too common. Please choose another.".to_owned()));
+        assert!(policy.check_password("test", "correct horse").is_ok());
+    }
+
+    #[test]
+    fn load_common_passwords() {
+        let _ = create_dir_all(Path::new("data/"));
+        let mut f = File::create(Path::new("data/test-passwords.txt")).unwrap();
+        f.write_all(b"# Local additions\nhunter2\n\nCorrectHorse\n").unwrap();
+        let mut policy = Policy::new(1, 1, "", false);
+        assert!(policy.load_common_passwords("data/missing-passwords.txt").is_err());
+        assert!(policy.check_password("test", "password").is_err());
+        policy.load_common_passwords("data/test-passwords.txt").unwrap();
+        assert_eq!(policy.common_passwords, vec!["hunter2".to_owned(), "CorrectHorse".to_owned()]);
+        assert!(policy.check_password("test", "correcthorse").is_err());
+    }
+
+    #[test]
+    fn check_nickname() {
+        let policy = Policy::new(1, 1, "*Serv, Admin*", false);
+        assert_eq!(policy.forbidden_nicks, vec!["*Serv".to_owned(), "Admin*".to_owned()]);
+        assert!(policy.check_nickname("nickserv").is_err());
+        assert!(policy.check_nickname("administrator").is_err());
+        assert!(policy.check_nickname("test").is_ok());
+    }
+
+    #[test]
+    fn check_registration() {
+        let policy = Policy::new(1, 1, "", true);
+        assert_eq!(policy.check_registration("test", "hunter2", None),
+                   Err("An email address is required to register.".to_owned()));
+        assert!(policy.check_registration("test", "hunter2", Some("test@example.com")).is_ok());
+    }
+}
@@ -47,6 +47,10 @@ impl Resistance {
         &self.leader[..] == nick
     }
 
+    pub fn has_player(&self, nick: &str) -> bool {
+        self.players.iter().any(|p| &p[..] == nick)
+    }
+
     pub fn start<'a, T: IrcRead, U: IrcWrite>(&mut self, server: &'a ServerExt<'a, T, U>) 
         -> Result<()> {
         if self.started {
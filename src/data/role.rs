@@ -0,0 +1,130 @@
+use std::ascii::AsciiExt;
+use std::borrow::ToOwned;
+use std::fs::{File, create_dir_all, read_dir, remove_file};
+use std::io::{Error, ErrorKind, Result};
+use std::io::prelude::*;
+use std::path::Path;
+use rustc_serialize::json::{decode, encode};
+use data::BotResult;
+use data::channel::AccessLevel;
+
+/// A named template of channel access, shared by every channel that assigns it.
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug, PartialEq)]
+pub struct Role {
+    pub name: String,
+    pub owner: String,
+    pub level: AccessLevel,
+}
+
+/// Records that an account holds a role in a channel.
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug, PartialEq)]
+pub struct RoleAssignment {
+    pub account: String,
+    pub role: String,
+}
+
+impl RoleAssignment {
+    pub fn new(account: &str, role: &str) -> RoleAssignment {
+        RoleAssignment { account: account.to_owned(), role: role.to_ascii_lowercase() }
+    }
+}
+
+/// Checks that a role name only uses letters, digits, `_` and `-`, which also keeps it safe to
+/// use as a file name.
+pub fn is_valid_role_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| {
+        (c.is_ascii() && c.is_alphanumeric()) || c == '_' || c == '-'
+    })
+}
+
+impl Role {
+    pub fn new(name: &str, owner: &str, level: AccessLevel) -> Role {
+        Role { name: name.to_ascii_lowercase(), owner: owner.to_owned(), level: level }
+    }
+
+    /// Parses the access a role grants, which stops short of channel ownership.
+    pub fn parse_level(level: &str) -> BotResult<AccessLevel> {
+        match &level.to_ascii_lowercase()[..] {
+            "voice" => Ok(AccessLevel::Voice),
+            "oper" => Ok(AccessLevel::Oper),
+            "admin" => Ok(AccessLevel::Admin),
+            _ => Err(format!("{} is not a valid level. Use VOICE, OPER or ADMIN.", level)),
+        }
+    }
+
+    pub fn list() -> Result<Vec<String>> {
+        let mut ret = Vec::new();
+        if !Path::new("data/roles/").exists() {
+            return Ok(ret)
+        }
+        for entry in try!(read_dir(Path::new("data/roles/"))) {
+            let path = try!(entry).path();
+            if path.extension().map_or(false, |e| e == "json") {
+                if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+                    ret.push(name.to_owned());
+                }
+            }
+        }
+        ret.sort();
+        Ok(ret)
+    }
+
+    pub fn exists(name: &str) -> bool {
+        Path::new(&format!("data/roles/{}.json", name.to_ascii_lowercase())).exists()
+    }
+
+    pub fn load(name: &str) -> Result<Role> {
+        let path = format!("data/roles/{}.json", name.to_ascii_lowercase());
+        let mut file = try!(File::open(Path::new(&path)));
+        let mut data = String::new();
+        try!(file.read_to_string(&mut data));
+        decode(&data).map_err(|_| Error::new(
+            ErrorKind::InvalidInput, "Failed to decode role data."
+        ))
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let _ = create_dir_all(Path::new("data/roles/"));
+        let path = format!("data/roles/{}.json", self.name);
+        let mut f = try!(File::create(Path::new(&path)));
+        try!(f.write_all(try!(encode(self).map_err(|_| Error::new(
+            ErrorKind::InvalidInput, "Failed to encode role data."
+        ))).as_bytes()));
+        f.flush()
+    }
+
+    pub fn delete(name: &str) -> Result<()> {
+        remove_file(Path::new(&format!("data/roles/{}.json", name.to_ascii_lowercase())))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Role, is_valid_role_name};
+    use data::channel::AccessLevel;
+    use std::borrow::ToOwned;
+
+    #[test]
+    fn save_and_load() {
+        let role = Role::new("Tester1", "test", AccessLevel::Oper);
+        role.save().unwrap();
+        assert!(Role::exists("TESTER1"));
+        assert_eq!(Role::load("tester1").unwrap(), role);
+        assert!(Role::list().unwrap().contains(&"tester1".to_owned()));
+        Role::delete("tester1").unwrap();
+        assert!(!Role::exists("tester1"));
+    }
+
+    #[test]
+    fn valid_role_name() {
+        assert!(is_valid_role_name("tester_1-a"));
+        assert!(!is_valid_role_name("../tester"));
+        assert!(!is_valid_role_name(""));
+    }
+
+    #[test]
+    fn parse_level() {
+        assert_eq!(Role::parse_level("OPER"), Ok(AccessLevel::Oper));
+        assert!(Role::parse_level("owner").is_err());
+    }
+}
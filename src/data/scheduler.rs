@@ -0,0 +1,158 @@
+use std::fs::{File, create_dir_all};
+use std::io::{Error, ErrorKind, Result};
+use std::io::prelude::*;
+use std::path::Path;
+use rustc_serialize::json::{decode, encode};
+
+/// A source of the current time, so that tests can decide when jobs become due.
+pub trait Clock: Send {
+    fn now(&self) -> i64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        super::now()
+    }
+}
+
+#[cfg(test)]
+pub struct FixedClock(pub i64);
+
+#[cfg(test)]
+impl Clock for FixedClock {
+    fn now(&self) -> i64 {
+        self.0
+    }
+}
+
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug, PartialEq)]
+pub enum Task {
+    /// Lifts the ban on a mask in a channel.
+    Unban(String, String),
+    /// Removes an account from a channel's voice list.
+    RemoveVoice(String, String),
+    /// Drops a democracy proposal that is still open.
+    ExpireProposal(String, u8),
+    /// Removes someone from a protected nick they haven't identified for.
+    Enforce(String),
+    /// Removes someone without access from a RESTRICTED channel once their grace period ends.
+    Restrict(String, String),
+}
+
+impl Task {
+    /// Proposals only live in memory and their ids restart with the bot, so their expiry
+    /// would hit an unrelated proposal if it survived a restart.
+    pub fn is_persistent(&self) -> bool {
+        match *self {
+            Task::ExpireProposal(..) => false,
+            _ => true,
+        }
+    }
+}
+
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug, PartialEq)]
+pub struct Job {
+    pub id: u32,
+    pub due: i64,
+    pub task: Task,
+}
+
+#[derive(RustcEncodable, RustcDecodable, Debug, PartialEq)]
+pub struct Schedule {
+    pub next_id: u32,
+    pub jobs: Vec<Job>,
+}
+
+impl Schedule {
+    pub fn new() -> Schedule {
+        Schedule { next_id: 1, jobs: Vec::new() }
+    }
+
+    pub fn add(&mut self, due: i64, task: Task) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.jobs.push(Job { id: id, due: due, task: task });
+        id
+    }
+
+    /// Removes every pending job for the given task, returning true if there were any.
+    pub fn cancel(&mut self, task: &Task) -> bool {
+        let before = self.jobs.len();
+        self.jobs.retain(|j| &j.task != task);
+        self.jobs.len() != before
+    }
+
+    /// Removes and returns the jobs due at or before `now`, earliest first.
+    pub fn take_due(&mut self, now: i64) -> Vec<Job> {
+        let (mut due, pending): (Vec<Job>, Vec<Job>) = self.jobs.iter().cloned().partition(|j| {
+            j.due <= now
+        });
+        self.jobs = pending;
+        due.sort_by(|a, b| (a.due, a.id).cmp(&(b.due, b.id)));
+        due
+    }
+
+    pub fn load() -> Result<Schedule> {
+        let path = Path::new("data/scheduler/jobs.json");
+        if !path.exists() {
+            return Ok(Schedule::new())
+        }
+        let mut file = try!(File::open(path));
+        let mut data = String::new();
+        try!(file.read_to_string(&mut data));
+        let mut schedule: Schedule = try!(decode(&data).map_err(|_| Error::new(
+            ErrorKind::InvalidInput, "Failed to decode scheduled jobs."
+        )));
+        schedule.jobs.retain(|j| j.task.is_persistent());
+        Ok(schedule)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let _ = create_dir_all(Path::new("data/scheduler/"));
+        let persisted = Schedule {
+            next_id: self.next_id,
+            jobs: self.jobs.iter().filter(|j| j.task.is_persistent()).cloned().collect(),
+        };
+        let mut f = try!(File::create(Path::new("data/scheduler/jobs.json")));
+        try!(f.write_all(try!(encode(&persisted).map_err(|_| Error::new(
+            ErrorKind::InvalidInput, "Failed to encode scheduled jobs."
+        ))).as_bytes()));
+        f.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Schedule, Task};
+    use std::borrow::ToOwned;
+
+    #[test]
+    fn take_due() {
+        let mut schedule = Schedule::new();
+        schedule.add(30, Task::Unban("#test".to_owned(), "*!*@a".to_owned()));
+        schedule.add(10, Task::RemoveVoice("#test".to_owned(), "test".to_owned()));
+        schedule.add(50, Task::ExpireProposal("#test".to_owned(), 0));
+        assert!(schedule.take_due(5).is_empty());
+        let due = schedule.take_due(30);
+        assert_eq!(due.iter().map(|j| j.id).collect::<Vec<_>>(), vec![2, 1]);
+        assert_eq!(schedule.jobs.len(), 1);
+    }
+
+    #[test]
+    fn is_persistent() {
+        assert!(Task::Unban("#test".to_owned(), "*!*@a".to_owned()).is_persistent());
+        assert!(!Task::ExpireProposal("#test".to_owned(), 0).is_persistent());
+    }
+
+    #[test]
+    fn cancel() {
+        let mut schedule = Schedule::new();
+        let task = Task::Unban("#test".to_owned(), "*!*@a".to_owned());
+        schedule.add(30, task.clone());
+        assert!(schedule.cancel(&task));
+        assert!(!schedule.cancel(&task));
+        assert!(schedule.jobs.is_empty());
+    }
+}
@@ -0,0 +1,296 @@
+use std::ascii::AsciiExt;
+use std::borrow::ToOwned;
+use std::collections::BTreeMap;
+use data::BotResult;
+
+pub const LANGUAGES: &'static [&'static str] = &["en"];
+
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug, PartialEq)]
+pub struct Settings {
+    pub hide_email: bool,
+    pub enforce: bool,
+    pub enforce_timeout: u32,
+    pub use_privmsg: bool,
+    pub language: String,
+    pub properties: BTreeMap<String, String>,
+    pub private: bool,
+    pub greet: Option<String>,
+}
+
+impl Settings {
+    pub fn new() -> Settings {
+        Settings {
+            hide_email: false,
+            enforce: false,
+            enforce_timeout: 60,
+            use_privmsg: false,
+            language: "en".to_owned(),
+            properties: BTreeMap::new(),
+            private: false,
+            greet: None,
+        }
+    }
+
+    pub fn apply(&mut self, setting: Setting) {
+        match setting {
+            Setting::HideEmail(value) => self.hide_email = value,
+            Setting::Enforce(value, timeout) => {
+                self.enforce = value;
+                if let Some(timeout) = timeout {
+                    self.enforce_timeout = timeout;
+                }
+            },
+            Setting::Msg(value) => self.use_privmsg = value,
+            Setting::Language(language) => self.language = language,
+            Setting::Property(key, Some(value)) => { self.properties.insert(key, value); },
+            Setting::Property(key, None) => { self.properties.remove(&key); },
+            Setting::Private(value) => self.private = value,
+            Setting::Greet(greet) => self.greet = greet,
+        }
+    }
+
+    pub fn display(&self) -> Vec<String> {
+        let mut ret = vec![
+            format!("HIDEMAIL {}", on_off(self.hide_email)),
+            format!("ENFORCE {} ({} seconds)", on_off(self.enforce), self.enforce_timeout),
+            format!("MSG {}", on_off(self.use_privmsg)),
+            format!("LANGUAGE {}", self.language),
+            format!("PRIVATE {}", on_off(self.private)),
+        ];
+        if let Some(ref greet) = self.greet {
+            ret.push(format!("GREET {}", greet));
+        }
+        for (key, value) in self.properties.iter() {
+            ret.push(format!("PROPERTY {} {}", key, value));
+        }
+        ret
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Setting {
+    HideEmail(bool),
+    Enforce(bool, Option<u32>),
+    Msg(bool),
+    Language(String),
+    Property(String, Option<String>),
+    Private(bool),
+    Greet(Option<String>),
+}
+
+impl Setting {
+    pub fn parse(args: &[&str]) -> BotResult<Setting> {
+        if args.len() < 2 {
+            return Err("Syntax: NS SET option value".to_owned())
+        }
+        let option: String = args[0].chars().map(|c| c.to_ascii_uppercase()).collect();
+        match (&option[..], args.len()) {
+            ("HIDEMAIL", 2) => parse_bool(args[1]).map(Setting::HideEmail),
+            ("ENFORCE", 2) => parse_bool(args[1]).map(|v| Setting::Enforce(v, None)),
+            ("ENFORCE", 3) => {
+                let timeout = try!(args[2].parse().map_err(|_| {
+                    format!("{} is not a valid timeout in seconds.", args[2])
+                }));
+                parse_bool(args[1]).map(|v| Setting::Enforce(v, Some(timeout)))
+            },
+            ("MSG", 2) => parse_bool(args[1]).map(Setting::Msg),
+            ("LANGUAGE", 2) if LANGUAGES.contains(&args[1]) => {
+                Ok(Setting::Language(args[1].to_owned()))
+            },
+            ("LANGUAGE", 2) => {
+                Err(format!("{} is not a supported language. Languages: {}", args[1],
+                            LANGUAGES.join(", ")))
+            },
+            ("PROPERTY", 2) => Ok(Setting::Property(args[1].to_owned(), None)),
+            ("PROPERTY", _) => {
+                Ok(Setting::Property(args[1].to_owned(), Some(args[2..].join(" "))))
+            },
+            ("PRIVATE", 2) => parse_bool(args[1]).map(Setting::Private),
+            ("GREET", 2) if args[1].eq_ignore_ascii_case("OFF") => Ok(Setting::Greet(None)),
+            ("GREET", _) => Ok(Setting::Greet(Some(args[1..].join(" ")))),
+            _ => Err(format!("Syntax: NS SET {} value", option)),
+        }
+    }
+}
+
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug, PartialEq)]
+pub struct ChannelSettings {
+    pub keeptopic: bool,
+    pub topiclock: bool,
+    pub secureops: bool,
+    pub restricted: Restriction,
+    pub entrymsg: Option<String>,
+    pub greet: bool,
+    pub fantasy: Option<String>,
+}
+
+impl ChannelSettings {
+    pub fn new() -> ChannelSettings {
+        ChannelSettings {
+            keeptopic: true, topiclock: false, secureops: false, restricted: Restriction::Off,
+            entrymsg: None, greet: false, fantasy: None,
+        }
+    }
+
+    pub fn apply(&mut self, setting: ChannelSetting) {
+        match setting {
+            ChannelSetting::KeepTopic(value) => self.keeptopic = value,
+            ChannelSetting::TopicLock(value) => self.topiclock = value,
+            ChannelSetting::SecureOps(value) => self.secureops = value,
+            ChannelSetting::Restricted(value) => self.restricted = value,
+            ChannelSetting::EntryMsg(message) => self.entrymsg = message,
+            ChannelSetting::Greet(value) => self.greet = value,
+            ChannelSetting::Fantasy(prefix) => self.fantasy = prefix,
+        }
+    }
+
+    pub fn display(&self) -> Vec<String> {
+        let mut ret = vec![
+            format!("KEEPTOPIC {}", on_off(self.keeptopic)),
+            format!("TOPICLOCK {}", on_off(self.topiclock)),
+            format!("SECUREOPS {}", on_off(self.secureops)),
+            format!("RESTRICTED {}", self.restricted.name()),
+            format!("GREET {}", on_off(self.greet)),
+            format!("FANTASY {}", self.fantasy.as_ref().map_or("OFF", |p| &p[..])),
+        ];
+        if let Some(ref message) = self.entrymsg {
+            ret.push(format!("ENTRYMSG {}", message));
+        }
+        ret
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChannelSetting {
+    KeepTopic(bool),
+    TopicLock(bool),
+    SecureOps(bool),
+    Restricted(Restriction),
+    EntryMsg(Option<String>),
+    Greet(bool),
+    Fantasy(Option<String>),
+}
+
+impl ChannelSetting {
+    pub fn parse(args: &[&str]) -> BotResult<ChannelSetting> {
+        if args.len() < 2 {
+            return Err("Syntax: CS SET channel option value".to_owned())
+        }
+        let option: String = args[0].chars().map(|c| c.to_ascii_uppercase()).collect();
+        match (&option[..], args.len()) {
+            ("KEEPTOPIC", 2) => parse_bool(args[1]).map(ChannelSetting::KeepTopic),
+            ("TOPICLOCK", 2) => parse_bool(args[1]).map(ChannelSetting::TopicLock),
+            ("SECUREOPS", 2) => parse_bool(args[1]).map(ChannelSetting::SecureOps),
+            ("RESTRICTED", 2) => Restriction::parse(args[1]).map(ChannelSetting::Restricted),
+            ("ENTRYMSG", 2) if args[1].eq_ignore_ascii_case("OFF") => {
+                Ok(ChannelSetting::EntryMsg(None))
+            },
+            ("ENTRYMSG", _) => Ok(ChannelSetting::EntryMsg(Some(args[1..].join(" ")))),
+            ("GREET", 2) => parse_bool(args[1]).map(ChannelSetting::Greet),
+            ("FANTASY", 2) if args[1].eq_ignore_ascii_case("OFF") => {
+                Ok(ChannelSetting::Fantasy(None))
+            },
+            ("FANTASY", 2) if args[1].chars().count() == 1 && !args[1].chars().any(|c| {
+                c.is_alphanumeric()
+            }) => Ok(ChannelSetting::Fantasy(Some(args[1].to_owned()))),
+            ("FANTASY", 2) => {
+                Err(format!("{} is not a valid prefix. Use a single symbol or OFF.", args[1]))
+            },
+            _ => Err(format!("Syntax: CS SET channel {} value", option)),
+        }
+    }
+}
+
+/// What happens to users without access who join a RESTRICTED channel.
+#[derive(RustcEncodable, RustcDecodable, Clone, Copy, Debug, PartialEq)]
+pub enum Restriction {
+    Off,
+    Kick,
+    Ban,
+}
+
+impl Restriction {
+    pub fn parse(value: &str) -> BotResult<Restriction> {
+        let value: String = value.chars().map(|c| c.to_ascii_uppercase()).collect();
+        match &value[..] {
+            "OFF" => Ok(Restriction::Off),
+            "ON" | "KICK" => Ok(Restriction::Kick),
+            "BAN" => Ok(Restriction::Ban),
+            _ => Err(format!("{} is not a valid value. Use OFF, KICK or BAN.", value)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Restriction::Off => "OFF",
+            Restriction::Kick => "KICK",
+            Restriction::Ban => "BAN",
+        }
+    }
+}
+
+fn parse_bool(value: &str) -> BotResult<bool> {
+    if value.eq_ignore_ascii_case("ON") {
+        Ok(true)
+    } else if value.eq_ignore_ascii_case("OFF") {
+        Ok(false)
+    } else {
+        Err(format!("{} is not a valid value. Use ON or OFF.", value))
+    }
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value { "ON" } else { "OFF" }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ChannelSetting, ChannelSettings, Restriction, Setting, Settings};
+    use std::borrow::ToOwned;
+
+    #[test]
+    fn parse() {
+        assert_eq!(Setting::parse(&["hidemail", "on"]), Ok(Setting::HideEmail(true)));
+        assert_eq!(Setting::parse(&["ENFORCE", "ON", "30"]), Ok(Setting::Enforce(true, Some(30))));
+        assert_eq!(Setting::parse(&["LANGUAGE", "en"]), Ok(Setting::Language("en".to_owned())));
+        assert_eq!(Setting::parse(&["GREET", "Hello", "all"]),
+                   Ok(Setting::Greet(Some("Hello all".to_owned()))));
+        assert_eq!(Setting::parse(&["GREET", "off"]), Ok(Setting::Greet(None)));
+        assert_eq!(Setting::parse(&["PROPERTY", "url", "http://example.com"]),
+                   Ok(Setting::Property("url".to_owned(), Some("http://example.com".to_owned()))));
+        assert!(Setting::parse(&["LANGUAGE", "xx"]).is_err());
+        assert!(Setting::parse(&["PRIVATE", "maybe"]).is_err());
+        assert!(Setting::parse(&["COLOUR", "blue"]).is_err());
+    }
+
+    #[test]
+    fn apply() {
+        let mut settings = Settings::new();
+        settings.apply(Setting::Enforce(true, Some(30)));
+        settings.apply(Setting::Property("url".to_owned(), Some("http://example.com".to_owned())));
+        assert!(settings.enforce);
+        assert_eq!(settings.enforce_timeout, 30);
+        assert_eq!(settings.display().last().unwrap(), "PROPERTY url http://example.com");
+        settings.apply(Setting::Property("url".to_owned(), None));
+        assert!(settings.properties.is_empty());
+    }
+
+    #[test]
+    fn channel_settings() {
+        let mut settings = ChannelSettings::new();
+        settings.apply(ChannelSetting::parse(&["topiclock", "on"]).unwrap());
+        settings.apply(ChannelSetting::parse(&["RESTRICTED", "ban"]).unwrap());
+        assert!(settings.topiclock);
+        assert_eq!(settings.restricted, Restriction::Ban);
+        settings.apply(ChannelSetting::parse(&["ENTRYMSG", "Be", "nice!"]).unwrap());
+        assert_eq!(settings.display().join(", "), "KEEPTOPIC ON, TOPICLOCK ON, SECUREOPS OFF, \
+                                                   RESTRICTED BAN, GREET OFF, FANTASY OFF, \
+                                                   ENTRYMSG Be nice!");
+        assert_eq!(ChannelSetting::parse(&["FANTASY", "."]),
+                   Ok(ChannelSetting::Fantasy(Some(".".to_owned()))));
+        assert!(ChannelSetting::parse(&["FANTASY", "cs"]).is_err());
+        assert!(ChannelSetting::parse(&["RESTRICTED", "maybe"]).is_err());
+        assert!(ChannelSetting::parse(&["KEEPTOPIC"]).is_err());
+        assert!(ChannelSetting::parse(&["FANCY", "ON"]).is_err());
+    }
+}
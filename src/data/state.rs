@@ -1,13 +1,29 @@
+use std::ascii::AsciiExt;
 use std::borrow::ToOwned;
-#[cfg(any(feature = "democracy", feature = "resistance"))] use std::collections::HashMap;
+use std::collections::HashMap;
+use std::io::Result;
 use std::sync::Mutex;
 #[cfg(any(feature = "democracy", feature = "resistance"))] use std::sync::MutexGuard;
-#[cfg(feature = "democracy")] use data::channel::Channel;
 #[cfg(feature = "democracy")] use data::democracy::Democracy;
 #[cfg(feature = "resistance")] use data::resistance::Resistance;
+use data::channel::Channel;
+use data::mode::ModeSpec;
+use data::scheduler::{Clock, Job, Schedule, SystemClock, Task};
+use data::throttle::{Throttle, ThrottleConfig};
 
 pub struct State {
-    identified: Mutex<Vec<String>>,
+    identified: Mutex<HashMap<String, String>>,
+    certfps: Mutex<HashMap<String, String>>,
+    hostmasks: Mutex<HashMap<String, String>>,
+    recognized: Mutex<Vec<String>>,
+    throttles: Mutex<HashMap<String, Throttle>>,
+    guests: Mutex<u32>,
+    modes: Mutex<ModeSpec>,
+    clock: Mutex<Box<Clock>>,
+    schedule: Mutex<Schedule>,
+    chanops: Mutex<HashMap<String, HashMap<String, String>>>,
+    whois_requests: Mutex<HashMap<String, i64>>,
+    registrations: Mutex<HashMap<String, Channel>>,
     #[cfg(feature = "resistance")]
     resistance: Mutex<HashMap<String, Resistance>>,
     #[cfg(feature = "democracy")]
@@ -18,44 +34,310 @@ impl State {
     #[cfg(not(feature = "resistance"))]
     #[cfg(not(feature = "democracy"))]
     pub fn new() -> State {
-        State { identified: Mutex::new(Vec::new()) }
+        State {
+            identified: Mutex::new(HashMap::new()),
+            certfps: Mutex::new(HashMap::new()),
+            hostmasks: Mutex::new(HashMap::new()),
+            recognized: Mutex::new(Vec::new()),
+            throttles: Mutex::new(HashMap::new()),
+            guests: Mutex::new(0),
+            modes: Mutex::new(ModeSpec::new()),
+            clock: Mutex::new(Box::new(SystemClock)),
+            schedule: Mutex::new(Schedule::new()),
+            chanops: Mutex::new(HashMap::new()),
+            whois_requests: Mutex::new(HashMap::new()),
+            registrations: Mutex::new(HashMap::new()),
+        }
     }
 
     #[cfg(not(feature = "democracy"))]
     #[cfg(feature = "resistance")]
     pub fn new() -> State {
-        State { identified: Mutex::new(Vec::new()), resistance: Mutex::new(HashMap::new()) }
+        State {
+            identified: Mutex::new(HashMap::new()),
+            certfps: Mutex::new(HashMap::new()),
+            hostmasks: Mutex::new(HashMap::new()),
+            recognized: Mutex::new(Vec::new()),
+            throttles: Mutex::new(HashMap::new()),
+            guests: Mutex::new(0),
+            modes: Mutex::new(ModeSpec::new()),
+            clock: Mutex::new(Box::new(SystemClock)),
+            schedule: Mutex::new(Schedule::new()),
+            chanops: Mutex::new(HashMap::new()),
+            whois_requests: Mutex::new(HashMap::new()),
+            registrations: Mutex::new(HashMap::new()),
+            resistance: Mutex::new(HashMap::new())
+        }
     }
 
     #[cfg(not(feature = "resistance"))]
     #[cfg(feature = "democracy")]
     pub fn new() -> State {
-        State { identified: Mutex::new(Vec::new()), democracy: Mutex::new(HashMap::new()) }
+        State {
+            identified: Mutex::new(HashMap::new()),
+            certfps: Mutex::new(HashMap::new()),
+            hostmasks: Mutex::new(HashMap::new()),
+            recognized: Mutex::new(Vec::new()),
+            throttles: Mutex::new(HashMap::new()),
+            guests: Mutex::new(0),
+            modes: Mutex::new(ModeSpec::new()),
+            clock: Mutex::new(Box::new(SystemClock)),
+            schedule: Mutex::new(Schedule::new()),
+            chanops: Mutex::new(HashMap::new()),
+            whois_requests: Mutex::new(HashMap::new()),
+            registrations: Mutex::new(HashMap::new()),
+            democracy: Mutex::new(HashMap::new())
+        }
     }
 
     #[cfg(feature = "resistance")]
     #[cfg(feature = "democracy")]
     pub fn new() -> State {
-        State { 
-            identified: Mutex::new(Vec::new()),
+        State {
+            identified: Mutex::new(HashMap::new()),
+            certfps: Mutex::new(HashMap::new()),
+            hostmasks: Mutex::new(HashMap::new()),
+            recognized: Mutex::new(Vec::new()),
+            throttles: Mutex::new(HashMap::new()),
+            guests: Mutex::new(0),
+            modes: Mutex::new(ModeSpec::new()),
+            clock: Mutex::new(Box::new(SystemClock)),
+            schedule: Mutex::new(Schedule::new()),
+            chanops: Mutex::new(HashMap::new()),
+            whois_requests: Mutex::new(HashMap::new()),
+            registrations: Mutex::new(HashMap::new()),
             resistance: Mutex::new(HashMap::new()),
             democracy:  Mutex::new(HashMap::new())
         }
     }
 
     pub fn identify(&self, nick: &str) {
-        self.identified.lock().unwrap().push(nick.to_owned())
+        self.identified.lock().unwrap().insert(nick.to_owned(), nick.to_owned());
     }
 
+    /// Checks whether the nick is identified to the account of the same name.
     pub fn is_identified(&self, nick: &str) -> bool {
-        self.identified.lock().unwrap().contains(&nick.to_owned())
+        self.identified.lock().unwrap().get(nick).map_or(false, |a| &a[..] == nick)
+    }
+
+    pub fn get_account(&self, nick: &str) -> Option<String> {
+        self.identified.lock().unwrap().get(nick).cloned()
+    }
+
+    pub fn get_sessions(&self, account: &str) -> Vec<String> {
+        let mut sessions: Vec<_> = self.identified.lock().unwrap().iter()
+                                       .filter(|&(_, a)| &a[..] == account)
+                                       .map(|(n, _)| n.clone()).collect();
+        sessions.sort();
+        sessions
+    }
+
+    pub fn rename_account(&self, old: &str, new: &str) {
+        for account in self.identified.lock().unwrap().values_mut() {
+            if &account[..] == old {
+                *account = new.to_owned();
+            }
+        }
+    }
+
+    pub fn logout(&self, nick: &str) -> Option<String> {
+        self.identified.lock().unwrap().remove(nick)
     }
 
     pub fn remove(&self, nick: &str) {
+        self.identified.lock().unwrap().remove(nick);
+        self.certfps.lock().unwrap().remove(nick);
+        self.hostmasks.lock().unwrap().remove(nick);
+        self.whois_requests.lock().unwrap().remove(nick);
+        self.recognized.lock().unwrap().retain(|n| &n[..] != nick);
+        for ops in self.chanops.lock().unwrap().values_mut() {
+            ops.remove(nick);
+        }
+    }
+
+    pub fn change_nick(&self, old: &str, new: &str) {
         let mut identified = self.identified.lock().unwrap();
-        if let Some(i) = identified.iter().position(|n| &n[..] == nick) {
-            identified.swap_remove(i);
+        if let Some(account) = identified.remove(old) {
+            identified.insert(new.to_owned(), account);
+        }
+        self.recognized.lock().unwrap().retain(|n| &n[..] != old);
+        let mut certfps = self.certfps.lock().unwrap();
+        if let Some(fp) = certfps.remove(old) {
+            certfps.insert(new.to_owned(), fp);
+        }
+        let mut hostmasks = self.hostmasks.lock().unwrap();
+        if let Some(host) = hostmasks.remove(old) {
+            hostmasks.insert(new.to_owned(), host);
+        }
+        for ops in self.chanops.lock().unwrap().values_mut() {
+            if let Some(modes) = ops.remove(old) {
+                ops.insert(new.to_owned(), modes);
+            }
+        }
+    }
+
+    /// Records the operator-level prefix modes (q, a and o) a nick holds in a channel.
+    pub fn set_prefix_modes(&self, chan: &str, nick: &str, modes: &str) {
+        let held: String = modes.chars().filter(|m| "qao".contains(*m)).collect();
+        let mut chanops = self.chanops.lock().unwrap();
+        let ops = chanops.entry(chan.to_owned()).or_insert(HashMap::new());
+        if held.is_empty() {
+            ops.remove(nick);
+        } else {
+            ops.insert(nick.to_owned(), held);
+        }
+    }
+
+    /// Applies a single prefix mode change, keeping any other operator-level modes held.
+    pub fn update_prefix_mode(&self, chan: &str, nick: &str, mode: char, adding: bool) {
+        let current = self.chanops.lock().unwrap().get(chan).and_then(|ops| ops.get(nick))
+            .cloned().unwrap_or(String::new());
+        let mut held: String = current.chars().filter(|m| *m != mode).collect();
+        if adding {
+            held.push(mode);
         }
+        self.set_prefix_modes(chan, nick, &held);
+    }
+
+    pub fn is_chanop(&self, chan: &str, nick: &str) -> bool {
+        self.chanops.lock().unwrap().get(chan).map_or(false, |ops| ops.contains_key(nick))
+    }
+
+    /// Drops the prefix modes recorded for a channel, which are only trusted right after the
+    /// NAMES list they came from.
+    pub fn forget_chanops(&self, chan: &str) {
+        self.chanops.lock().unwrap().remove(chan);
+    }
+
+    /// Holds a channel registration until the NAMES list confirms the owner is an operator.
+    pub fn add_pending_registration(&self, channel: Channel) {
+        let key = channel.name.to_ascii_lowercase();
+        self.forget_chanops(&channel.name);
+        self.registrations.lock().unwrap().insert(key, channel);
+    }
+
+    pub fn take_pending_registration(&self, chan: &str) -> Option<Channel> {
+        self.registrations.lock().unwrap().remove(&chan.to_ascii_lowercase())
+    }
+
+    pub fn part_channel(&self, chan: &str, nick: &str) {
+        if let Some(ops) = self.chanops.lock().unwrap().get_mut(chan) {
+            ops.remove(nick);
+        }
+    }
+
+    pub fn recognize(&self, nick: &str) {
+        self.recognized.lock().unwrap().push(nick.to_owned())
+    }
+
+    pub fn is_recognized(&self, nick: &str) -> bool {
+        self.recognized.lock().unwrap().contains(&nick.to_owned())
+    }
+
+    pub fn update_hostmask(&self, prefix: &str) {
+        if let Some(i) = prefix.find('!') {
+            self.hostmasks.lock().unwrap().insert(prefix[..i].to_owned(), prefix[i+1..].to_owned());
+        }
+    }
+
+    pub fn get_hostmask(&self, nick: &str) -> Option<String> {
+        self.hostmasks.lock().unwrap().get(nick).cloned()
+    }
+
+    pub fn set_certfp(&self, nick: &str, fingerprint: &str) {
+        self.certfps.lock().unwrap().insert(nick.to_owned(), fingerprint.to_owned());
+    }
+
+    pub fn get_certfp(&self, nick: &str) -> Option<String> {
+        self.certfps.lock().unwrap().get(nick).cloned()
+    }
+
+    pub fn throttle_wait(&self, key: &str, now: i64, config: &ThrottleConfig) -> Option<i64> {
+        self.throttles.lock().unwrap().get(key).and_then(|t| t.wait_time(now, config))
+    }
+
+    pub fn throttle_fail(&self, key: &str, now: i64, config: &ThrottleConfig) -> bool {
+        let mut throttles = self.throttles.lock().unwrap();
+        let stale: Vec<_> = throttles.iter().filter(|&(_, t)| t.is_stale(now, config))
+                                     .map(|(k, _)| k.clone()).collect();
+        for key in stale.iter() {
+            throttles.remove(key);
+        }
+        throttles.entry(key.to_owned()).or_insert(Throttle::new()).fail(now, config)
+    }
+
+    pub fn throttle_clear(&self, key: &str) {
+        self.throttles.lock().unwrap().remove(key);
+    }
+
+    pub fn next_guest_nick(&self, prefix: &str) -> String {
+        let mut guests = self.guests.lock().unwrap();
+        *guests += 1;
+        format!("{}{}", prefix, *guests)
+    }
+
+    pub fn get_mode_spec(&self) -> ModeSpec {
+        self.modes.lock().unwrap().clone()
+    }
+
+    pub fn update_mode_spec(&self, tokens: &[&str]) {
+        self.modes.lock().unwrap().update(tokens)
+    }
+
+    pub fn now(&self) -> i64 {
+        self.clock.lock().unwrap().now()
+    }
+
+    /// Records a WHOIS for a nick, returning false if one was already sent within `interval`
+    /// seconds.
+    pub fn request_whois(&self, nick: &str, interval: i64) -> bool {
+        let now = self.now();
+        let mut requests = self.whois_requests.lock().unwrap();
+        match requests.get(nick) {
+            Some(&sent) if now - sent < interval => return false,
+            _ => (),
+        }
+        requests.insert(nick.to_owned(), now);
+        true
+    }
+
+    pub fn set_clock(&self, clock: Box<Clock>) {
+        *self.clock.lock().unwrap() = clock;
+    }
+
+    /// Replaces the in-memory schedule with the jobs persisted by a previous run.
+    pub fn load_schedule(&self) -> Result<()> {
+        *self.schedule.lock().unwrap() = try!(Schedule::load());
+        Ok(())
+    }
+
+    pub fn schedule(&self, due: i64, task: Task) -> Result<u32> {
+        let mut schedule = self.schedule.lock().unwrap();
+        let id = schedule.add(due, task);
+        try!(schedule.save());
+        Ok(id)
+    }
+
+    pub fn cancel_scheduled(&self, task: &Task) -> Result<()> {
+        let mut schedule = self.schedule.lock().unwrap();
+        if schedule.cancel(task) {
+            try!(schedule.save());
+        }
+        Ok(())
+    }
+
+    pub fn take_due_jobs(&self) -> Result<Vec<Job>> {
+        let now = self.now();
+        let mut schedule = self.schedule.lock().unwrap();
+        let jobs = schedule.take_due(now);
+        if !jobs.is_empty() {
+            if let Err(e) = schedule.save() {
+                // Put the jobs back so that they're tried again rather than lost.
+                schedule.jobs.extend(jobs.into_iter());
+                return Err(e)
+            }
+        }
+        Ok(jobs)
     }
 
     #[cfg(test)]
@@ -73,10 +355,33 @@ impl State {
         self.democracy.lock().unwrap()
     }
 
+    #[cfg(feature = "resistance")]
+    pub fn get_games_of(&self, nick: &str) -> Vec<String> {
+        self.get_games().iter().filter(|&(_, game)| game.has_player(nick))
+                        .map(|(chan, _)| chan.clone()).collect()
+    }
+
+    #[cfg(not(feature = "resistance"))]
+    pub fn get_games_of(&self, _: &str) -> Vec<String> {
+        Vec::new()
+    }
+
+    #[cfg(feature = "democracy")]
+    pub fn get_votes_by(&self, nick: &str) -> Vec<String> {
+        self.get_votes().iter().flat_map(|(chan, democracy)| {
+            democracy.get_votes_by(nick).into_iter().map(move |v| format!("{}: {}", chan, v))
+        }).collect()
+    }
+
+    #[cfg(not(feature = "democracy"))]
+    pub fn get_votes_by(&self, _: &str) -> Vec<String> {
+        Vec::new()
+    }
+
     #[cfg(feature = "democracy")]
     pub fn get_online_voting_pop(&self, chan: &str) -> usize {
         if let Ok(mut chan) = Channel::load(chan) {
-            chan.voice.retain(|u| self.identified.lock().unwrap().contains(u));
+            chan.voice.retain(|u| self.is_identified(u));
             chan.voice.len()
         } else {
             0
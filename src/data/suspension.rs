@@ -0,0 +1,54 @@
+use std::borrow::ToOwned;
+use data::format_time;
+
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug, PartialEq)]
+pub struct Suspension {
+    pub setter: String,
+    pub reason: String,
+    pub since: i64,
+    pub expires: Option<i64>,
+}
+
+impl Suspension {
+    pub fn new(setter: &str, reason: &str, since: i64, duration: Option<i64>) -> Suspension {
+        Suspension {
+            setter: setter.to_owned(),
+            reason: reason.to_owned(),
+            since: since,
+            expires: duration.map(|d| since + d),
+        }
+    }
+
+    pub fn is_active(&self, now: i64) -> bool {
+        self.expires.map_or(true, |expires| expires > now)
+    }
+
+    pub fn describe(&self) -> String {
+        match self.expires {
+            Some(expires) => format!("{} (by {}, until {})", self.reason, self.setter,
+                                     format_time(expires)),
+            None => format!("{} (by {}, permanently)", self.reason, self.setter),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Suspension;
+
+    #[test]
+    fn is_active() {
+        let temporary = Suspension::new("test", "spam", 100, Some(60));
+        assert!(temporary.is_active(159));
+        assert!(!temporary.is_active(160));
+        assert!(Suspension::new("test", "spam", 100, None).is_active(1 << 40));
+    }
+
+    #[test]
+    fn describe() {
+        assert_eq!(&Suspension::new("test", "spam", 0, None).describe()[..],
+                   "spam (by test, permanently)");
+        assert_eq!(&Suspension::new("test", "spam", 0, Some(60)).describe()[..],
+                   "spam (by test, until 1970-01-01T00:01:00Z)");
+    }
+}
@@ -0,0 +1,105 @@
+use std::cmp::min;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ThrottleConfig {
+    pub max_failures: u32,
+    pub backoff: i64,
+    pub lockout: i64,
+}
+
+impl ThrottleConfig {
+    pub fn new(max_failures: u32, backoff: i64, lockout: i64) -> ThrottleConfig {
+        ThrottleConfig { max_failures: max_failures, backoff: backoff, lockout: lockout }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Throttle {
+    failures: u32,
+    last_failure: i64,
+    locked_until: i64,
+}
+
+impl Throttle {
+    pub fn new() -> Throttle {
+        Throttle { failures: 0, last_failure: 0, locked_until: 0 }
+    }
+
+    /// Returns how many seconds remain before another attempt is allowed, if any.
+    pub fn wait_time(&self, now: i64, config: &ThrottleConfig) -> Option<i64> {
+        let until = if self.locked_until > now {
+            self.locked_until
+        } else if self.failures > 0 && self.locked_until == 0 {
+            self.last_failure + self.delay(config)
+        } else {
+            0
+        };
+        if until > now { Some(until - now) } else { None }
+    }
+
+    /// Records a failed attempt, returning true if it caused the throttle to lock.
+    pub fn fail(&mut self, now: i64, config: &ThrottleConfig) -> bool {
+        if self.locked_until != 0 && self.locked_until <= now {
+            *self = Throttle::new();
+        }
+        self.failures += 1;
+        self.last_failure = now;
+        if self.failures >= config.max_failures && self.locked_until == 0 {
+            self.locked_until = now + config.lockout;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn is_stale(&self, now: i64, config: &ThrottleConfig) -> bool {
+        self.locked_until <= now && self.last_failure + config.lockout <= now
+    }
+
+    fn delay(&self, config: &ThrottleConfig) -> i64 {
+        let exponent = min(self.failures - 1, 30);
+        min(config.backoff.saturating_mul(1 << exponent), config.lockout)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Throttle, ThrottleConfig};
+
+    #[test]
+    fn backoff() {
+        let config = ThrottleConfig::new(5, 2, 900);
+        let mut throttle = Throttle::new();
+        assert_eq!(throttle.wait_time(100, &config), None);
+        assert!(!throttle.fail(100, &config));
+        assert_eq!(throttle.wait_time(100, &config), Some(2));
+        assert_eq!(throttle.wait_time(102, &config), None);
+        assert!(!throttle.fail(102, &config));
+        assert_eq!(throttle.wait_time(102, &config), Some(4));
+        assert!(!throttle.fail(106, &config));
+        assert_eq!(throttle.wait_time(106, &config), Some(8));
+    }
+
+    #[test]
+    fn lockout() {
+        let config = ThrottleConfig::new(2, 1, 900);
+        let mut throttle = Throttle::new();
+        assert!(!throttle.fail(100, &config));
+        assert!(throttle.fail(101, &config));
+        assert_eq!(throttle.wait_time(101, &config), Some(900));
+        assert!(!throttle.fail(200, &config));
+        assert_eq!(throttle.wait_time(200, &config), Some(801));
+        assert_eq!(throttle.wait_time(1001, &config), None);
+        assert!(!throttle.fail(1001, &config));
+        assert_eq!(throttle.wait_time(1001, &config), Some(1));
+    }
+
+    #[test]
+    fn is_stale() {
+        let config = ThrottleConfig::new(5, 1, 900);
+        let mut throttle = Throttle::new();
+        throttle.fail(100, &config);
+        assert!(!throttle.is_stale(500, &config));
+        assert!(throttle.is_stale(1000, &config));
+    }
+}
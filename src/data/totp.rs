@@ -0,0 +1,126 @@
+use std::ascii::AsciiExt;
+use openssl::crypto::hash::Type;
+use openssl::crypto::hmac::hmac;
+use openssl::crypto::rand::rand_bytes;
+use rustc_serialize::hex::ToHex;
+
+const ALPHABET: &'static [u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+const STEP: i64 = 30;
+
+pub fn generate_secret() -> String {
+    base32_encode(&rand_bytes(20))
+}
+
+pub fn generate_recovery_codes(count: usize) -> Vec<String> {
+    (0..count).map(|_| rand_bytes(5).to_hex()).collect()
+}
+
+pub fn code_at(secret: &str, time: i64) -> Option<String> {
+    base32_decode(secret).map(|key| format!("{:06}", hotp(&key, (time / STEP) as u64)))
+}
+
+/// Checks a code against the secret, returning the time step it belongs to.
+pub fn verify(secret: &str, code: &str, time: i64) -> Option<i64> {
+    // Allow one step of clock drift in either direction.
+    [time - STEP, time, time + STEP].iter().find(|t| {
+        code_at(secret, **t).map_or(false, |c| &c[..] == code)
+    }).map(|t| *t / STEP)
+}
+
+pub fn otpauth_uri(issuer: &str, account: &str, secret: &str) -> String {
+    format!("otpauth://totp/{}:{}?secret={}&issuer={}", uri_encode(issuer), uri_encode(account),
+            secret, uri_encode(issuer))
+}
+
+fn hotp(key: &[u8], counter: u64) -> u32 {
+    let msg: Vec<u8> = (0..8).rev().map(|i| (counter >> (i * 8)) as u8).collect();
+    let hash = hmac(Type::SHA1, key, &msg);
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let code = ((hash[offset] as u32 & 0x7f) << 24) | ((hash[offset + 1] as u32) << 16)
+             | ((hash[offset + 2] as u32) << 8) | (hash[offset + 3] as u32);
+    code % 1000000
+}
+
+fn base32_encode(data: &[u8]) -> String {
+    let mut ret = String::new();
+    let (mut buffer, mut bits) = (0u32, 0u32);
+    for byte in data.iter() {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            ret.push(ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        ret.push(ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    ret
+}
+
+fn base32_decode(data: &str) -> Option<Vec<u8>> {
+    let mut ret = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0u32);
+    for c in data.chars().filter(|c| *c != '=' && *c != ' ') {
+        let c = c.to_ascii_uppercase() as u8;
+        let value = match ALPHABET.iter().position(|a| *a == c) {
+            Some(value) => value as u32,
+            None => return None,
+        };
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            ret.push((buffer >> bits) as u8);
+        }
+    }
+    Some(ret)
+}
+
+fn uri_encode(data: &str) -> String {
+    let mut ret = String::new();
+    for byte in data.bytes() {
+        match byte {
+            b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' | b'-' | b'.' | b'_' | b'~' => {
+                ret.push(byte as char)
+            },
+            _ => ret.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use super::{base32_decode, base32_encode, hotp};
+
+    #[test]
+    fn base32() {
+        assert_eq!(&base32_encode(b"12345678901234567890")[..],
+                   "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        assert_eq!(base32_decode("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ").unwrap(),
+                   b"12345678901234567890".to_vec());
+        assert!(base32_decode("not base32!").is_none());
+    }
+
+    #[test]
+    fn rfc_vectors() {
+        assert_eq!(hotp(b"12345678901234567890", 0), 755224);
+        assert_eq!(hotp(b"12345678901234567890", 1), 287082);
+        assert_eq!(&super::code_at("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ", 59).unwrap()[..], "287082");
+    }
+
+    #[test]
+    fn verify() {
+        let secret = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+        assert_eq!(super::verify(secret, "287082", 59), Some(1));
+        assert_eq!(super::verify(secret, "287082", 89), Some(1));
+        assert_eq!(super::verify(secret, "287082", 150), None);
+    }
+
+    #[test]
+    fn otpauth_uri() {
+        assert_eq!(&super::otpauth_uri("Services", "te[st]", "ABC")[..],
+                   "otpauth://totp/Services:te%5Bst%5D?secret=ABC&issuer=Services");
+    }
+}
@@ -1,16 +1,41 @@
-use super::password_hash;
+use super::{decode_with_defaults, mask_matches, password_hash, totp};
+use super::channel::Channel;
+use super::memo::MemoBox;
+use super::settings::Settings;
+use super::suspension::Suspension;
+use std::ascii::AsciiExt;
 use std::borrow::ToOwned;
-use std::fs::{File, create_dir_all};
+use std::fs::{File, create_dir_all, read_dir, remove_file};
 use std::io::{Error, ErrorKind, Result};
 use std::io::prelude::*;
 use std::path::Path;
-use rustc_serialize::json::{decode, encode};
+use rustc_serialize::json::encode;
+
+const HISTORY_LENGTH: usize = 10;
 
 #[derive(RustcEncodable, RustcDecodable, Debug, PartialEq)]
 pub struct User {
     pub nickname: String,
     pub password: String,
     pub email: Option<String>,
+    pub certfps: Vec<String>,
+    pub access: Vec<String>,
+    pub access_identifies: bool,
+    pub totp_secret: Option<String>,
+    pub totp_last_step: Option<i64>,
+    pub recovery_codes: Vec<String>,
+    pub lock_notice: Option<String>,
+    pub suspension: Option<Suspension>,
+    pub history: Vec<HostRecord>,
+    pub vhost: Option<String>,
+    pub settings: Settings,
+    pub registered: Option<i64>,
+}
+
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug, PartialEq)]
+pub struct HostRecord {
+    pub hostmask: String,
+    pub time: i64,
 }
 
 impl User {
@@ -19,6 +44,18 @@ impl User {
             nickname: nickname.to_owned(),
             password: try!(password_hash(password)),
             email: email.map(|s| s.to_owned()),
+            certfps: Vec::new(),
+            access: Vec::new(),
+            access_identifies: false,
+            totp_secret: None,
+            totp_last_step: None,
+            recovery_codes: Vec::new(),
+            lock_notice: None,
+            suspension: None,
+            history: Vec::new(),
+            vhost: None,
+            settings: Settings::new(),
+            registered: None,
         })
     }
 
@@ -31,21 +68,184 @@ impl User {
         Ok(self.password == try!(password_hash(password)))
     }
 
+    pub fn add_certfp(&mut self, fingerprint: &str) -> bool {
+        let fp = normalize_certfp(fingerprint);
+        if self.certfps.contains(&fp) {
+            false
+        } else {
+            self.certfps.push(fp);
+            true
+        }
+    }
+
+    pub fn remove_certfp(&mut self, fingerprint: &str) -> bool {
+        let fp = normalize_certfp(fingerprint);
+        let len = self.certfps.len();
+        self.certfps.retain(|f| f != &fp);
+        self.certfps.len() != len
+    }
+
+    pub fn has_certfp(&self, fingerprint: &str) -> bool {
+        self.certfps.contains(&normalize_certfp(fingerprint))
+    }
+
+    pub fn add_access(&mut self, mask: &str) -> bool {
+        if self.access.iter().any(|m| m.eq_ignore_ascii_case(mask)) {
+            false
+        } else {
+            self.access.push(mask.to_owned());
+            true
+        }
+    }
+
+    pub fn remove_access(&mut self, mask: &str) -> bool {
+        let len = self.access.len();
+        self.access.retain(|m| !m.eq_ignore_ascii_case(mask));
+        self.access.len() != len
+    }
+
+    pub fn matches_access(&self, userhost: &str) -> bool {
+        self.access.iter().any(|m| mask_matches(m, userhost))
+    }
+
+    /// Returns the email address as `viewer` may see it. HIDEMAIL only hides it from other
+    /// users, never from the account itself or from services operators.
+    pub fn email_for(&self, viewer: &str, operator: bool) -> Option<&str> {
+        if self.settings.hide_email && !operator && viewer != &self.nickname[..] {
+            None
+        } else {
+            self.email.as_ref().map(|s| &s[..])
+        }
+    }
+
+    pub fn has_totp(&self) -> bool {
+        self.totp_secret.is_some()
+    }
+
+    pub fn enable_totp(&mut self, secret: &str, recovery_codes: &[String]) -> Result<()> {
+        let mut hashes = Vec::new();
+        for code in recovery_codes.iter() {
+            hashes.push(try!(password_hash(code)));
+        }
+        self.totp_secret = Some(secret.to_owned());
+        self.totp_last_step = None;
+        self.recovery_codes = hashes;
+        Ok(())
+    }
+
+    pub fn disable_totp(&mut self) {
+        self.totp_secret = None;
+        self.totp_last_step = None;
+        self.recovery_codes = Vec::new();
+    }
+
+    /// Checks a two-factor code, consuming it if it was a recovery code. A time-based code is
+    /// only accepted once, so it can't be replayed while it's still valid.
+    pub fn check_code(&mut self, code: &str, time: i64) -> Result<bool> {
+        let step = match self.totp_secret {
+            Some(ref secret) => totp::verify(secret, code, time),
+            None => return Ok(true),
+        };
+        if let Some(step) = step {
+            if self.totp_last_step.map_or(true, |last| step > last) {
+                self.totp_last_step = Some(step);
+                try!(self.save());
+                return Ok(true)
+            }
+        }
+        let hash = try!(password_hash(code));
+        let len = self.recovery_codes.len();
+        self.recovery_codes.retain(|c| c != &hash);
+        if self.recovery_codes.len() != len {
+            try!(self.save());
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn active_suspension(&self, now: i64) -> Option<&Suspension> {
+        self.suspension.as_ref().and_then(|s| if s.is_active(now) { Some(s) } else { None })
+    }
+
+    /// Records a hostmask used to identify, keeping only the most recent entries.
+    pub fn record_host(&mut self, hostmask: &str, time: i64) {
+        self.history.retain(|r| &r.hostmask[..] != hostmask);
+        self.history.push(HostRecord { hostmask: hostmask.to_owned(), time: time });
+        while self.history.len() > HISTORY_LENGTH {
+            self.history.remove(0);
+        }
+    }
+
+    pub fn last_host(&self) -> Option<&str> {
+        self.history.last().map(|r| &r.hostmask[..])
+    }
+
+    pub fn list() -> Result<Vec<String>> {
+        let mut ret = Vec::new();
+        for entry in try!(read_dir(Path::new("data/nickserv/"))) {
+            let path = try!(entry).path();
+            if path.extension().map_or(false, |e| e == "json") {
+                if let Some(nickname) = path.file_stem().and_then(|s| s.to_str()) {
+                    ret.push(nickname.to_owned());
+                }
+            }
+        }
+        ret.sort();
+        Ok(ret)
+    }
+
+    /// Renames the account, rewriting its channel access entries and memos. If any write fails,
+    /// everything written so far is rolled back and the account keeps its old name.
+    /// Moves the account, its memos and its channel access to a new nickname. The account
+    /// itself is moved first so a failure there leaves everything untouched; the names of any
+    /// channels that could not be updated afterwards are returned.
+    pub fn rename(&mut self, nickname: &str) -> Result<Vec<String>> {
+        try!(check_path_name(nickname));
+        let old = self.nickname.clone();
+        let mut channels = Vec::new();
+        for name in try!(Channel::list()).iter() {
+            let mut chan = try!(Channel::load(name));
+            if chan.rename_user(&old, nickname) {
+                channels.push(chan);
+            }
+        }
+        let mut memos = try!(MemoBox::load(&old));
+        memos.owner = nickname.to_owned();
+        self.nickname = nickname.to_owned();
+        let res = self.save().and_then(|_| {
+            if memos.memos.is_empty() { Ok(()) } else { memos.save() }
+        }).and_then(|_| remove_file(Path::new(&format!("data/nickserv/{}.json", old))));
+        if let Err(e) = res {
+            self.nickname = old;
+            try!(remove_if_exists(&format!("data/memoserv/{}.json", nickname)));
+            try!(remove_if_exists(&format!("data/nickserv/{}.json", nickname)));
+            return Err(e)
+        }
+        let _ = remove_file(Path::new(&format!("data/memoserv/{}.json", old)));
+        Ok(channels.iter().filter(|chan| chan.save().is_err()).map(|chan| chan.name.clone())
+                   .collect())
+    }
+
     pub fn exists(nickname: &str) -> bool {
-        Path::new(&format!("data/nickserv/{}.json", nickname)).exists()
+        check_path_name(nickname).is_ok() &&
+            Path::new(&format!("data/nickserv/{}.json", nickname)).exists()
     }
 
     pub fn load(nickname: &str) -> Result<User> {
+        try!(check_path_name(nickname));
         let path = format!("data/nickserv/{}.json", nickname);
         let mut file = try!(File::open(Path::new(&path)));
         let mut data = String::new();
         try!(file.read_to_string(&mut data));
-        decode(&data).map_err(|_| Error::new(
+        let defaults = try!(User::new(nickname, "", None));
+        decode_with_defaults(&data, &defaults).ok_or(Error::new(
             ErrorKind::InvalidInput, "Failed to decode user data."
         ))
     }
 
     pub fn save(&self) -> Result<()> {
+        try!(check_path_name(&self.nickname));
         let mut path = "data/nickserv/".to_owned();
         let _ = create_dir_all(Path::new(&path));
         path.push_str(&self.nickname);
@@ -58,12 +258,58 @@ impl User {
     }
 }
 
+/// Refuses nicknames that would reach outside the data directory when used in a path.
+fn check_path_name(nickname: &str) -> Result<()> {
+    if nickname.contains('/') || nickname.contains("..") {
+        Err(Error::new(ErrorKind::InvalidInput, "Invalid nickname."))
+    } else {
+        Ok(())
+    }
+}
+
+fn remove_if_exists(path: &str) -> Result<()> {
+    match remove_file(Path::new(path)) {
+        Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        res => res,
+    }
+}
+
+pub fn normalize_certfp(fingerprint: &str) -> String {
+    fingerprint.chars().filter(|c| *c != ':').map(|c| c.to_ascii_lowercase()).collect()
+}
+
+pub fn is_valid_access_mask(mask: &str) -> bool {
+    let parts: Vec<_> = mask.split('@').collect();
+    parts.len() == 2 && parts[0].len() > 0 && parts[1].chars().any(|c| c != '*' && c != '?')
+}
+
+/// Checks a nickname against the IRC nickname character set, which also keeps it safe to use
+/// as a file name.
+pub fn is_valid_nickname(nickname: &str) -> bool {
+    let special = |c: char| "[]\\`_^{|}".chars().any(|s| s == c);
+    let mut chars = nickname.chars();
+    match chars.next() {
+        Some(c) if (c.is_ascii() && c.is_alphabetic()) || special(c) => {
+            chars.all(|c| (c.is_ascii() && c.is_alphanumeric()) || c == '-' || special(c))
+        },
+        _ => false,
+    }
+}
+
+pub fn is_valid_certfp(fingerprint: &str) -> bool {
+    let fp = normalize_certfp(fingerprint);
+    fp.len() >= 32 && fp.chars().all(|c| c.is_digit(16))
+}
+
 #[cfg(test)]
 mod test {
+    use super::super::channel::Channel;
     use super::super::password_hash;
+    use super::super::settings::Settings;
     use super::User;
     use std::borrow::ToOwned;
-    use std::fs::remove_file;
+    use std::fs::{File, create_dir_all, remove_file};
+    use std::io::prelude::*;
     use std::path::Path;
 
     #[test]
@@ -72,11 +318,35 @@ mod test {
             nickname: "test".to_owned(),
             password: password_hash("test").unwrap(),
             email: None,
+            certfps: Vec::new(),
+            access: Vec::new(),
+            access_identifies: false,
+            totp_secret: None,
+            totp_last_step: None,
+            recovery_codes: Vec::new(),
+            lock_notice: None,
+            suspension: None,
+            history: Vec::new(),
+            vhost: None,
+            settings: Settings::new(),
+            registered: None,
         });
         assert_eq!(User::new("test", "test", Some("test@test.com")).unwrap(), User {
             nickname: "test".to_owned(),
             password: password_hash("test").unwrap(),
             email: Some("test@test.com".to_owned()),
+            certfps: Vec::new(),
+            access: Vec::new(),
+            access_identifies: false,
+            totp_secret: None,
+            totp_last_step: None,
+            recovery_codes: Vec::new(),
+            lock_notice: None,
+            suspension: None,
+            history: Vec::new(),
+            vhost: None,
+            settings: Settings::new(),
+            registered: None,
         });
     }
 
@@ -87,6 +357,8 @@ mod test {
         assert!(!User::exists("test2"));
         u.save().unwrap();
         assert!(User::exists("test2"));
+        assert!(!User::exists("../nickserv/test2"));
+        assert!(User::load("../nickserv/test2").is_err());
     }
 
     #[test]
@@ -103,4 +375,109 @@ mod test {
         assert!(v.is_ok());
         assert_eq!(v.unwrap(), u);
     }
+
+    #[test]
+    fn load_baseline_record() {
+        let _ = create_dir_all(Path::new("data/nickserv/"));
+        let mut f = File::create(Path::new("data/nickserv/test115.json")).unwrap();
+        f.write_all(b"{\"nickname\":\"test115\",\"password\":\"x\",\"email\":null}").unwrap();
+        let u = User::load("test115").unwrap();
+        assert_eq!(&u.password[..], "x");
+        assert!(u.certfps.is_empty());
+        assert_eq!(u.settings, Settings::new());
+    }
+
+    #[test]
+    fn certfps() {
+        let mut u = User::new("test", "test", None).unwrap();
+        assert!(u.add_certfp("AB:CD:EF"));
+        assert!(!u.add_certfp("abcdef"));
+        assert!(u.has_certfp("ABCDEF"));
+        assert!(u.remove_certfp("ab:cd:ef"));
+        assert!(!u.has_certfp("abcdef"));
+    }
+
+    #[test]
+    fn is_valid_certfp() {
+        assert!(super::is_valid_certfp("0123456789abcdef0123456789ABCDEF"));
+        assert!(!super::is_valid_certfp("0123456789abcdef"));
+        assert!(!super::is_valid_certfp("0123456789abcdef0123456789abcdeg"));
+    }
+
+    #[test]
+    fn access() {
+        let mut u = User::new("test", "test", None).unwrap();
+        assert!(u.add_access("*@*.example.com"));
+        assert!(!u.add_access("*@*.EXAMPLE.com"));
+        assert!(u.matches_access("user@host.example.com"));
+        assert!(!u.matches_access("user@example.org"));
+        assert!(u.remove_access("*@*.example.com"));
+        assert!(!u.matches_access("user@host.example.com"));
+    }
+
+    #[test]
+    fn is_valid_nickname() {
+        assert!(super::is_valid_nickname("test"));
+        assert!(super::is_valid_nickname("[test]-1"));
+        assert!(!super::is_valid_nickname("1test"));
+        assert!(!super::is_valid_nickname("../test"));
+        assert!(!super::is_valid_nickname(""));
+    }
+
+    #[test]
+    fn is_valid_access_mask() {
+        assert!(super::is_valid_access_mask("*@*.example.com"));
+        assert!(!super::is_valid_access_mask("*@*"));
+        assert!(!super::is_valid_access_mask("example.com"));
+    }
+
+    #[test]
+    fn record_host() {
+        let mut u = User::new("test", "test", None).unwrap();
+        for i in 0..12 {
+            u.record_host(&format!("user@host{}", i), i);
+        }
+        u.record_host("user@host5", 20);
+        assert_eq!(u.history.len(), 10);
+        assert_eq!(&u.history[0].hostmask[..], "user@host2");
+        assert_eq!(u.last_host(), Some("user@host5"));
+    }
+
+    #[test]
+    fn rename() {
+        let _ = remove_file(Path::new("data/nickserv/test65.json"));
+        let _ = remove_file(Path::new("data/nickserv/test65_new.json"));
+        let mut u = User::new("test65", "test", None).unwrap();
+        u.save().unwrap();
+        let mut chan = Channel::new("#test30", "test", "test65").unwrap();
+        chan.voice.push("test65".to_owned());
+        chan.save().unwrap();
+        assert!(u.rename("test65_new").unwrap().is_empty());
+        assert!(!User::exists("test65"));
+        assert_eq!(&User::load("test65_new").unwrap().nickname[..], "test65_new");
+        let chan = Channel::load("#test30").unwrap();
+        assert_eq!(&chan.owner[..], "test65_new");
+        assert_eq!(chan.voice, vec!["test65_new".to_owned()]);
+    }
+
+    #[test]
+    fn email_for() {
+        let mut u = User::new("test", "test", Some("test@example.com")).unwrap();
+        u.settings.hide_email = true;
+        assert_eq!(u.email_for("test", false), Some("test@example.com"));
+        assert_eq!(u.email_for("test2", true), Some("test@example.com"));
+        assert_eq!(u.email_for("test2", false), None);
+    }
+
+    #[test]
+    fn check_code() {
+        let mut u = User::new("test23", "test", None).unwrap();
+        assert!(u.check_code("000000", 59).unwrap());
+        u.enable_totp("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ", &["recovery".to_owned()]).unwrap();
+        assert!(u.check_code("287082", 59).unwrap());
+        assert!(!u.check_code("287082", 89).unwrap());
+        assert!(!u.check_code("000000", 59).unwrap());
+        assert!(u.check_code("recovery", 59).unwrap());
+        assert!(!u.check_code("recovery", 59).unwrap());
+    }
 }
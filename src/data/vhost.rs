@@ -0,0 +1,126 @@
+use super::mask_matches;
+use std::ascii::AsciiExt;
+use std::borrow::ToOwned;
+use std::fs::{File, create_dir_all};
+use std::io::{Error, ErrorKind, Result};
+use std::io::prelude::*;
+use std::path::Path;
+use rustc_serialize::json::{decode, encode};
+use data::BotResult;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VhostPolicy {
+    pub max_length: usize,
+    pub forbidden: Vec<String>,
+}
+
+impl VhostPolicy {
+    pub fn new(max_length: usize, forbidden: &str) -> VhostPolicy {
+        VhostPolicy {
+            max_length: max_length,
+            forbidden: forbidden.split(',').map(|s| s.trim()).filter(|s| !s.is_empty())
+                                .map(|s| s.to_owned()).collect(),
+        }
+    }
+
+    pub fn check(&self, vhost: &str) -> BotResult<()> {
+        let valid_label = |label: &str| {
+            !label.is_empty() && !label.starts_with("-") && !label.ends_with("-") &&
+            label.chars().all(|c| (c.is_ascii() && c.is_alphanumeric()) || c == '-')
+        };
+        if vhost.len() > self.max_length {
+            Err(format!("Vhosts may be at most {} characters long.", self.max_length))
+        } else if !vhost.split(|c| c == '.' || c == '/').all(valid_label) {
+            Err(format!("{} is not a valid vhost. Use letters, digits, hyphens, dots and \
+                         slashes.", vhost))
+        } else if !vhost.chars().any(|c| c.is_alphabetic()) {
+            Err("Vhosts must contain at least one letter.".to_owned())
+        } else if self.forbidden.iter().any(|mask| mask_matches(mask, vhost)) {
+            Err(format!("{} is not allowed as a vhost.", vhost))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug, PartialEq)]
+pub struct VhostRequest {
+    pub account: String,
+    pub vhost: String,
+    pub time: i64,
+}
+
+#[derive(RustcEncodable, RustcDecodable, Debug, PartialEq)]
+pub struct VhostRequests {
+    pub requests: Vec<VhostRequest>,
+}
+
+impl VhostRequests {
+    pub fn new() -> VhostRequests {
+        VhostRequests { requests: Vec::new() }
+    }
+
+    /// Adds a request, replacing any pending request for the same account.
+    pub fn add(&mut self, account: &str, vhost: &str, time: i64) {
+        self.requests.retain(|r| &r.account[..] != account);
+        self.requests.push(VhostRequest {
+            account: account.to_owned(), vhost: vhost.to_owned(), time: time
+        });
+    }
+
+    pub fn take(&mut self, account: &str) -> Option<VhostRequest> {
+        let index = self.requests.iter().position(|r| &r.account[..] == account);
+        index.map(|i| self.requests.remove(i))
+    }
+
+    pub fn load() -> Result<VhostRequests> {
+        let path = Path::new("data/hostserv/requests.json");
+        if !path.exists() {
+            return Ok(VhostRequests::new())
+        }
+        let mut file = try!(File::open(path));
+        let mut data = String::new();
+        try!(file.read_to_string(&mut data));
+        decode(&data).map_err(|_| Error::new(
+            ErrorKind::InvalidInput, "Failed to decode vhost request data."
+        ))
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let _ = create_dir_all(Path::new("data/hostserv/"));
+        let mut f = try!(File::create(Path::new("data/hostserv/requests.json")));
+        try!(f.write_all(try!(encode(self).map_err(|_| Error::new(
+            ErrorKind::InvalidInput, "Failed to encode vhost request data."
+        ))).as_bytes()));
+        f.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{VhostPolicy, VhostRequests};
+
+    #[test]
+    fn check() {
+        let policy = VhostPolicy::new(20, "*.gov, *staff*");
+        assert!(policy.check("cool.example.com").is_ok());
+        assert!(policy.check("users/test").is_ok());
+        assert!(policy.check("this.vhost.is.much.too.long").is_err());
+        assert!(policy.check("bad..host").is_err());
+        assert!(policy.check("-bad.host").is_err());
+        assert!(policy.check("bad host").is_err());
+        assert!(policy.check("127.0.0.1").is_err());
+        assert!(policy.check("whitehouse.gov").is_err());
+        assert!(policy.check("ircstaff.net").is_err());
+    }
+
+    #[test]
+    fn requests() {
+        let mut requests = VhostRequests::new();
+        requests.add("test", "first.example.com", 0);
+        requests.add("test", "second.example.com", 1);
+        assert_eq!(requests.requests.len(), 1);
+        assert_eq!(&requests.take("test").unwrap().vhost[..], "second.example.com");
+        assert!(requests.take("test").is_none());
+    }
+}
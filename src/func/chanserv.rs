@@ -1,8 +1,16 @@
-use super::Functionality;
+use super::{Functionality, config_option, is_services_operator, log_event, send_reply,
+            upper_case};
 use std::borrow::ToOwned;
 use std::io::Result;
-use data::BotResult;
-use data::channel::Channel;
+use data::{BotResult, Pattern, format_time, parse_duration};
+use data::channel::{self, AccessLevel, Channel};
+use data::forbid::ForbidList;
+use data::mode::{self, ModeChange, ModeLock};
+use data::role::{Role, is_valid_role_name};
+use data::scheduler::Task;
+use data::settings::{ChannelSetting, Restriction};
+use data::suspension::Suspension;
+use data::user::User;
 use data::state::State;
 use irc::client::prelude::*;
 
@@ -32,6 +40,68 @@ impl<'a, T: IrcRead, U: IrcWrite> Register<'a, T, U> {
     }
 }
 
+impl<'a, T: IrcRead, U: IrcWrite> Register<'a, T, U> {
+    /// Checks the anti-squatting limits, which services operators are exempt from.
+    fn check_restrictions(&self) -> Option<String> {
+        if is_services_operator(self.server, self.state, &self.owner) {
+            return None
+        }
+        let limit = config_option(self.server, "channel-limit", 10);
+        let owned = Channel::list().unwrap_or(Vec::new()).iter().filter_map(|name| {
+            Channel::load(name).ok()
+        }).filter(|chan| chan.owner == self.owner).count();
+        if limit > 0 && owned >= limit {
+            return Some(format!("You may not register more than {} channels.", limit))
+        }
+        let age: String = config_option(self.server, "channel-min-age", "0".to_owned());
+        let min_age = parse_duration(&age).ok().and_then(|d| d).unwrap_or(0);
+        let registered = User::load(&self.owner).ok().and_then(|u| u.registered);
+        if let Some(registered) = registered {
+            if self.state.now() - registered < min_age {
+                return Some(format!("Your account must be registered for at least {} before \
+                                     registering a channel.", age))
+            }
+        }
+        None
+    }
+}
+
+/// Completes a registration that was waiting on the NAMES list for its channel, which has now
+/// ended. The prefix modes it listed are dropped afterwards, since we won't see later changes
+/// in channels we aren't in.
+pub fn finish_registration<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>,
+                                                        state: &State, channel: &str)
+    -> Result<()> {
+    let chan = match state.take_pending_registration(channel) {
+        Some(chan) => chan,
+        None => return Ok(()),
+    };
+    let is_op = state.is_chanop(channel, &chan.owner);
+    state.forget_chanops(channel);
+    let msg = if !state.is_identified(&chan.owner) {
+        format!("You must be identify as {} to do that.", &chan.owner)
+    } else if Channel::exists(&chan.name) {
+        format!("Channel {} is already registered!", chan.name)
+    } else if !is_op {
+        format!("You must be a channel operator in {} to register it.", chan.name)
+    } else {
+        try!(save_registration(server, &chan))
+    };
+    send_reply(server, state, &chan.owner, &msg)
+}
+
+fn save_registration<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>,
+                                                  chan: &Channel) -> Result<String> {
+    if chan.save().is_err() {
+        return Ok(format!("Failed to register {} due to an I/O issue.", chan.name))
+    }
+    try!(server.send_samode(&chan.name, "+r", ""));
+    try!(server.send_samode(&chan.name, "+qa", &chan.owner));
+    try!(server.send_join(&chan.name));
+    try!(server.send_samode(&chan.name, "+a", server.config().nickname()));
+    Ok(format!("Channel {} has been registered. Don't forget the password!", chan.name))
+}
+
 impl<'a, T: IrcRead, U: IrcWrite> Functionality for Register<'a, T, U> {
     fn do_func(&self) -> Result<()> {
         let chan = try!(Channel::new(&self.channel, &self.password, &self.owner));
@@ -39,16 +109,20 @@ impl<'a, T: IrcRead, U: IrcWrite> Functionality for Register<'a, T, U> {
             format!("You must be identify as {} to do that.", &self.owner)
         } else if Channel::exists(&self.channel) {
             format!("Channel {} is already registered!", chan.name)
-        } else if chan.save().is_ok() {;
-            try!(self.server.send_samode(&self.channel, "+r", ""));
-            try!(self.server.send_samode(&self.channel, "+qa", &self.owner));
-            try!(self.server.send_join(&self.channel));
-            try!(self.server.send_samode(&self.channel, "+a", self.server.config().nickname()));
-            format!("Channel {} has been registered. Don't forget the password!", chan.name)
+        } else if ForbidList::is_forbidden("channels", &self.channel) {
+            format!("Channel {} is forbidden and may not be registered.", chan.name)
+        } else if let Some(msg) = self.check_restrictions() {
+            msg
+        } else if !is_services_operator(self.server, self.state, &self.owner) {
+            // Ask for a fresh NAMES list and finish once it ends, as a cached operator status
+            // can be stale in channels we aren't in.
+            self.state.add_pending_registration(chan);
+            let names = Message::new(None, "NAMES", Some(vec![&self.channel[..]]), None);
+            return self.server.send(names)
         } else {
-            format!("Failed to register {} due to an I/O issue.", chan.name)
+            try!(save_registration(self.server, &chan))
         };
-        self.server.send_notice(&self.owner, &msg)
+        send_reply(self.server, self.state, &self.owner, &msg)
     }
 }
 
@@ -98,7 +172,7 @@ impl<'a, T: IrcRead, U: IrcWrite> Functionality for Admin<'a, T, U> {
         } else {
             format!("Failed to admin {} due to an I/O issue.", &self.target)
         };
-        self.server.send_notice(&self.owner, &msg)
+        send_reply(self.server, self.state, &self.owner, &msg)
     }
 }
 
@@ -148,7 +222,7 @@ impl<'a, T: IrcRead, U: IrcWrite> Functionality for Oper<'a, T, U> {
         } else {
             format!("Failed to oper {} due to an I/O issue.", &self.target)
         };
-        self.server.send_notice(&self.owner, &msg)
+        send_reply(self.server, self.state, &self.owner, &msg)
     }
 }
 
@@ -159,13 +233,14 @@ pub struct Voice<'a, T: IrcRead, U: IrcWrite> {
     channel: String,
     password: String,
     target: String,
+    duration: Option<i64>,
 }
 
 impl<'a, T: IrcRead, U: IrcWrite> Voice<'a, T, U> {
     pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
         -> BotResult<Box<Functionality + 'a>> {
-        if args.len() != 5 {
-            return Err("Syntax: CS VOICE user channel password".to_owned())
+        if args.len() != 5 && (args.len() != 6 || !args[5].starts_with("+")) {
+            return Err("Syntax: CS VOICE user channel password [+duration]".to_owned())
         }
         Ok(Box::new(Voice {
             server: server,
@@ -174,6 +249,7 @@ impl<'a, T: IrcRead, U: IrcWrite> Voice<'a, T, U> {
             channel: args[3].to_owned(),
             password: args[4].to_owned(),
             target: args[2].to_owned(),
+            duration: if args.len() == 6 { try!(parse_duration(&args[5][1..])) } else { None },
         }))
     }
 }
@@ -191,14 +267,22 @@ impl<'a, T: IrcRead, U: IrcWrite> Functionality for Voice<'a, T, U> {
                 chan.voice.push(self.target.clone());
                 try!(chan.save());
                 try!(self.server.send_samode(&self.channel, "+v", &self.target));
-                format!("{} is now voiced.", &self.target)
+                let task = Task::RemoveVoice(self.channel.clone(), self.target.clone());
+                try!(self.state.cancel_scheduled(&task));
+                if let Some(duration) = self.duration {
+                    let expires = self.state.now() + duration;
+                    try!(self.state.schedule(expires, task));
+                    format!("{} is now voiced until {}.", &self.target, format_time(expires))
+                } else {
+                    format!("{} is now voiced.", &self.target)
+                }
             } else {
                 format!("Password incorrect.")
             }
         } else {
             format!("Failed to voice {} due to an I/O issue.", &self.target)
         };
-        self.server.send_notice(&self.owner, &msg)
+        send_reply(self.server, self.state, &self.owner, &msg)
     }
 }
 
@@ -246,7 +330,7 @@ impl<'a, T: IrcRead, U: IrcWrite> Functionality for Mode<'a, T, U> {
         } else {
             format!("Failed to set channel mode {} due to an I/O issue.", &self.mode)
         };
-        self.server.send_notice(&self.owner, &msg)
+        send_reply(self.server, self.state, &self.owner, &msg)
     }
 }
 
@@ -296,7 +380,7 @@ impl<'a, T: IrcRead, U: IrcWrite> Functionality for DeAdmin<'a, T, U> {
         } else {
             format!("Failed to de-admin {} due to an I/O issue.", &self.target)
         };
-        self.server.send_notice(&self.owner, &msg)
+        send_reply(self.server, self.state, &self.owner, &msg)
     }
 }
 
@@ -346,7 +430,7 @@ impl<'a, T: IrcRead, U: IrcWrite> Functionality for DeOper<'a, T, U> {
         } else {
             format!("Failed to de-oper {} due to an I/O issue.", &self.target)
         };
-        self.server.send_notice(&self.owner, &msg)
+        send_reply(self.server, self.state, &self.owner, &msg)
     }
 }
 
@@ -359,94 +443,1416 @@ pub struct DeVoice<'a, T: IrcRead, U: IrcWrite> {
     target: String,
 }
 
-impl<'a, T: IrcRead, U: IrcWrite> DeVoice<'a, T, U> {
-    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
-        -> BotResult<Box<Functionality + 'a>> {
-        if args.len() != 5 {
-            return Err("Syntax: CS DEVOICE user channel password".to_owned())
+impl<'a, T: IrcRead, U: IrcWrite> DeVoice<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() != 5 {
+            return Err("Syntax: CS DEVOICE user channel password".to_owned())
+        }
+        Ok(Box::new(DeVoice {
+            server: server,
+            state: state,
+            owner: user.to_owned(),
+            channel: args[3].to_owned(),
+            password: args[4].to_owned(),
+            target: args[2].to_owned(),
+        }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for DeVoice<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let msg = if !self.state.is_identified(&self.owner) {
+            format!("You must be identify as {} to do that.", &self.owner)
+        } else if !self.state.is_identified(&self.target) {
+            format!("{} must be identified to do that.", &self.target)
+        } else if !Channel::exists(&self.channel) {
+            format!("Channel {} is not registered!", &self.channel)
+        } else if let Ok(mut chan) = Channel::load(&self.channel) {
+            if try!(chan.is_password(&self.password)) {
+                chan.voice.retain(|u| u != &self.target);
+                try!(chan.save());
+                try!(self.server.send_samode(&self.channel, "-v", &self.target));
+                format!("{} is no longer voiced.", &self.target)
+            } else {
+                format!("Password incorrect.")
+            }
+        } else {
+            format!("Failed to de-voice {} due to an I/O issue.", &self.target)
+        };
+        send_reply(self.server, self.state, &self.owner, &msg)
+    }
+}
+
+pub struct ChangeOwner<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    owner: String,
+    channel: String,
+    password: String,
+    target: String,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> ChangeOwner<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() != 5 {
+            return Err("Syntax: CS CHOWN user channel password".to_owned())
+        }
+        Ok(Box::new(ChangeOwner {
+            server: server,
+            state: state,
+            owner: user.to_owned(),
+            channel: args[3].to_owned(),
+            password: args[4].to_owned(),
+            target: args[2].to_owned(),
+        }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for ChangeOwner<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let msg = if !self.state.is_identified(&self.owner) {
+            format!("You must be identify as {} to do that.", &self.owner)
+        } else if !self.state.is_identified(&self.target) && &self.target[..] != "Pidgey" {
+            format!("{} must be identified to do that.", &self.target)
+        } else if !Channel::exists(&self.channel) {
+            format!("Channel {} is not registered!", &self.channel)
+        } else if let Ok(mut chan) = Channel::load(&self.channel) {
+            if try!(chan.is_password(&self.password)) {
+                chan.owner = self.target.clone();
+                try!(chan.save());
+                try!(self.server.send_samode(&self.channel, "+q", &self.target));
+                format!("{} is now the channel owner.", &self.target)
+            } else {
+                format!("Password incorrect.")
+            }
+        } else {
+            format!("Failed to change owner to {} due to an I/O issue.", &self.target)
+        };
+        send_reply(self.server, self.state, &self.owner, &msg)
+    }
+}
+
+/// Determines the access a nickname currently holds, which requires it to be identified.
+pub fn effective_access(state: &State, channel: &Channel, nickname: &str) -> AccessLevel {
+    match state.get_account(nickname) {
+        Some(account) => channel.access_of(&account),
+        None => AccessLevel::None,
+    }
+}
+
+fn can_see_details<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>, state: &State,
+                                                channel: &Channel, nickname: &str) -> bool {
+    is_services_operator(server, state, nickname) ||
+    effective_access(state, channel, nickname) > AccessLevel::None
+}
+
+fn join_or_none(list: &[String]) -> String {
+    if list.is_empty() { "none".to_owned() } else { list.join(", ") }
+}
+
+pub struct Info<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    channel: String,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Info<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() != 3 {
+            return Err("Syntax: CS INFO channel".to_owned())
+        }
+        Ok(Box::new(Info {
+            server: server, state: state, user: user.to_owned(), channel: args[2].to_owned()
+        }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Info<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let msg = if !Channel::exists(&self.channel) {
+            format!("Channel {} is not registered!", &self.channel)
+        } else if let Ok(chan) = Channel::load(&self.channel) {
+            try!(send_reply(self.server, self.state, &self.user,
+                            &format!("{} is owned by {}.", chan.name, chan.owner)));
+            if let Some(suspension) = chan.active_suspension(self.state.now()) {
+                try!(send_reply(self.server, self.state, &self.user,
+                                &format!("Suspended: {}", suspension.describe())));
+            }
+            if can_see_details(self.server, self.state, &chan, &self.user) {
+                let mut lines = vec![
+                    format!("Admins: {}", join_or_none(&chan.admins)),
+                    format!("Opers: {}", join_or_none(&chan.opers)),
+                    format!("Voice: {}", join_or_none(&chan.voice)),
+                    match (chan.topic_setter.as_ref(), chan.topic_time) {
+                        (Some(setter), Some(time)) => {
+                            format!("Topic: {} (set by {} at {})", chan.topic, setter,
+                                    format_time(time))
+                        },
+                        _ => format!("Topic: {}", chan.topic),
+                    },
+                    format!("Mode: {}", chan.mode),
+                ];
+                if !chan.roles().is_empty() {
+                    let roles: Vec<_> = chan.roles().iter().map(|r| {
+                        format!("{} ({})", r.account, r.role)
+                    }).collect();
+                    lines.push(format!("Roles: {}", roles.join(", ")));
+                }
+                for line in lines.iter() {
+                    try!(send_reply(self.server, self.state, &self.user, line));
+                }
+            }
+            "End of info.".to_owned()
+        } else {
+            format!("Failed to load {} due to an I/O issue.", &self.channel)
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+pub struct List<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    pattern: Pattern,
+    raw_pattern: String,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> List<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() != 3 {
+            return Err("Syntax: CS LIST pattern".to_owned())
+        }
+        Ok(Box::new(List {
+            server: server,
+            state: state,
+            user: user.to_owned(),
+            pattern: try!(Pattern::parse(args[2])),
+            raw_pattern: args[2].to_owned(),
+        }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for List<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let max_results = config_option(self.server, "list-max-results", 50);
+        let mut matches = 0;
+        for name in Channel::list().unwrap_or(Vec::new()).iter() {
+            if !self.pattern.matches(name) {
+                continue
+            }
+            let chan = match Channel::load(name) {
+                Ok(chan) => chan,
+                Err(_) => continue,
+            };
+            let line = if can_see_details(self.server, self.state, &chan, &self.user) {
+                format!("{} (owner: {}, topic: {})", name, chan.owner, chan.topic)
+            } else {
+                name.clone()
+            };
+            matches += 1;
+            if matches <= max_results {
+                try!(send_reply(self.server, self.state, &self.user, &line));
+            }
+        }
+        let msg = if matches == 0 {
+            format!("No channels match {}.", self.raw_pattern)
+        } else if matches > max_results {
+            format!("End of list - showing {} of {} matches.", max_results, matches)
+        } else {
+            "End of list.".to_owned()
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+pub struct Status<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    channel: String,
+    target: String,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Status<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() != 3 && args.len() != 4 {
+            return Err("Syntax: CS STATUS channel [nick]".to_owned())
+        }
+        Ok(Box::new(Status {
+            server: server,
+            state: state,
+            user: user.to_owned(),
+            channel: args[2].to_owned(),
+            target: if args.len() == 4 { args[3] } else { user }.to_owned(),
+        }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Status<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let msg = if !Channel::exists(&self.channel) {
+            format!("Channel {} is not registered!", &self.channel)
+        } else if let Ok(chan) = Channel::load(&self.channel) {
+            if self.target != self.user &&
+               !can_see_details(self.server, self.state, &chan, &self.user) {
+                format!("You must have access to {} to do that.", &self.channel)
+            } else if let Some(account) = self.state.get_account(&self.target) {
+                let level = effective_access(self.state, &chan, &self.target);
+                format!("{} (account {}) has {} access to {}.", &self.target, account,
+                        level.name(), &self.channel)
+            } else {
+                format!("{} is not identified and has no access to {}.", &self.target,
+                        &self.channel)
+            }
+        } else {
+            format!("Failed to load {} due to an I/O issue.", &self.channel)
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+/// Loads a registered channel unless it is suspended, in which case services leave it alone.
+pub fn load_active(state: &State, channel: &str) -> Option<Channel> {
+    match Channel::load(channel) {
+        Ok(chan) => if chan.active_suspension(state.now()).is_some() { None } else { Some(chan) },
+        Err(_) => None,
+    }
+}
+
+/// Refuses commands that would change or act through a suspended channel.
+pub fn check_suspended(state: &State, command: &str, tokens: &[&str]) -> Option<String> {
+    // Only the positions that name a channel are checked, as free text such as a topic or a
+    // reason may mention any channel.
+    let positions = match command {
+        "INFO" | "LIST" | "STATUS" | "SUSPEND" | "UNSUSPEND" | "FORBID" | "ROLE" => return None,
+        "VOICE" | "DEVOICE" if tokens.len() < 5 => vec![2],
+        "ADMIN" | "OPER" | "VOICE" | "MODE" | "DEADMIN" | "DEOPER" | "DEVOICE" | "CHOWN" => {
+            vec![3]
+        },
+        "CLONE" => vec![2, 3],
+        _ => vec![2],
+    };
+    for name in positions.iter().filter_map(|&i| tokens.get(i)).filter(|t| t.starts_with("#")) {
+        if let Ok(chan) = Channel::load(name) {
+            if let Some(suspension) = chan.active_suspension(state.now()) {
+                return Some(format!("{} is suspended: {}", chan.name, suspension.describe()))
+            }
+        }
+    }
+    None
+}
+
+/// Kicks users out of channels closed by CS FORBID, returning true if it did.
+pub fn enforce_forbid<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>,
+                                                   state: &State, channel: &str,
+                                                   nickname: &str) -> Result<bool> {
+    if nickname == server.config().nickname() || is_services_operator(server, state, nickname) ||
+       !ForbidList::is_forbidden("closed-channels", channel) {
+        return Ok(false)
+    }
+    try!(kick(server, channel, nickname, "This channel has been closed by services."));
+    Ok(true)
+}
+
+/// Reverts any of the given changes to a channel that violate its mode lock.
+pub fn enforce_mlock<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>,
+                                                  state: &State, channel: &str,
+                                                  changes: &[ModeChange]) -> Result<()> {
+    if let Some(Channel { mlock: Some(lock), .. }) = load_active(state, channel) {
+        let reverts = lock.reverts(&state.get_mode_spec(), changes);
+        if !reverts.is_empty() {
+            let (modes, params) = mode::format(&reverts);
+            try!(server.send_samode(channel, &modes, &params.join(" ")));
+        }
+    }
+    Ok(())
+}
+
+/// Removes prefixes given to users without the matching access when SECUREOPS is on.
+pub fn enforce_secureops<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>,
+                                                      state: &State, channel: &str,
+                                                      changes: &[ModeChange]) -> Result<()> {
+    let chan = match load_active(state, channel) {
+        Some(chan) => chan,
+        None => return Ok(()),
+    };
+    if !chan.settings.secureops {
+        return Ok(())
+    }
+    let reverts: Vec<_> = changes.iter().filter(|c| c.adding).filter_map(|c| {
+        let required = match c.mode {
+            'q' => AccessLevel::Owner,
+            'a' => AccessLevel::Admin,
+            'o' | 'h' => AccessLevel::Oper,
+            _ => return None,
+        };
+        match c.param {
+            Some(ref target) if &target[..] != server.config().nickname() &&
+                                effective_access(state, &chan, target) < required => {
+                Some(ModeChange::new(false, c.mode, Some(target)))
+            },
+            _ => None,
+        }
+    }).collect();
+    if !reverts.is_empty() {
+        let (modes, params) = mode::format(&reverts);
+        try!(server.send_samode(channel, &modes, &params.join(" ")));
+    }
+    Ok(())
+}
+
+/// Kicks or bans a user without access from a RESTRICTED channel, returning true if it did.
+/// Registered nicks that haven't identified yet are given a grace period to do so first.
+pub fn enforce_restricted<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>,
+                                                       state: &State, channel: &Channel,
+                                                       nickname: &str) -> Result<bool> {
+    if !is_restricted_from(server, state, channel, nickname) {
+        return Ok(false)
+    }
+    if User::exists(nickname) && !state.is_identified(nickname) {
+        let grace: i64 = config_option(server, "restricted-grace", 30);
+        let task = Task::Restrict(channel.name.clone(), nickname.to_owned());
+        try!(state.cancel_scheduled(&task));
+        try!(state.schedule(state.now() + grace, task));
+        return Ok(false)
+    }
+    try!(remove_restricted(server, state, channel, nickname));
+    Ok(true)
+}
+
+/// Removes a user whose grace period has passed, unless they've since identified to an account
+/// with access.
+pub fn enforce_restricted_grace<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>,
+                                                             state: &State, channel: &str,
+                                                             nickname: &str) -> Result<()> {
+    if let Some(chan) = load_active(state, channel) {
+        if is_restricted_from(server, state, &chan, nickname) {
+            try!(remove_restricted(server, state, &chan, nickname));
+        }
+    }
+    Ok(())
+}
+
+fn is_restricted_from<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>,
+                                                   state: &State, channel: &Channel,
+                                                   nickname: &str) -> bool {
+    channel.settings.restricted != Restriction::Off &&
+        nickname != server.config().nickname() &&
+        !is_services_operator(server, state, nickname) &&
+        effective_access(state, channel, nickname) == AccessLevel::None
+}
+
+fn remove_restricted<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>,
+                                                  state: &State, channel: &Channel,
+                                                  nickname: &str) -> Result<()> {
+    if channel.settings.restricted == Restriction::Ban {
+        let hostmask = state.get_hostmask(nickname);
+        let mask = match hostmask.as_ref().and_then(|h| h.find('@').map(|i| &h[i..])) {
+            Some(host) => format!("*!*{}", host),
+            None => format!("{}!*@*", nickname),
+        };
+        let duration: i64 = config_option(server, "restricted-ban-duration", 3600);
+        let ban = channel::Ban::new(&mask, server.config().nickname(),
+                                    Some("Restricted channel"), state.now(), Some(duration));
+        let mut chan = channel.clone();
+        chan.add_ban(ban.clone());
+        try!(chan.save());
+        let task = Task::Unban(chan.name.clone(), mask.clone());
+        try!(state.cancel_scheduled(&task));
+        if let Some(expires) = ban.expires {
+            try!(state.schedule(expires, task));
+        }
+        try!(server.send_samode(&channel.name, "+b", &mask));
+    }
+    try!(server.send(Message::new(None, "KICK", Some(vec![&channel.name[..], nickname]),
+                                  Some("This channel is restricted to its access list."))));
+    Ok(())
+}
+
+/// Sends the entry message and, if the channel allows it, the user's greet on join.
+pub fn welcome<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>, state: &State,
+                                            channel: &Channel, nickname: &str) -> Result<()> {
+    if nickname == server.config().nickname() {
+        return Ok(())
+    }
+    if let Some(ref message) = channel.settings.entrymsg {
+        try!(server.send_notice(nickname, &format!("[{}] {}", channel.name, message)));
+    }
+    if channel.settings.greet && effective_access(state, channel, nickname) > AccessLevel::None {
+        let account = state.get_account(nickname).unwrap_or(nickname.to_owned());
+        if let Some(greet) = User::load(&account).ok().and_then(|u| u.settings.greet) {
+            try!(server.send_privmsg(&channel.name, &format!("[{}] {}", nickname, greet)));
+        }
+    }
+    Ok(())
+}
+
+/// Runs an in-channel command such as `!kick troll` if the channel has a fantasy prefix set,
+/// returning true if the message was handled.
+pub fn fantasy<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>, user: &str,
+                                            channel: &str, message: &str, state: &'a State)
+    -> Result<bool> {
+    let prefix = match load_active(state, channel).and_then(|chan| chan.settings.fantasy) {
+        Some(prefix) => prefix,
+        None => return Ok(false),
+    };
+    if !message.starts_with(&prefix[..]) {
+        return Ok(false)
+    }
+    let words: Vec<_> = message[prefix.len()..].split(" ").filter(|w| !w.is_empty()).collect();
+    if words.is_empty() {
+        return Ok(false)
+    }
+    let mut tokens = vec!["CS", words[0], channel];
+    tokens.extend(words[1..].iter().cloned());
+    let cmd = upper_case(words[0]);
+    let res = match &cmd[..] {
+        "OP"      => SetStatus::new(server, user, tokens, state, true, 'o'),
+        "DEOP"    => SetStatus::new(server, user, tokens, state, false, 'o'),
+        "VOICE"   => SetStatus::new(server, user, tokens, state, true, 'v'),
+        "DEVOICE" => SetStatus::new(server, user, tokens, state, false, 'v'),
+        "KICK"    => Kick::new(server, user, tokens, state),
+        "BAN"     => Ban::new(server, user, tokens, state),
+        "UNBAN"   => Unban::new(server, user, tokens, state),
+        "INVITE"  => Invite::new(server, user, tokens, state),
+        "QUIET"   => Quiet::new(server, user, tokens, state),
+        "TOPIC"   => Topic::new(server, user, tokens, state),
+        "STATUS"  => Status::new(server, user, tokens, state),
+        "INFO"    => Info::new(server, user, tokens, state),
+        _         => return Ok(false),
+    };
+    match res {
+        Ok(func) => try!(func.do_func()),
+        Err(msg) => try!(send_reply(server, state, user, &msg)),
+    }
+    Ok(true)
+}
+
+pub struct LockModes<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    channel: String,
+    lock: Option<Option<ModeLock>>,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> LockModes<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() < 3 {
+            return Err("Syntax: CS MLOCK channel [modes [params]|OFF]".to_owned())
+        }
+        let lock = if args.len() == 3 {
+            None
+        } else if &upper_case(args[3])[..] == "OFF" {
+            Some(None)
+        } else {
+            let spec = state.get_mode_spec();
+            let changes = mode::parse(&spec, args[3], &args[4..]);
+            Some(Some(try!(ModeLock::from_changes(&spec, &changes))))
+        };
+        Ok(Box::new(LockModes {
+            server: server, state: state, user: user.to_owned(), channel: args[2].to_owned(),
+            lock: lock,
+        }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for LockModes<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let spec = self.state.get_mode_spec();
+        let msg = if !Channel::exists(&self.channel) {
+            format!("Channel {} is not registered!", &self.channel)
+        } else if let Ok(mut chan) = Channel::load(&self.channel) {
+            if effective_access(self.state, &chan, &self.user) < AccessLevel::Admin {
+                format!("You must be an admin of {} to do that.", &self.channel)
+            } else if let Some(ref lock) = self.lock {
+                chan.mlock = lock.clone();
+                try!(chan.save());
+                if let Some(ref lock) = chan.mlock {
+                    let (modes, params) = mode::format(&lock.changes(&spec));
+                    try!(self.server.send_samode(&self.channel, &modes, &params.join(" ")));
+                    format!("Mode lock for {} is now {}.", &self.channel, lock.describe(&spec))
+                } else {
+                    format!("Mode lock for {} has been removed.", &self.channel)
+                }
+            } else if let Some(ref lock) = chan.mlock {
+                format!("Mode lock for {} is {}.", &self.channel, lock.describe(&spec))
+            } else {
+                format!("{} has no mode lock.", &self.channel)
+            }
+        } else {
+            format!("Failed to lock modes on {} due to an I/O issue.", &self.channel)
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+pub struct Set<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    channel: String,
+    setting: Option<ChannelSetting>,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Set<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() < 3 {
+            return Err("Syntax: CS SET channel [option value]".to_owned())
+        }
+        Ok(Box::new(Set {
+            server: server,
+            state: state,
+            user: user.to_owned(),
+            channel: args[2].to_owned(),
+            setting: if args.len() > 3 {
+                Some(try!(ChannelSetting::parse(&args[3..])))
+            } else {
+                None
+            },
+        }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Set<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let msg = if !Channel::exists(&self.channel) {
+            format!("Channel {} is not registered!", &self.channel)
+        } else if let Ok(mut chan) = Channel::load(&self.channel) {
+            if effective_access(self.state, &chan, &self.user) < AccessLevel::Admin {
+                format!("You must be an admin of {} to do that.", &self.channel)
+            } else if let Some(ref setting) = self.setting {
+                chan.settings.apply(setting.clone());
+                try!(chan.save());
+                format!("Settings for {} updated: {}", &self.channel,
+                        chan.settings.display().join(", "))
+            } else {
+                format!("Settings for {}: {}", &self.channel, chan.settings.display().join(", "))
+            }
+        } else {
+            format!("Failed to update settings for {} due to an I/O issue.", &self.channel)
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+pub struct Topic<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    channel: String,
+    topic: String,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Topic<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() < 3 {
+            return Err("Syntax: CS TOPIC channel [topic]".to_owned())
+        }
+        Ok(Box::new(Topic {
+            server: server,
+            state: state,
+            user: user.to_owned(),
+            channel: args[2].to_owned(),
+            topic: args[3..].join(" "),
+        }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Topic<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let msg = if !Channel::exists(&self.channel) {
+            format!("Channel {} is not registered!", &self.channel)
+        } else if let Ok(mut chan) = Channel::load(&self.channel) {
+            if effective_access(self.state, &chan, &self.user) < AccessLevel::Oper {
+                format!("You must be an oper of {} to do that.", &self.channel)
+            } else {
+                chan.topic = self.topic.clone();
+                chan.topic_setter = Some(self.user.clone());
+                chan.topic_time = Some(self.state.now());
+                try!(chan.save());
+                try!(self.server.send_topic(&self.channel, &self.topic));
+                format!("Topic of {} updated.", &self.channel)
+            }
+        } else {
+            format!("Failed to set the topic of {} due to an I/O issue.", &self.channel)
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+/// Loads a channel, failing with a message unless the user holds at least `required` access.
+fn load_with_access(state: &State, channel: &str, user: &str, required: AccessLevel)
+    -> BotResult<Channel> {
+    if !Channel::exists(channel) {
+        return Err(format!("Channel {} is not registered!", channel))
+    }
+    let chan = try!(Channel::load(channel).map_err(|_| {
+        format!("Failed to load {} due to an I/O issue.", channel)
+    }));
+    if effective_access(state, &chan, user) < required {
+        Err(format!("You need {} access on {} to do that.", required.name(), channel))
+    } else {
+        Ok(chan)
+    }
+}
+
+/// Checks that the user outranks the target, so that staff can't act against their superiors.
+fn outranks(state: &State, chan: &Channel, user: &str, target: &str) -> BotResult<()> {
+    let level = effective_access(state, chan, user);
+    if level != AccessLevel::Owner && effective_access(state, chan, target) >= level {
+        Err(format!("You cannot do that to {}.", target))
+    } else {
+        Ok(())
+    }
+}
+
+/// Turns a nickname into a host ban mask, leaving anything that looks like a mask untouched.
+fn ban_mask(state: &State, target: &str) -> String {
+    if target.contains('!') || target.contains('@') {
+        return target.to_owned()
+    }
+    let hostmask = state.get_hostmask(target);
+    match hostmask.as_ref().and_then(|h| h.find('@').map(|i| &h[i..])) {
+        Some(host) => format!("*!*{}", host),
+        None => format!("{}!*@*", target),
+    }
+}
+
+fn kick<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>, channel: &str,
+                                     target: &str, reason: &str) -> Result<()> {
+    server.send(Message::new(None, "KICK", Some(vec![channel, target]), Some(reason)))
+}
+
+pub struct Kick<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    channel: String,
+    target: String,
+    reason: Option<String>,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Kick<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() < 4 {
+            return Err("Syntax: CS KICK channel nick [reason]".to_owned())
+        }
+        Ok(Box::new(Kick {
+            server: server,
+            state: state,
+            user: user.to_owned(),
+            channel: args[2].to_owned(),
+            target: args[3].to_owned(),
+            reason: if args.len() > 4 { Some(args[4..].join(" ")) } else { None },
+        }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Kick<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let checked = load_with_access(self.state, &self.channel, &self.user, AccessLevel::Oper)
+                          .and_then(|chan| outranks(self.state, &chan, &self.user, &self.target));
+        let msg = match checked {
+            Ok(()) => {
+                let reason = match self.reason {
+                    Some(ref reason) => format!("{} ({})", reason, self.user),
+                    None => format!("Requested by {}", self.user),
+                };
+                try!(kick(self.server, &self.channel, &self.target, &reason));
+                format!("Kicked {} from {}.", &self.target, &self.channel)
+            },
+            Err(msg) => msg,
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+pub struct Ban<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    channel: String,
+    target: String,
+    duration: Option<i64>,
+    reason: Option<String>,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Ban<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() < 4 {
+            return Err("Syntax: CS BAN channel nick|mask [+duration] [reason]".to_owned())
+        }
+        let (duration, reason_start) = if args.len() > 4 && args[4].starts_with("+") {
+            (try!(parse_duration(&args[4][1..])), 5)
+        } else {
+            (None, 4)
+        };
+        Ok(Box::new(Ban {
+            server: server,
+            state: state,
+            user: user.to_owned(),
+            channel: args[2].to_owned(),
+            target: args[3].to_owned(),
+            duration: duration,
+            reason: if args.len() > reason_start {
+                Some(args[reason_start..].join(" "))
+            } else {
+                None
+            },
+        }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Ban<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let is_nick = !self.target.contains('!') && !self.target.contains('@');
+        let checked = load_with_access(self.state, &self.channel, &self.user, AccessLevel::Oper)
+                          .and_then(|chan| if is_nick {
+                              outranks(self.state, &chan, &self.user, &self.target).map(|_| chan)
+                          } else {
+                              Ok(chan)
+                          });
+        let msg = match checked {
+            Ok(mut chan) => {
+                let mask = ban_mask(self.state, &self.target);
+                let reason = self.reason.as_ref().map(|r| &r[..]);
+                let ban = channel::Ban::new(&mask, &self.user, reason, self.state.now(),
+                                            self.duration);
+                chan.add_ban(ban.clone());
+                try!(chan.save());
+                let task = Task::Unban(self.channel.clone(), mask.clone());
+                try!(self.state.cancel_scheduled(&task));
+                if let Some(expires) = ban.expires {
+                    try!(self.state.schedule(expires, task));
+                }
+                try!(self.server.send_samode(&self.channel, "+b", &mask));
+                if is_nick {
+                    let reason = format!("{} ({})", reason.unwrap_or("Banned"), self.user);
+                    try!(kick(self.server, &self.channel, &self.target, &reason));
+                }
+                match ban.expires {
+                    Some(expires) => format!("Banned {} from {} until {}.", mask, &self.channel,
+                                             format_time(expires)),
+                    None => format!("Banned {} from {}.", mask, &self.channel),
+                }
+            },
+            Err(msg) => msg,
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+pub struct Unban<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    channel: String,
+    mask: Option<String>,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Unban<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() != 3 && args.len() != 4 {
+            return Err("Syntax: CS UNBAN channel [mask]".to_owned())
+        }
+        Ok(Box::new(Unban {
+            server: server,
+            state: state,
+            user: user.to_owned(),
+            channel: args[2].to_owned(),
+            mask: if args.len() == 4 { Some(args[3].to_owned()) } else { None },
+        }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Unban<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let required = if self.mask.is_some() { AccessLevel::Oper } else { AccessLevel::Voice };
+        let msg = match load_with_access(self.state, &self.channel, &self.user, required) {
+            Ok(mut chan) => {
+                let masks = match self.mask {
+                    Some(ref mask) => {
+                        chan.remove_ban(mask);
+                        vec![mask.clone()]
+                    },
+                    None => {
+                        let hostmask = self.state.get_hostmask(&self.user).unwrap_or(String::new());
+                        let full = format!("{}!{}", self.user, hostmask);
+                        chan.remove_bans_matching(&full).into_iter().map(|b| b.mask).collect()
+                    },
+                };
+                try!(chan.save());
+                for mask in masks.iter() {
+                    try!(self.state.cancel_scheduled(&Task::Unban(self.channel.clone(),
+                                                                  mask.clone())));
+                    try!(self.server.send_samode(&self.channel, "-b", mask));
+                }
+                match (&self.mask, masks.len()) {
+                    (&Some(ref mask), _) => {
+                        format!("Removed the ban on {} from {}.", mask, &self.channel)
+                    },
+                    (&None, 0) => {
+                        format!("No bans set through services match you on {}.", &self.channel)
+                    },
+                    (&None, 1) => {
+                        format!("Removed 1 ban set through services from {}.", &self.channel)
+                    },
+                    (&None, n) => {
+                        format!("Removed {} bans set through services from {}.", n, &self.channel)
+                    },
+                }
+            },
+            Err(msg) => msg,
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+pub struct Invite<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    channel: String,
+    target: String,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Invite<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() != 3 && args.len() != 4 {
+            return Err("Syntax: CS INVITE channel [nick]".to_owned())
+        }
+        Ok(Box::new(Invite {
+            server: server,
+            state: state,
+            user: user.to_owned(),
+            channel: args[2].to_owned(),
+            target: if args.len() == 4 { args[3] } else { user }.to_owned(),
+        }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Invite<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let required = if self.target == self.user {
+            AccessLevel::Voice
+        } else {
+            AccessLevel::Oper
+        };
+        let msg = match load_with_access(self.state, &self.channel, &self.user, required) {
+            Ok(_) => {
+                try!(self.server.send(Message::new(None, "INVITE",
+                     Some(vec![&self.target[..], &self.channel[..]]), None)));
+                format!("Invited {} to {}.", &self.target, &self.channel)
+            },
+            Err(msg) => msg,
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+pub struct Quiet<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    channel: String,
+    target: String,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Quiet<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() != 4 {
+            return Err("Syntax: CS QUIET channel nick|mask".to_owned())
+        }
+        Ok(Box::new(Quiet {
+            server: server,
+            state: state,
+            user: user.to_owned(),
+            channel: args[2].to_owned(),
+            target: args[3].to_owned(),
+        }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Quiet<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let is_nick = !self.target.contains('!') && !self.target.contains('@');
+        let checked = load_with_access(self.state, &self.channel, &self.user, AccessLevel::Oper)
+                          .and_then(|chan| if is_nick {
+                              outranks(self.state, &chan, &self.user, &self.target)
+                          } else {
+                              Ok(())
+                          });
+        let msg = match checked {
+            Ok(()) => {
+                let prefix: String = config_option(self.server, "quiet-extban", "~q:".to_owned());
+                let mask = format!("{}{}", prefix, ban_mask(self.state, &self.target));
+                try!(self.server.send_samode(&self.channel, "+b", &mask));
+                format!("Quieted {} on {}.", mask, &self.channel)
+            },
+            Err(msg) => msg,
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+/// Momentarily gives or takes a status mode, without touching the access lists.
+pub struct SetStatus<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    channel: String,
+    target: String,
+    change: ModeChange,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> SetStatus<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State,
+               adding: bool, mode: char) -> BotResult<Box<Functionality + 'a>> {
+        if args.len() != 3 && args.len() != 4 {
+            return Err(format!("Syntax: CS {} channel [nick]", upper_case(args[1])))
+        }
+        let target = if args.len() == 4 { args[3] } else { user };
+        Ok(Box::new(SetStatus {
+            server: server,
+            state: state,
+            user: user.to_owned(),
+            channel: args[2].to_owned(),
+            target: target.to_owned(),
+            change: ModeChange::new(adding, mode, Some(target)),
+        }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for SetStatus<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let required = if self.change.mode == 'v' && self.target == self.user {
+            AccessLevel::Voice
+        } else {
+            AccessLevel::Oper
+        };
+        let checked = load_with_access(self.state, &self.channel, &self.user, required)
+                          .and_then(|chan| if self.change.adding || self.target == self.user {
+                              Ok(())
+                          } else {
+                              outranks(self.state, &chan, &self.user, &self.target)
+                          });
+        let msg = match checked {
+            Ok(()) => {
+                let (modes, params) = mode::format(&[self.change.clone()]);
+                try!(self.server.send_samode(&self.channel, &modes, &params.join(" ")));
+                format!("Set {} {} on {}.", modes, &self.target, &self.channel)
+            },
+            Err(msg) => msg,
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+pub struct Suspend<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    channel: String,
+    duration: Option<i64>,
+    reason: String,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Suspend<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() < 5 {
+            return Err("Syntax: CS SUSPEND channel duration reason".to_owned())
+        }
+        Ok(Box::new(Suspend {
+            server: server,
+            state: state,
+            user: user.to_owned(),
+            channel: args[2].to_owned(),
+            duration: try!(parse_duration(args[3])),
+            reason: args[4..].join(" "),
+        }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Suspend<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let msg = if !is_services_operator(self.server, self.state, &self.user) {
+            "You must be a services operator to do that.".to_owned()
+        } else if !Channel::exists(&self.channel) {
+            format!("Channel {} is not registered!", &self.channel)
+        } else if let Ok(mut chan) = Channel::load(&self.channel) {
+            let suspension = Suspension::new(&self.user, &self.reason, self.state.now(),
+                                             self.duration);
+            let description = suspension.describe();
+            chan.suspension = Some(suspension);
+            try!(chan.save());
+            try!(log_event(self.server,
+                 &format!("{} suspended {}: {}", self.user, self.channel, description)));
+            format!("{} has been suspended.", self.channel)
+        } else {
+            format!("Failed to suspend {} due to an I/O issue.", &self.channel)
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+pub struct Unsuspend<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    channel: String,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Unsuspend<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() != 3 {
+            return Err("Syntax: CS UNSUSPEND channel".to_owned())
+        }
+        Ok(Box::new(Unsuspend {
+            server: server, state: state, user: user.to_owned(), channel: args[2].to_owned()
+        }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Unsuspend<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let msg = if !is_services_operator(self.server, self.state, &self.user) {
+            "You must be a services operator to do that.".to_owned()
+        } else if let Ok(mut chan) = Channel::load(&self.channel) {
+            if chan.suspension.take().is_some() {
+                try!(chan.save());
+                try!(log_event(self.server,
+                     &format!("{} unsuspended {}.", self.user, self.channel)));
+                format!("{} is no longer suspended.", self.channel)
+            } else {
+                format!("{} is not suspended.", self.channel)
+            }
+        } else {
+            format!("Channel {} is not registered!", &self.channel)
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+enum ForbidAction {
+    Add(String, bool),
+    Del(String),
+    List,
+}
+
+pub struct Forbid<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    action: ForbidAction,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Forbid<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        let last = args.last().map(|s| upper_case(s));
+        let action = match (args.len(), last) {
+            (3, Some(ref action)) if &action[..] == "LIST" => ForbidAction::List,
+            (4, _) if &upper_case(args[2])[..] == "DEL" => ForbidAction::Del(args[3].to_owned()),
+            (3, _) => ForbidAction::Add(args[2].to_owned(), false),
+            (4, Some(ref flag)) if &flag[..] == "CLOSE" => {
+                ForbidAction::Add(args[2].to_owned(), true)
+            },
+            _ => return Err("Syntax: CS FORBID pattern [CLOSE] | DEL pattern | LIST".to_owned()),
+        };
+        Ok(Box::new(Forbid { server: server, state: state, user: user.to_owned(), action: action }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Forbid<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let lists = (ForbidList::load("channels"), ForbidList::load("closed-channels"));
+        let msg = if !is_services_operator(self.server, self.state, &self.user) {
+            "You must be a services operator to do that.".to_owned()
+        } else if let (Ok(mut list), Ok(mut closed)) = lists {
+            match self.action {
+                ForbidAction::List if list.patterns.is_empty() => {
+                    "No channels are forbidden.".to_owned()
+                },
+                ForbidAction::List => {
+                    let patterns: Vec<_> = list.patterns.iter().map(|p| {
+                        if closed.patterns.contains(p) { format!("{} (closed)", p) }
+                        else { p.clone() }
+                    }).collect();
+                    format!("Forbidden channels: {}", patterns.join(", "))
+                },
+                ForbidAction::Add(ref pattern, close) => {
+                    let added = list.add(pattern);
+                    if !(close && closed.add(pattern)) && !added {
+                        format!("{} is already forbidden.", pattern)
+                    } else {
+                        try!(list.save());
+                        try!(closed.save());
+                        let what = if close { "forbade and closed" } else { "forbade" };
+                        try!(log_event(self.server,
+                             &format!("{} {} channels matching {}.", self.user, what, pattern)));
+                        format!("Channels matching {} are now forbidden.", pattern)
+                    }
+                },
+                ForbidAction::Del(ref pattern) if list.remove(pattern) | closed.remove(pattern) => {
+                    try!(list.save());
+                    try!(closed.save());
+                    try!(log_event(self.server,
+                         &format!("{} removed the forbid on {}.", self.user, pattern)));
+                    format!("Channels matching {} are no longer forbidden.", pattern)
+                },
+                ForbidAction::Del(ref pattern) => format!("{} is not forbidden.", pattern),
+            }
+        } else {
+            "Failed to update forbidden channels due to an I/O issue.".to_owned()
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+pub struct CloneChannel<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    source: String,
+    target: String,
+    what: String,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> CloneChannel<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        let what = args.get(4).map_or("ALL".to_owned(), |w| upper_case(w));
+        match (args.len(), &what[..]) {
+            (4, _) | (5, "ACCESS") | (5, "AKICK") | (5, "SETTINGS") | (5, "ALL") => (),
+            _ => {
+                return Err("Syntax: CS CLONE source target [ACCESS|AKICK|SETTINGS|ALL]".to_owned())
+            },
+        }
+        Ok(Box::new(CloneChannel {
+            server: server,
+            state: state,
+            user: user.to_owned(),
+            source: args[2].to_owned(),
+            target: args[3].to_owned(),
+            what: what,
+        }))
+    }
+
+    fn copies(&self, what: &str) -> bool {
+        &self.what[..] == what || &self.what[..] == "ALL"
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for CloneChannel<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let checked = load_with_access(self.state, &self.source, &self.user, AccessLevel::Admin)
+                          .and_then(|src| {
+                              load_with_access(self.state, &self.target, &self.user,
+                                               AccessLevel::Owner).map(|dst| (src, dst))
+                          });
+        let msg = match checked {
+            Ok((src, mut dst)) => {
+                if self.copies("ACCESS") {
+                    dst.admins = src.admins.clone();
+                    dst.opers = src.opers.clone();
+                    dst.voice = src.voice.clone();
+                    dst.roles = src.roles.clone();
+                }
+                if self.copies("AKICK") {
+                    for ban in src.bans().iter() {
+                        dst.add_ban(ban.clone());
+                        try!(self.server.send_samode(&self.target, "+b", &ban.mask));
+                        if let Some(expires) = ban.expires {
+                            let task = Task::Unban(self.target.clone(), ban.mask.clone());
+                            try!(self.state.cancel_scheduled(&task));
+                            try!(self.state.schedule(expires, task));
+                        }
+                    }
+                }
+                if self.copies("SETTINGS") {
+                    dst.settings = src.settings.clone();
+                    dst.mlock = src.mlock.clone();
+                    if let Some(ref lock) = dst.mlock {
+                        let spec = self.state.get_mode_spec();
+                        let (modes, params) = mode::format(&lock.changes(&spec));
+                        try!(self.server.send_samode(&self.target, &modes, &params.join(" ")));
+                    }
+                }
+                try!(dst.save());
+                let what = match &self.what[..] {
+                    "ACCESS" => "the access list",
+                    "AKICK" => "the ban list",
+                    "SETTINGS" => "the settings",
+                    _ => "the access list, ban list and settings",
+                };
+                format!("Copied {} of {} to {}.", what, &self.source, &self.target)
+            },
+            Err(msg) => msg,
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+enum RoleAction {
+    Set(String, AccessLevel),
+    Del(String),
+    List,
+}
+
+pub struct Roles<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    action: RoleAction,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Roles<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        let action = args.get(2).map_or(String::new(), |a| upper_case(a));
+        let action = match (args.len(), &action[..]) {
+            (3, "LIST") => RoleAction::List,
+            (4, "DEL") => RoleAction::Del(args[3].to_owned()),
+            (5, "SET") => RoleAction::Set(args[3].to_owned(), try!(Role::parse_level(args[4]))),
+            _ => return Err("Syntax: CS ROLE SET name level | DEL name | LIST".to_owned()),
+        };
+        match action {
+            RoleAction::Set(ref name, _) | RoleAction::Del(ref name)
+                if !is_valid_role_name(name) => {
+                return Err(format!("{} is not a valid role name.", name))
+            },
+            _ => (),
+        }
+        Ok(Box::new(Roles { server: server, state: state, user: user.to_owned(), action: action }))
+    }
+
+    fn list(&self) -> Result<String> {
+        let names = Role::list().unwrap_or(Vec::new());
+        for name in names.iter() {
+            if let Ok(role) = Role::load(name) {
+                try!(send_reply(self.server, self.state, &self.user, &format!(
+                    "{}: {} access (owned by {})", role.name, role.level.name(), role.owner
+                )));
+            }
+        }
+        Ok(if names.is_empty() { "No roles are defined.".to_owned() }
+           else { "End of list.".to_owned() })
+    }
+
+    /// Removes a deleted role from every channel that assigned it.
+    fn unassign_everywhere(&self, name: &str) -> Result<()> {
+        for channel in Channel::list().unwrap_or(Vec::new()).iter() {
+            if let Ok(mut chan) = Channel::load(channel) {
+                if chan.unassign_role(name) {
+                    try!(chan.save());
+                }
+            }
         }
-        Ok(Box::new(DeVoice {
-            server: server,
-            state: state,
-            owner: user.to_owned(),
-            channel: args[3].to_owned(),
-            password: args[4].to_owned(),
-            target: args[2].to_owned(),
-        }))
+        Ok(())
     }
 }
 
-impl<'a, T: IrcRead, U: IrcWrite> Functionality for DeVoice<'a, T, U> {
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Roles<'a, T, U> {
     fn do_func(&self) -> Result<()> {
-        let msg = if !self.state.is_identified(&self.owner) {
-            format!("You must be identify as {} to do that.", &self.owner)
-        } else if !self.state.is_identified(&self.target) {
-            format!("{} must be identified to do that.", &self.target)
-        } else if !Channel::exists(&self.channel) {
-            format!("Channel {} is not registered!", &self.channel)
-        } else if let Ok(mut chan) = Channel::load(&self.channel) {
-            if try!(chan.is_password(&self.password)) {
-                chan.voice.retain(|u| u != &self.target);
-                try!(chan.save());
-                try!(self.server.send_samode(&self.channel, "-v", &self.target));
-                format!("{} is no longer voiced.", &self.target)
-            } else {
-                format!("Password incorrect.")
-            }
-        } else {
-            format!("Failed to de-voice {} due to an I/O issue.", &self.target)
+        // Roles apply to every channel that assigns them, so only services operators may
+        // define them; otherwise a role's owner could raise the access it grants everywhere.
+        let msg = match self.action {
+            RoleAction::List => try!(self.list()),
+            _ if !is_services_operator(self.server, self.state, &self.user) => {
+                "You must be a services operator to do that.".to_owned()
+            },
+            RoleAction::Set(ref name, level) => {
+                let account = self.state.get_account(&self.user).unwrap_or(self.user.clone());
+                let owner = Role::load(name).map(|r| r.owner).unwrap_or(account);
+                try!(Role::new(name, &owner, level).save());
+                format!("Role {} now grants {} access.", name, level.name())
+            },
+            RoleAction::Del(ref name) if Role::exists(name) => {
+                try!(Role::delete(name));
+                try!(self.unassign_everywhere(name));
+                format!("Role {} has been deleted.", name)
+            },
+            RoleAction::Del(ref name) => format!("Role {} does not exist.", name),
         };
-        self.server.send_notice(&self.owner, &msg)
+        send_reply(self.server, self.state, &self.user, &msg)
     }
 }
 
-pub struct ChangeOwner<'a, T: IrcRead, U: IrcWrite> {
+pub struct Assign<'a, T: IrcRead, U: IrcWrite> {
     server: &'a ServerExt<'a, T, U>,
     state: &'a State,
-    owner: String,
+    user: String,
     channel: String,
-    password: String,
-    target: String,
+    account: String,
+    role: Option<String>,
 }
 
-impl<'a, T: IrcRead, U: IrcWrite> ChangeOwner<'a, T, U> {
+impl<'a, T: IrcRead, U: IrcWrite> Assign<'a, T, U> {
     pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
         -> BotResult<Box<Functionality + 'a>> {
         if args.len() != 5 {
-            return Err("Syntax: CS CHOWN user channel password".to_owned())
+            return Err("Syntax: CS ASSIGN channel account role|OFF".to_owned())
+        } else if &upper_case(args[4])[..] != "OFF" && !is_valid_role_name(args[4]) {
+            return Err(format!("{} is not a valid role name.", args[4]))
         }
-        Ok(Box::new(ChangeOwner {
+        Ok(Box::new(Assign {
             server: server,
             state: state,
-            owner: user.to_owned(),
-            channel: args[3].to_owned(),
-            password: args[4].to_owned(),
-            target: args[2].to_owned(),
+            user: user.to_owned(),
+            channel: args[2].to_owned(),
+            account: args[3].to_owned(),
+            role: if &upper_case(args[4])[..] == "OFF" { None } else { Some(args[4].to_owned()) },
         }))
     }
 }
 
-impl<'a, T: IrcRead, U: IrcWrite> Functionality for ChangeOwner<'a, T, U> {
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Assign<'a, T, U> {
     fn do_func(&self) -> Result<()> {
-        let msg = if !self.state.is_identified(&self.owner) {
-            format!("You must be identify as {} to do that.", &self.owner)
-        } else if !self.state.is_identified(&self.target) && &self.target[..] != "Pidgey" {
-            format!("{} must be identified to do that.", &self.target)
-        } else if !Channel::exists(&self.channel) {
-            format!("Channel {} is not registered!", &self.channel)
-        } else if let Ok(mut chan) = Channel::load(&self.channel) {
-            if try!(chan.is_password(&self.password)) {
-                chan.owner = self.target.clone();
-                try!(chan.save());
-                try!(self.server.send_samode(&self.channel, "+q", &self.target));
-                format!("{} is now the channel owner.", &self.target)
-            } else {
-                format!("Password incorrect.")
-            }
-        } else {
-            format!("Failed to change owner to {} due to an I/O issue.", &self.target)
+        let checked = load_with_access(self.state, &self.channel, &self.user, AccessLevel::Admin);
+        let msg = match (checked, self.role.as_ref().map(|r| Role::load(r))) {
+            (Err(msg), _) => msg,
+            (_, Some(Err(_))) => {
+                format!("Role {} does not exist.", self.role.as_ref().unwrap())
+            },
+            (Ok(mut chan), role) => {
+                let level = effective_access(self.state, &chan, &self.user);
+                match role {
+                    Some(Ok(ref role)) if level != AccessLevel::Owner && role.level >= level => {
+                        format!("You cannot assign {} in {}.", role.name, &self.channel)
+                    },
+                    Some(Ok(ref role)) => {
+                        chan.assign_role(&self.account, Some(&role.name[..]));
+                        try!(chan.save());
+                        format!("{} now has the {} role in {}.", &self.account, role.name,
+                                &self.channel)
+                    },
+                    _ => {
+                        chan.assign_role(&self.account, None);
+                        try!(chan.save());
+                        format!("{} no longer has a role in {}.", &self.account, &self.channel)
+                    },
+                }
+            },
         };
-        self.server.send_notice(&self.owner, &msg)
+        send_reply(self.server, self.state, &self.user, &msg)
     }
 }
 
@@ -455,17 +1861,24 @@ mod test {
     use std::borrow::ToOwned;
     use std::fs::remove_file;
     use std::path::Path;
-    use data::channel::Channel;
-    use func::test::test_helper;
+    use data::channel::{Ban, Channel};
+    use data::scheduler::FixedClock;
+    use data::role::Role;
+    use data::suspension::Suspension;
+    use data::user::User;
+    use func::test::{test_helper, test_helper_with_options};
 
     #[test]
     fn register_succeeded() {
         let _ = remove_file(Path::new("data/chanserv/#test4.json"));
         let (data, _) = test_helper(
-            ":test2!test@test PRIVMSG test :CS REGISTER #test4 test\r\n", |state| {
+            ":test2!test@test PRIVMSG test :CS REGISTER #test4 test\r\n\
+             :test.net 353 test = #test4 :@test2\r\n\
+             :test.net 366 test #test4 :End of /NAMES list.\r\n", |state| {
             state.identify("test2");
         });
-        let exp = "SAMODE #test4 +r\r\n\
+        let exp = "NAMES #test4\r\n\
+                   SAMODE #test4 +r\r\n\
                    SAMODE #test4 +qa test2\r\n\
                    JOIN #test4\r\n\
                    SAMODE #test4 +a test\r\n\
@@ -474,6 +1887,88 @@ mod test {
         assert_eq!(&data[..], exp);
     }
 
+    #[test]
+    fn register_after_names() {
+        let _ = remove_file(Path::new("data/chanserv/#test49.json"));
+        let (data, _) = test_helper(
+            ":test105!test@test PRIVMSG test :CS REGISTER #test49 test\r\n\
+             :test.net 353 test = #test49 :test @test105 +test106\r\n\
+             :test.net 366 test #test49 :End of /NAMES list.\r\n", |state| {
+            state.identify("test105");
+        });
+        let exp = "NAMES #test49\r\n\
+                   SAMODE #test49 +r\r\n\
+                   SAMODE #test49 +qa test105\r\n\
+                   JOIN #test49\r\n\
+                   SAMODE #test49 +a test\r\n\
+                   NOTICE test105 :Channel #test49 has been registered. \
+                   Don't forget the password!\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn register_failed_not_op() {
+        let _ = remove_file(Path::new("data/chanserv/#test50.json"));
+        let (data, _) = test_helper(
+            ":test.net 353 test = #test50 :test @test106\r\n\
+             :test106!test@test PRIVMSG test :CS REGISTER #test50 test\r\n\
+             :test.net 353 test = #test50 :test @test105 +test106\r\n\
+             :test.net 366 test #test50 :End of /NAMES list.\r\n", |state| {
+            state.identify("test106");
+        });
+        let exp = "NAMES #test50\r\n\
+                   NOTICE test106 :You must be a channel operator in #test50 to register it.\r\n";
+        assert_eq!(&data[..], exp);
+        assert!(!Channel::exists("#test50"));
+    }
+
+    #[test]
+    fn register_as_admin() {
+        let _ = remove_file(Path::new("data/chanserv/#test65.json"));
+        let (data, _) = test_helper(
+            ":test123!test@test PRIVMSG test :CS REGISTER #test65 test\r\n\
+             :test.net 353 test = #test65 :test &test123\r\n\
+             :test.net 366 test #test65 :End of /NAMES list.\r\n", |state| {
+            state.identify("test123");
+        });
+        let exp = "NAMES #test65\r\n\
+                   SAMODE #test65 +r\r\n\
+                   SAMODE #test65 +qa test123\r\n\
+                   JOIN #test65\r\n\
+                   SAMODE #test65 +a test\r\n\
+                   NOTICE test123 :Channel #test65 has been registered. \
+                   Don't forget the password!\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn register_failed_limit() {
+        Channel::new("#test51", "test", "test107").unwrap().save().unwrap();
+        let _ = remove_file(Path::new("data/chanserv/#test52.json"));
+        let (data, _) = test_helper_with_options(
+            ":test107!test@test PRIVMSG test :CS REGISTER #test52 test\r\n",
+            &[("channel-limit", "1")], |state| {
+            state.identify("test107");
+        });
+        assert_eq!(&data[..], "NOTICE test107 :You may not register more than 1 channels.\r\n");
+    }
+
+    #[test]
+    fn register_failed_account_age() {
+        let mut u = User::new("test108", "test", None).unwrap();
+        u.registered = Some(0);
+        u.save().unwrap();
+        let (data, _) = test_helper_with_options(
+            ":test108!test@test PRIVMSG test :CS REGISTER #test53 test\r\n",
+            &[("channel-min-age", "7d")], |state| {
+            state.identify("test108");
+            state.set_clock(Box::new(FixedClock(60)));
+        });
+        let exp = "NOTICE test108 :Your account must be registered for at least 7d before \
+                   registering a channel.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
     #[test]
     fn register_failed_not_identified() {
         let (data, _) = test_helper(
@@ -917,4 +2412,388 @@ mod test {
         });
         assert_eq!(&data[..], "NOTICE test :Password incorrect.\r\n");
     }
+
+    #[test]
+    fn info() {
+        let mut ch = Channel::new("#test31", "test", "test71").unwrap();
+        ch.opers.push("test72".to_owned());
+        ch.topic = "Welcome!".to_owned();
+        ch.save().unwrap();
+        let (data, _) = test_helper(
+            ":test72!test@test PRIVMSG test :CS INFO #test31\r\n", |state| {
+            state.identify("test72");
+        });
+        let exp = "NOTICE test72 :#test31 is owned by test71.\r\n\
+                   NOTICE test72 :Admins: none\r\n\
+                   NOTICE test72 :Opers: test72\r\n\
+                   NOTICE test72 :Voice: none\r\n\
+                   NOTICE test72 :Topic: Welcome!\r\n\
+                   NOTICE test72 :Mode: \r\n\
+                   NOTICE test72 :End of info.\r\n";
+        assert_eq!(&data[..], exp);
+        let (data, _) = test_helper(
+            ":test73!test@test PRIVMSG test :CS INFO #test31\r\n", |state| {
+            state.identify("test73");
+        });
+        let exp = "NOTICE test73 :#test31 is owned by test71.\r\n\
+                   NOTICE test73 :End of info.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn list() {
+        let mut ch = Channel::new("#test32", "test", "test74").unwrap();
+        ch.topic = "Secret plans".to_owned();
+        ch.save().unwrap();
+        let (data, _) = test_helper(
+            ":test74!test@test PRIVMSG test :CS LIST #test32\r\n", |state| {
+            state.identify("test74");
+        });
+        let exp = "NOTICE test74 :#test32 (owner: test74, topic: Secret plans)\r\n\
+                   NOTICE test74 :End of list.\r\n";
+        assert_eq!(&data[..], exp);
+        let (data, _) = test_helper(
+            ":test75!test@test PRIVMSG test :CS LIST #test32\r\n", |_| {}
+        );
+        assert_eq!(&data[..], "NOTICE test75 :#test32\r\nNOTICE test75 :End of list.\r\n");
+    }
+
+    #[test]
+    fn status() {
+        let mut ch = Channel::new("#test33", "test", "test76").unwrap();
+        ch.voice.push("test77".to_owned());
+        ch.save().unwrap();
+        let (data, _) = test_helper(
+            ":test76!test@test PRIVMSG test :CS STATUS #test33 test77\r\n", |state| {
+            state.identify("test76");
+            state.identify("test77");
+        });
+        assert_eq!(&data[..], "NOTICE test76 :test77 (account test77) has voice access to \
+                               #test33.\r\n");
+        let (data, _) = test_helper(
+            ":test78!test@test PRIVMSG test :CS STATUS #test33\r\n", |state| {
+            state.identify("test78");
+        });
+        assert_eq!(&data[..], "NOTICE test78 :test78 (account test78) has no access to \
+                               #test33.\r\n");
+    }
+
+    #[test]
+    fn status_failed_not_member() {
+        let ch = Channel::new("#test34", "test", "test79").unwrap();
+        ch.save().unwrap();
+        let (data, _) = test_helper(
+            ":test80!test@test PRIVMSG test :CS STATUS #test34 test79\r\n", |state| {
+            state.identify("test80");
+        });
+        assert_eq!(&data[..], "NOTICE test80 :You must have access to #test34 to do that.\r\n");
+    }
+
+    #[test]
+    fn mlock() {
+        let ch = Channel::new("#test35", "test", "test81").unwrap();
+        ch.save().unwrap();
+        let (data, _) = test_helper(
+            ":test81!test@test PRIVMSG test :CS MLOCK #test35 +ntl-s 10\r\n", |state| {
+            state.identify("test81");
+        });
+        let exp = "SAMODE #test35 +ntl-s 10\r\n\
+                   NOTICE test81 :Mode lock for #test35 is now +ntl-s 10.\r\n";
+        assert_eq!(&data[..], exp);
+        assert_eq!(Channel::load("#test35").unwrap().mlock.unwrap().limit, Some(10));
+        let (data, _) = test_helper(
+            ":test82!test@test PRIVMSG test :CS MLOCK #test35 OFF\r\n", |state| {
+            state.identify("test82");
+        });
+        assert_eq!(&data[..], "NOTICE test82 :You must be an admin of #test35 to do that.\r\n");
+    }
+
+    #[test]
+    fn mlock_failed_list_mode() {
+        let (data, _) = test_helper(
+            ":test81!test@test PRIVMSG test :CS MLOCK #test35 +b *!*@*\r\n", |_| {}
+        );
+        assert_eq!(&data[..], "NOTICE test81 :Mode b cannot be locked.\r\n");
+    }
+
+    #[test]
+    fn set_and_topic() {
+        let ch = Channel::new("#test39", "test", "test86").unwrap();
+        ch.save().unwrap();
+        let (data, _) = test_helper(
+            ":test86!test@test PRIVMSG test :CS SET #test39 TOPICLOCK ON\r\n", |state| {
+            state.identify("test86");
+        });
+        assert_eq!(&data[..], "NOTICE test86 :Settings for #test39 updated: KEEPTOPIC ON, \
+                               TOPICLOCK ON, SECUREOPS OFF, RESTRICTED OFF, GREET OFF, \
+                               FANTASY OFF\r\n");
+        let (data, _) = test_helper(
+            ":test86!test@test PRIVMSG test :CS TOPIC #test39 Hello world\r\n", |state| {
+            state.identify("test86");
+        });
+        let exp = "TOPIC #test39 :Hello world\r\n\
+                   NOTICE test86 :Topic of #test39 updated.\r\n";
+        assert_eq!(&data[..], exp);
+        let ch = Channel::load("#test39").unwrap();
+        assert!(ch.settings.topiclock);
+        assert_eq!(&ch.topic[..], "Hello world");
+        assert_eq!(ch.topic_setter, Some("test86".to_owned()));
+    }
+
+    #[test]
+    fn topic_failed_no_access() {
+        let ch = Channel::new("#test40", "test", "test87").unwrap();
+        ch.save().unwrap();
+        let (data, _) = test_helper(
+            ":test88!test@test PRIVMSG test :CS TOPIC #test40 Hijacked\r\n", |state| {
+            state.identify("test88");
+        });
+        assert_eq!(&data[..], "NOTICE test88 :You must be an oper of #test40 to do that.\r\n");
+    }
+
+    #[test]
+    fn kick() {
+        let mut ch = Channel::new("#test44", "test", "test96").unwrap();
+        ch.opers.push("test97".to_owned());
+        ch.save().unwrap();
+        let (data, _) = test_helper(
+            ":test97!test@test PRIVMSG test :CS KICK #test44 test98 spamming\r\n", |state| {
+            state.identify("test97");
+        });
+        let exp = "KICK #test44 test98 :spamming (test97)\r\n\
+                   NOTICE test97 :Kicked test98 from #test44.\r\n";
+        assert_eq!(&data[..], exp);
+        let (data, _) = test_helper(
+            ":test97!test@test PRIVMSG test :CS KICK #test44 test96\r\n", |state| {
+            state.identify("test96");
+            state.identify("test97");
+        });
+        assert_eq!(&data[..], "NOTICE test97 :You cannot do that to test96.\r\n");
+    }
+
+    #[test]
+    fn ban_and_unban() {
+        let ch = Channel::new("#test45", "test", "test99").unwrap();
+        ch.save().unwrap();
+        let (data, _) = test_helper(
+            ":test99!test@test PRIVMSG test :CS BAN #test45 *!*@test\r\n", |state| {
+            state.identify("test99");
+        });
+        let exp = "SAMODE #test45 +b *!*@test\r\n\
+                   NOTICE test99 :Banned *!*@test from #test45.\r\n";
+        assert_eq!(&data[..], exp);
+        let (_, _) = test_helper(
+            ":test99!test@test PRIVMSG test :CS BAN #test45 test100 +1h flooding\r\n", |state| {
+            state.identify("test99");
+        });
+        let ch = Channel::load("#test45").unwrap();
+        assert_eq!(ch.bans().len(), 2);
+        assert_eq!(&ch.bans()[1].mask[..], "test100!*@*");
+        assert_eq!(ch.bans()[1].expires, Some(ch.bans()[1].time + 3600));
+        let (data, _) = test_helper(
+            ":test99!test@test PRIVMSG test :CS UNBAN #test45\r\n", |state| {
+            state.identify("test99");
+        });
+        let exp = "SAMODE #test45 -b *!*@test\r\n\
+                   NOTICE test99 :Removed 1 ban set through services from #test45.\r\n";
+        assert_eq!(&data[..], exp);
+        assert_eq!(Channel::load("#test45").unwrap().bans().len(), 1);
+        let (data, _) = test_helper(
+            ":test99!test@test PRIVMSG test :CS UNBAN #test45\r\n", |state| {
+            state.identify("test99");
+        });
+        let exp = "NOTICE test99 :No bans set through services match you on #test45.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn momentary_status() {
+        let mut ch = Channel::new("#test46", "test", "test101").unwrap();
+        ch.voice.push("test102".to_owned());
+        ch.save().unwrap();
+        let (data, _) = test_helper(
+            ":test102!test@test PRIVMSG test :CS VOICE #test46\r\n", |state| {
+            state.identify("test102");
+        });
+        let exp = "SAMODE #test46 +v test102\r\n\
+                   NOTICE test102 :Set +v test102 on #test46.\r\n";
+        assert_eq!(&data[..], exp);
+        assert!(Channel::load("#test46").unwrap().voice.len() == 1);
+        let (data, _) = test_helper(
+            ":test102!test@test PRIVMSG test :CS OP #test46\r\n", |state| {
+            state.identify("test102");
+        });
+        assert_eq!(&data[..], "NOTICE test102 :You need oper access on #test46 to do that.\r\n");
+        let (data, _) = test_helper(
+            ":test102!test@test PRIVMSG test :CS DEVOICE &test46\r\n", |state| {
+            state.identify("test102");
+        });
+        assert_eq!(&data[..], "NOTICE test102 :Channel &test46 is not registered!\r\n");
+    }
+
+    #[test]
+    fn suspend() {
+        Channel::new("#test54", "test", "test109").unwrap().save().unwrap();
+        let (data, _) = test_helper(
+            ":test!test@test PRIVMSG test :CS SUSPEND #test54 0 spamming\r\n", |state| {
+            state.identify("test");
+        });
+        assert_eq!(&data[..], "NOTICE test :#test54 has been suspended.\r\n");
+        assert!(Channel::load("#test54").unwrap().suspension.is_some());
+    }
+
+    #[test]
+    fn suspended_channel_is_frozen() {
+        let mut ch = Channel::new("#test55", "test", "test109").unwrap();
+        ch.suspension = Some(Suspension::new("test", "spamming", 0, None));
+        ch.save().unwrap();
+        let (data, _) = test_helper(
+            ":test109!test@test PRIVMSG test :CS TOPIC #test55 Mine again\r\n\
+             :test109!test@test JOIN :#test55\r\n\
+             :test109!test@test PRIVMSG test :CS INFO #test55\r\n", |state| {
+            state.identify("test109");
+        });
+        let exp = "NOTICE test109 :#test55 is suspended: spamming (by test, permanently)\r\n\
+                   NOTICE test109 :#test55 is owned by test109.\r\n\
+                   NOTICE test109 :Suspended: spamming (by test, permanently)\r\n\
+                   NOTICE test109 :Admins: none\r\n\
+                   NOTICE test109 :Opers: none\r\n\
+                   NOTICE test109 :Voice: none\r\n\
+                   NOTICE test109 :Topic: \r\n\
+                   NOTICE test109 :Mode: \r\n\
+                   NOTICE test109 :End of info.\r\n";
+        assert_eq!(&data[..], exp);
+        Channel::new("#test68", "test", "test128").unwrap().save().unwrap();
+        let (data, _) = test_helper(
+            ":test128!test@test PRIVMSG test :CS TOPIC #test68 Not to be confused with #test55\r\n",
+            |state| {
+            state.identify("test128");
+        });
+        let exp = "TOPIC #test68 :Not to be confused with #test55\r\n\
+                   NOTICE test128 :Topic of #test68 updated.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn unsuspend() {
+        let mut ch = Channel::new("#test56", "test", "test109").unwrap();
+        ch.suspension = Some(Suspension::new("test", "spamming", 0, Some(60)));
+        ch.save().unwrap();
+        let (data, _) = test_helper(
+            ":test!test@test PRIVMSG test :CS UNSUSPEND #test56\r\n", |state| {
+            state.identify("test");
+        });
+        assert_eq!(&data[..], "NOTICE test :#test56 is no longer suspended.\r\n");
+        assert!(Channel::load("#test56").unwrap().suspension.is_none());
+    }
+
+    #[test]
+    fn suspend_failed_not_operator() {
+        let (data, _) = test_helper(
+            ":test109!test@test PRIVMSG test :CS SUSPEND #test54 0 mine\r\n", |state| {
+            state.identify("test109");
+        });
+        assert_eq!(&data[..], "NOTICE test109 :You must be a services operator to do that.\r\n");
+    }
+
+    #[test]
+    fn forbid_register_and_close() {
+        let _ = remove_file(Path::new("data/chanserv/#test57.json"));
+        let (data, _) = test_helper(
+            ":test!test@test PRIVMSG test :CS FORBID #test57*\r\n\
+             :test!test@test PRIVMSG test :CS FORBID #test58 CLOSE\r\n\
+             :test!test@test PRIVMSG test :CS FORBID LIST\r\n", |state| {
+            state.identify("test");
+        });
+        let exp = "NOTICE test :Channels matching #test57* are now forbidden.\r\n\
+                   NOTICE test :Channels matching #test58 are now forbidden.\r\n\
+                   NOTICE test :Forbidden channels: #test57*, #test58 (closed)\r\n";
+        assert_eq!(&data[..], exp);
+        let (data, _) = test_helper(
+            ":test110!test@test PRIVMSG test :CS REGISTER #test57 test\r\n\
+             :test110!test@test JOIN :#test58\r\n", |state| {
+            state.identify("test110");
+        });
+        let exp = "NOTICE test110 :Channel #test57 is forbidden and may not be registered.\r\n\
+                   KICK #test58 test110 :This channel has been closed by services.\r\n";
+        assert_eq!(&data[..], exp);
+        assert!(!Channel::exists("#test57"));
+        let (data, _) = test_helper(
+            ":test!test@test PRIVMSG test :CS FORBID DEL #test57*\r\n\
+             :test!test@test PRIVMSG test :CS FORBID DEL #test58\r\n", |state| {
+            state.identify("test");
+        });
+        let exp = "NOTICE test :Channels matching #test57* are no longer forbidden.\r\n\
+                   NOTICE test :Channels matching #test58 are no longer forbidden.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn clone_channel() {
+        let mut src = Channel::new("#test59", "test", "test111").unwrap();
+        src.admins.push("test112".to_owned());
+        src.add_ban(Ban::new("*!*@spam.example.com", "test111", None, 0, None));
+        src.settings.greet = true;
+        src.save().unwrap();
+        Channel::new("#test60", "test", "test111").unwrap().save().unwrap();
+        let (data, _) = test_helper(
+            ":test111!test@test PRIVMSG test :CS CLONE #test59 #test60\r\n", |state| {
+            state.identify("test111");
+        });
+        let exp = "SAMODE #test60 +b *!*@spam.example.com\r\n\
+                   NOTICE test111 :Copied the access list, ban list and settings of #test59 to \
+                   #test60.\r\n";
+        assert_eq!(&data[..], exp);
+        let dst = Channel::load("#test60").unwrap();
+        assert_eq!(dst.admins, vec!["test112".to_owned()]);
+        assert_eq!(dst.bans().len(), 1);
+        assert!(dst.settings.greet);
+    }
+
+    #[test]
+    fn clone_failed_not_owner() {
+        let mut src = Channel::new("#test61", "test", "test111").unwrap();
+        src.admins.push("test112".to_owned());
+        src.save().unwrap();
+        Channel::new("#test62", "test", "test111").unwrap().save().unwrap();
+        let (data, _) = test_helper(
+            ":test112!test@test PRIVMSG test :CS CLONE #test61 #test62 ACCESS\r\n", |state| {
+            state.identify("test112");
+        });
+        assert_eq!(&data[..], "NOTICE test112 :You need owner access on #test62 to do that.\r\n");
+    }
+
+    #[test]
+    fn role_templates() {
+        let _ = Role::delete("tester3");
+        Channel::new("#test63", "test", "test113").unwrap().save().unwrap();
+        let (data, _) = test_helper(
+            ":test!test@test PRIVMSG test :CS ROLE SET tester3 oper\r\n\
+             :test113!test@test PRIVMSG test :CS ASSIGN #test63 test114 tester3\r\n\
+             :test114!test@test PRIVMSG test :CS STATUS #test63\r\n\
+             :test114!test@test PRIVMSG test :CS ROLE SET tester3 admin\r\n\
+             :test!test@test PRIVMSG test :CS ROLE DEL tester3\r\n\
+             :test114!test@test PRIVMSG test :CS STATUS #test63\r\n", |state| {
+            state.identify("test");
+            state.identify("test113");
+            state.identify("test114");
+        });
+        let exp = "NOTICE test :Role tester3 now grants oper access.\r\n\
+                   NOTICE test113 :test114 now has the tester3 role in #test63.\r\n\
+                   NOTICE test114 :test114 (account test114) has oper access to #test63.\r\n\
+                   NOTICE test114 :You must be a services operator to do that.\r\n\
+                   NOTICE test :Role tester3 has been deleted.\r\n\
+                   NOTICE test114 :test114 (account test114) has no access to #test63.\r\n";
+        assert_eq!(&data[..], exp);
+        assert!(Channel::load("#test63").unwrap().roles().is_empty());
+    }
+
+    #[test]
+    fn role_failed_invalid_name() {
+        let (data, _) = test_helper(
+            ":test!test@test PRIVMSG test :CS ROLE SET ../tester3 oper\r\n", |state| {
+            state.identify("test");
+        });
+        assert_eq!(&data[..], "NOTICE test :../tester3 is not a valid role name.\r\n");
+    }
 }
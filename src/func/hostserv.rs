@@ -0,0 +1,257 @@
+use super::{Functionality, config_option, is_services_operator, log_event, send_reply};
+use std::borrow::ToOwned;
+use std::io::Result;
+use data::{BotResult, format_time, now};
+use data::state::State;
+use data::user::{User, is_valid_nickname};
+use data::vhost::{VhostPolicy, VhostRequests};
+use irc::client::prelude::*;
+
+pub fn apply_vhost<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>, nickname: &str,
+                                                vhost: &str) -> Result<()> {
+    server.send(Message::new(None, "CHGHOST", Some(vec![nickname, vhost]), None))
+}
+
+fn vhost_policy<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>) -> VhostPolicy {
+    VhostPolicy::new(config_option(server, "vhost-max-length", 64),
+                     &config_option(server, "vhost-forbidden", String::new()))
+}
+
+pub struct Request<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    vhost: String,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Request<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() != 3 {
+            return Err("Syntax: HS REQUEST vhost".to_owned())
+        }
+        try!(vhost_policy(server).check(args[2]));
+        Ok(Box::new(Request {
+            server: server, state: state, user: user.to_owned(), vhost: args[2].to_owned()
+        }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Request<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let msg = if !self.state.is_identified(&self.user) {
+            "You must be identified to do that.".to_owned()
+        } else if let Ok(mut requests) = VhostRequests::load() {
+            requests.add(&self.user, &self.vhost, now());
+            try!(requests.save());
+            try!(log_event(self.server,
+                 &format!("{} requested the vhost {}.", self.user, self.vhost)));
+            format!("Your request for {} is awaiting approval.", self.vhost)
+        } else {
+            "Failed to request vhost due to an I/O issue.".to_owned()
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+pub struct Activate<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    account: String,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Activate<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() != 3 {
+            return Err("Syntax: HS ACTIVATE account".to_owned())
+        } else if !is_valid_nickname(args[2]) {
+            return Err(format!("{} is not a valid nickname.", args[2]))
+        }
+        Ok(Box::new(Activate {
+            server: server, state: state, user: user.to_owned(), account: args[2].to_owned()
+        }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Activate<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let msg = if !is_services_operator(self.server, self.state, &self.user) {
+            "You must be a services operator to do that.".to_owned()
+        } else if let Ok(mut requests) = VhostRequests::load() {
+            match (requests.take(&self.account), User::load(&self.account)) {
+                (Some(request), Ok(mut user)) => {
+                    user.vhost = Some(request.vhost.clone());
+                    try!(user.save());
+                    try!(requests.save());
+                    for session in self.state.get_sessions(&self.account).iter() {
+                        try!(apply_vhost(self.server, session, &request.vhost));
+                        try!(send_reply(self.server, self.state, session,
+                             &format!("Your vhost {} has been activated.", request.vhost)));
+                    }
+                    try!(log_event(self.server, &format!("{} activated the vhost {} for {}.",
+                                                         self.user, request.vhost, self.account)));
+                    format!("Activated {} for {}.", request.vhost, self.account)
+                },
+                (Some(_), Err(_)) => format!("{} is not registered.", self.account),
+                (None, _) => format!("{} has no pending vhost request.", self.account),
+            }
+        } else {
+            "Failed to activate vhost due to an I/O issue.".to_owned()
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+pub struct Reject<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    account: String,
+    reason: Option<String>,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Reject<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() < 3 {
+            return Err("Syntax: HS REJECT account [reason]".to_owned())
+        } else if !is_valid_nickname(args[2]) {
+            return Err(format!("{} is not a valid nickname.", args[2]))
+        }
+        Ok(Box::new(Reject {
+            server: server,
+            state: state,
+            user: user.to_owned(),
+            account: args[2].to_owned(),
+            reason: if args.len() > 3 { Some(args[3..].join(" ")) } else { None },
+        }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Reject<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let msg = if !is_services_operator(self.server, self.state, &self.user) {
+            "You must be a services operator to do that.".to_owned()
+        } else if let Ok(mut requests) = VhostRequests::load() {
+            if let Some(request) = requests.take(&self.account) {
+                try!(requests.save());
+                let notice = match self.reason {
+                    Some(ref reason) => {
+                        format!("Your request for {} was rejected: {}", request.vhost, reason)
+                    },
+                    None => format!("Your request for {} was rejected.", request.vhost),
+                };
+                for session in self.state.get_sessions(&self.account).iter() {
+                    try!(send_reply(self.server, self.state, session, &notice));
+                }
+                format!("Rejected {} for {}.", request.vhost, self.account)
+            } else {
+                format!("{} has no pending vhost request.", self.account)
+            }
+        } else {
+            "Failed to reject vhost due to an I/O issue.".to_owned()
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+pub struct Waiting<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Waiting<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() != 2 {
+            return Err("Syntax: HS WAITING".to_owned())
+        }
+        Ok(Box::new(Waiting { server: server, state: state, user: user.to_owned() }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Waiting<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let msg = if !is_services_operator(self.server, self.state, &self.user) {
+            "You must be a services operator to do that.".to_owned()
+        } else if let Ok(requests) = VhostRequests::load() {
+            for request in requests.requests.iter() {
+                try!(send_reply(self.server, self.state, &self.user, &format!(
+                    "{} requested {} at {}", request.account, request.vhost,
+                    format_time(request.time)
+                )));
+            }
+            format!("{} pending vhost requests.", requests.requests.len())
+        } else {
+            "Failed to list vhost requests due to an I/O issue.".to_owned()
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::ToOwned;
+    use data::user::User;
+    use data::vhost::VhostRequests;
+    use func::test::test_helper;
+
+    #[test]
+    fn request_activate_and_reject() {
+        let u = User::new("test59", "test", None).unwrap();
+        u.save().unwrap();
+        let (data, _) = test_helper(
+            ":test59!test@test PRIVMSG test :HS REQUEST test59.example.com\r\n", |state| {
+            state.identify("test59");
+        });
+        assert_eq!(&data[..], "NOTICE test59 :Your request for test59.example.com is awaiting \
+                               approval.\r\n");
+        let (data, _) = test_helper(
+            ":test!test@test PRIVMSG test :HS ACTIVATE test59\r\n", |state| {
+            state.identify("test");
+            state.identify("test59");
+        });
+        assert_eq!(User::load("test59").unwrap().vhost, Some("test59.example.com".to_owned()));
+        let exp = "CHGHOST test59 test59.example.com\r\n\
+                   NOTICE test59 :Your vhost test59.example.com has been activated.\r\n\
+                   NOTICE test :Activated test59.example.com for test59.\r\n";
+        assert_eq!(&data[..], exp);
+        let mut requests = VhostRequests::load().unwrap();
+        requests.add("test61", "test61.example.com", 0);
+        requests.save().unwrap();
+        let (data, _) = test_helper(
+            ":test!test@test PRIVMSG test :HS REJECT test61 offensive\r\n", |state| {
+            state.identify("test");
+        });
+        assert!(VhostRequests::load().unwrap().take("test61").is_none());
+        assert_eq!(&data[..], "NOTICE test :Rejected test61.example.com for test61.\r\n");
+    }
+
+    #[test]
+    fn request_failed_policy() {
+        let (data, _) = test_helper(
+            ":test60!test@test PRIVMSG test :HS REQUEST bad..host\r\n", |state| {
+            state.identify("test60");
+        });
+        let exp = "NOTICE test60 :bad..host is not a valid vhost. Use letters, digits, hyphens, \
+                   dots and slashes.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn vhost_applied_on_identify() {
+        let mut u = User::new("test62", "test", None).unwrap();
+        u.vhost = Some("test62.example.com".to_owned());
+        u.save().unwrap();
+        let (data, _) = test_helper(
+            ":test62!test@test PRIVMSG test :NS IDENTIFY test\r\n", |_| {}
+        );
+        let exp = "SAMODE test62 +r\r\n\
+                   CHGHOST test62 test62.example.com\r\n\
+                   NOTICE test62 :Password accepted - you are now recognized.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+}
@@ -0,0 +1,443 @@
+use super::{Functionality, config_option, send_reply};
+use std::borrow::ToOwned;
+use std::io::Result;
+use data::{BotResult, format_time, now};
+use data::channel::{Channel, is_valid_channel_name};
+use data::memo::{Memo, MemoBox};
+use data::state::State;
+use data::user::{User, is_valid_nickname};
+use irc::client::prelude::*;
+
+pub fn announce_memos<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>,
+                                                   state: &State, nickname: &str) -> Result<()> {
+    if let Ok(memos) = MemoBox::load(nickname) {
+        let unread = memos.unread();
+        if unread > 0 {
+            try!(send_reply(server, state, nickname, &format!(
+                "You have {} new memo{}. Use MS LIST to see them.", unread,
+                if unread == 1 { "" } else { "s" }
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Delivers a memo, returning the reply to show the sender when it could not be delivered.
+fn deliver<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>, state: &State,
+                                        recipient: &str, memo: Memo) -> Result<BotResult<()>> {
+    let from = memo.from.clone();
+    let io_error = format!("Failed to deliver the memo to {} due to an I/O issue.", recipient);
+    let mut memos = match MemoBox::load(recipient) {
+        Ok(memos) => memos,
+        Err(_) => return Ok(Err(io_error)),
+    };
+    if !memos.deliver(memo, config_option(server, "memo-quota", 20)) {
+        return Ok(Err(format!("{}'s memo box is full.", recipient)))
+    }
+    if memos.save().is_err() {
+        return Ok(Err(io_error))
+    }
+    for session in state.get_sessions(recipient).iter() {
+        try!(send_reply(server, state, session, &format!(
+            "You have a new memo from {}. Use MS READ {} to read it.", from, memos.memos.len()
+        )));
+    }
+    Ok(Ok(()))
+}
+
+pub struct Send<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    target: String,
+    text: String,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Send<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() < 4 {
+            return Err("Syntax: MS SEND nickname|channel message".to_owned())
+        } else if args[2].starts_with("#") && !is_valid_channel_name(args[2]) {
+            return Err(format!("{} is not a valid channel name.", args[2]))
+        } else if !args[2].starts_with("#") && !is_valid_nickname(args[2]) {
+            return Err(format!("{} is not a valid nickname.", args[2]))
+        }
+        Ok(Box::new(Send {
+            server: server,
+            state: state,
+            user: user.to_owned(),
+            target: args[2].to_owned(),
+            text: args[3..].join(" "),
+        }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Send<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let msg = if !self.state.is_identified(&self.user) {
+            "You must be identified to do that.".to_owned()
+        } else if self.target.starts_with("#") {
+            if let Ok(chan) = Channel::load(&self.target) {
+                let mut members = vec![chan.owner.clone()];
+                let access = chan.admins.iter().chain(chan.opers.iter()).chain(chan.voice.iter());
+                for member in access {
+                    if !members.contains(member) {
+                        members.push(member.clone());
+                    }
+                }
+                if !members.contains(&self.user) {
+                    format!("You must be on the access list of {} to do that.", self.target)
+                } else {
+                    let text = format!("[{}] {}", self.target, self.text);
+                    let mut delivered = 0;
+                    for member in members.iter().filter(|m| *m != &self.user) {
+                        if User::exists(member) && try!(deliver(
+                            self.server, self.state, member, Memo::new(&self.user, &text, now())
+                        )).is_ok() {
+                            delivered += 1;
+                        }
+                    }
+                    format!("Memo sent to {} members of {}.", delivered, self.target)
+                }
+            } else {
+                format!("{} is not registered.", self.target)
+            }
+        } else if !User::exists(&self.target) {
+            format!("{} is not registered.", self.target)
+        } else {
+            match try!(deliver(self.server, self.state, &self.target,
+                               Memo::new(&self.user, &self.text, now()))) {
+                Ok(()) => format!("Memo sent to {}.", self.target),
+                Err(msg) => msg,
+            }
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+pub struct List<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> List<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() != 2 {
+            return Err("Syntax: MS LIST".to_owned())
+        }
+        Ok(Box::new(List { server: server, state: state, user: user.to_owned() }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for List<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let msg = if !self.state.is_identified(&self.user) {
+            "You must be identified to do that.".to_owned()
+        } else if let Ok(memos) = MemoBox::load(&self.user) {
+            if memos.memos.is_empty() {
+                "You have no memos.".to_owned()
+            } else {
+                for (i, memo) in memos.memos.iter().enumerate() {
+                    try!(send_reply(self.server, self.state, &self.user, &format!(
+                        "{}. {}from {} at {}", i + 1, if memo.read { "" } else { "[new] " },
+                        memo.from, format_time(memo.time)
+                    )));
+                }
+                format!("You have {} memos, {} unread.", memos.memos.len(), memos.unread())
+            }
+        } else {
+            "Failed to list memos due to an I/O issue.".to_owned()
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+pub struct Read<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    index: Option<usize>,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Read<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() != 3 {
+            return Err("Syntax: MS READ number|NEW".to_owned())
+        }
+        let index = if &super::upper_case(args[2])[..] == "NEW" {
+            None
+        } else {
+            Some(try!(args[2].parse().map_err(|_| "Syntax: MS READ number|NEW".to_owned())))
+        };
+        Ok(Box::new(Read { server: server, state: state, user: user.to_owned(), index: index }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Read<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let msg = if !self.state.is_identified(&self.user) {
+            "You must be identified to do that.".to_owned()
+        } else if let Ok(mut memos) = MemoBox::load(&self.user) {
+            let index = self.index.or_else(|| {
+                memos.memos.iter().position(|m| !m.read).map(|i| i + 1)
+            });
+            let text = match index.and_then(|i| memos.get_mut(i).map(|m| (i, m))) {
+                Some((i, memo)) => {
+                    memo.read = true;
+                    Some(format!("Memo {} from {} ({}): {}", i, memo.from, format_time(memo.time),
+                                 memo.text))
+                },
+                None => None,
+            };
+            match text {
+                Some(text) => {
+                    try!(memos.save());
+                    text
+                },
+                None if self.index.is_none() => "You have no new memos.".to_owned(),
+                None => "No such memo.".to_owned(),
+            }
+        } else {
+            "Failed to read memo due to an I/O issue.".to_owned()
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+pub struct Del<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    index: Option<usize>,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Del<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() != 3 {
+            return Err("Syntax: MS DEL number|ALL".to_owned())
+        }
+        let index = if &super::upper_case(args[2])[..] == "ALL" {
+            None
+        } else {
+            Some(try!(args[2].parse().map_err(|_| "Syntax: MS DEL number|ALL".to_owned())))
+        };
+        Ok(Box::new(Del { server: server, state: state, user: user.to_owned(), index: index }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Del<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let msg = if !self.state.is_identified(&self.user) {
+            "You must be identified to do that.".to_owned()
+        } else if let Ok(mut memos) = MemoBox::load(&self.user) {
+            match self.index {
+                None => {
+                    memos.memos.clear();
+                    try!(memos.save());
+                    "All of your memos have been deleted.".to_owned()
+                },
+                Some(i) if memos.remove(i).is_some() => {
+                    try!(memos.save());
+                    format!("Memo {} has been deleted.", i)
+                },
+                Some(_) => "No such memo.".to_owned(),
+            }
+        } else {
+            "Failed to delete memo due to an I/O issue.".to_owned()
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+pub struct Forward<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    index: usize,
+    target: String,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Forward<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() != 4 {
+            return Err("Syntax: MS FORWARD number nickname".to_owned())
+        } else if !is_valid_nickname(args[3]) {
+            return Err(format!("{} is not a valid nickname.", args[3]))
+        }
+        Ok(Box::new(Forward {
+            server: server,
+            state: state,
+            user: user.to_owned(),
+            index: try!(args[2].parse().map_err(|_| {
+                "Syntax: MS FORWARD number nickname".to_owned()
+            })),
+            target: args[3].to_owned(),
+        }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Forward<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let msg = if !self.state.is_identified(&self.user) {
+            "You must be identified to do that.".to_owned()
+        } else if !User::exists(&self.target) {
+            format!("{} is not registered.", self.target)
+        } else if let Ok(mut memos) = MemoBox::load(&self.user) {
+            match memos.get_mut(self.index).map(|m| m.clone()) {
+                Some(memo) => {
+                    let text = format!("(forwarded from {}) {}", memo.from, memo.text);
+                    match try!(deliver(self.server, self.state, &self.target,
+                                       Memo::new(&self.user, &text, now()))) {
+                        Ok(()) => format!("Memo {} forwarded to {}.", self.index, self.target),
+                        Err(msg) => msg,
+                    }
+                },
+                None => "No such memo.".to_owned(),
+            }
+        } else {
+            "Failed to forward memo due to an I/O issue.".to_owned()
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::ToOwned;
+    use data::channel::Channel;
+    use data::memo::{Memo, MemoBox};
+    use data::user::User;
+    use func::test::test_helper;
+
+    #[test]
+    fn send_succeeded() {
+        let u = User::new("test51", "test", None).unwrap();
+        u.save().unwrap();
+        let mut memos = MemoBox::new("test51");
+        memos.save().unwrap();
+        let (data, _) = test_helper(
+            ":test50!test@test PRIVMSG test :MS SEND test51 hello there\r\n", |state| {
+            state.identify("test50");
+            state.identify("test51");
+        });
+        memos = MemoBox::load("test51").unwrap();
+        assert_eq!(memos.memos.len(), 1);
+        assert_eq!(&memos.memos[0].text[..], "hello there");
+        let exp = "NOTICE test51 :You have a new memo from test50. Use MS READ 1 to read it.\r\n\
+                   NOTICE test50 :Memo sent to test51.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn send_failed_quota() {
+        let u = User::new("test52", "test", None).unwrap();
+        u.save().unwrap();
+        let mut memos = MemoBox::new("test52");
+        for _ in 0..20 {
+            memos.deliver(Memo::new("test50", "spam", 0), 20);
+        }
+        memos.save().unwrap();
+        let (data, _) = test_helper(
+            ":test50!test@test PRIVMSG test :MS SEND test52 hello\r\n", |state| {
+            state.identify("test50");
+        });
+        assert_eq!(&data[..], "NOTICE test50 :test52's memo box is full.\r\n");
+    }
+
+    #[test]
+    fn send_failed_invalid_target() {
+        let (data, _) = test_helper(
+            ":test50!test@test PRIVMSG test :MS SEND ../nickserv/test51 hi\r\n\
+             :test50!test@test PRIVMSG test :MS SEND #../nickserv/test51 hi\r\n", |state| {
+            state.identify("test50");
+        });
+        let exp = "NOTICE test50 :../nickserv/test51 is not a valid nickname.\r\n\
+                   NOTICE test50 :#../nickserv/test51 is not a valid channel name.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn read_new() {
+        let mut memos = MemoBox::new("test53");
+        memos.deliver(Memo::new("test50", "first", 0), 20);
+        memos.deliver(Memo::new("test50", "second", 0), 20);
+        memos.memos[0].read = true;
+        memos.save().unwrap();
+        let (data, _) = test_helper(
+            ":test53!test@test PRIVMSG test :MS READ NEW\r\n", |state| {
+            state.identify("test53");
+        });
+        assert_eq!(MemoBox::load("test53").unwrap().unread(), 0);
+        let exp = "NOTICE test53 :Memo 2 from test50 (1970-01-01T00:00:00Z): second\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn del_and_forward() {
+        let u = User::new("test55", "test", None).unwrap();
+        u.save().unwrap();
+        MemoBox::new("test55").save().unwrap();
+        let mut memos = MemoBox::new("test54");
+        memos.deliver(Memo::new("test50", "first", 0), 20);
+        memos.deliver(Memo::new("test50", "second", 0), 20);
+        memos.save().unwrap();
+        let (data, _) = test_helper(
+            ":test54!test@test PRIVMSG test :MS FORWARD 2 test55\r\n\
+             :test54!test@test PRIVMSG test :MS DEL 1\r\n", |state| {
+            state.identify("test54");
+        });
+        assert_eq!(&MemoBox::load("test55").unwrap().memos[0].text[..],
+                   "(forwarded from test50) second");
+        assert_eq!(MemoBox::load("test54").unwrap().memos.len(), 1);
+        let exp = "NOTICE test54 :Memo 2 forwarded to test55.\r\n\
+                   NOTICE test54 :Memo 1 has been deleted.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn announce_on_identify() {
+        let u = User::new("test56", "test", None).unwrap();
+        u.save().unwrap();
+        let mut memos = MemoBox::new("test56");
+        memos.deliver(Memo::new("test50", "hello", 0), 20);
+        memos.save().unwrap();
+        let (data, _) = test_helper(
+            ":test56!test@test PRIVMSG test :NS IDENTIFY test\r\n", |_| {}
+        );
+        let exp = "SAMODE test56 +r\r\n\
+                   NOTICE test56 :You have 1 new memo. Use MS LIST to see them.\r\n\
+                   NOTICE test56 :Password accepted - you are now recognized.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn send_failed_not_identified() {
+        let (data, _) = test_helper(
+            ":test50!test@test PRIVMSG test :MS SEND test hello\r\n", |_| {}
+        );
+        assert_eq!(&data[..], "NOTICE test50 :You must be identified to do that.\r\n");
+    }
+
+    #[test]
+    fn send_channel() {
+        let u = User::new("test58", "test", None).unwrap();
+        u.save().unwrap();
+        MemoBox::new("test58").save().unwrap();
+        let mut chan = Channel::new("#test29", "test", "test57").unwrap();
+        chan.admins.push("test58".to_owned());
+        chan.save().unwrap();
+        let (data, _) = test_helper(
+            ":test57!test@test PRIVMSG test :MS SEND #test29 meeting at noon\r\n", |state| {
+            state.identify("test57");
+        });
+        assert_eq!(&MemoBox::load("test58").unwrap().memos[0].text[..],
+                   "[#test29] meeting at noon");
+        assert_eq!(&data[..], "NOTICE test57 :Memo sent to 1 members of #test29.\r\n");
+    }
+}
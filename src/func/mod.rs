@@ -5,22 +5,30 @@ use std::borrow::ToOwned;
 use std::io::Result;
 use std::fs::walk_dir;
 use std::path::Path;
-use data::channel::Channel;
+use std::str::FromStr;
+use data::channel::{AccessLevel, Channel};
+use data::mode;
 #[cfg(feature = "democracy")] use data::democracy::Democracy;
+#[cfg(feature = "democracy")] use data::scheduler::Task;
 #[cfg(feature = "democracy")] use data::democracy::VoteResult::{VotePassed, VoteFailed};
 #[cfg(feature = "democracy")] use data::democracy::VotingResult::{VoteIssued, InvalidVote};
 #[cfg(feature = "democracy")] use data::democracy::VotingResult::NoSuchProposal;
 #[cfg(feature = "derp")] use data::derp::DerpCounter;
 #[cfg(feature = "resistance")] use data::resistance::Resistance;
 use data::state::State;
+use data::user::User;
 use irc::client::prelude::*;
 
 mod chanserv;
+mod hostserv;
+mod memoserv;
 mod nickserv;
+mod scheduler;
 
 pub fn process<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>, source: &str, 
                                                command: &str, args: &[&str], state: &'a State) 
     -> Result<()> { 
+    try!(scheduler::run_due(server, state));
     if let ("PRIVMSG", 2) = (command, args.len()) { // FIXME: use slice patterns when stable
         let chan = args[0];
         let msg = args[1];
@@ -32,42 +40,123 @@ pub fn process<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>, sou
                 return Ok(());
             }
         }
-        if chan.starts_with("#") { return do_democracy(server, source, msg, chan, state); }
+        if chan.starts_with("#") {
+            if try!(chanserv::fantasy(server, source, chan, msg, state)) {
+                return Ok(());
+            }
+            return do_democracy(server, source, msg, chan, state);
+        }
         let tokens: Vec<_> = msg.split(" ").collect();
         let res = if tokens.len() > 1 && &upper_case(tokens[0])[..] == "NS" {
             let cmd: String = upper_case(tokens[1]);
             match &cmd[..] {
-                "REGISTER" => nickserv::Register::new(server, source, tokens, state),
-                "IDENTIFY" => nickserv::Identify::new(server, source, tokens, state),
-                "GHOST"    => nickserv::Ghost::new(server, source,tokens),
-                "RECLAIM"  => nickserv::Reclaim::new(server, source, tokens, state),
-                "CHPASS"   => nickserv::ChangePassword::new(server, source, tokens),
-                _          => Err(format!("{} is not a valid command.", tokens[1])),
+                "REGISTER"   => nickserv::Register::new(server, source, tokens, state),
+                "IDENTIFY"   => nickserv::Identify::new(server, source, tokens, state),
+                "GHOST"      => nickserv::Ghost::new(server, source, tokens, state),
+                "RECLAIM"    => nickserv::Reclaim::new(server, source, tokens, state),
+                "CHPASS"     => nickserv::ChangePassword::new(server, source, tokens, state),
+                "CERT"       => nickserv::Cert::new(server, source, tokens, state),
+                "ACCESS"     => nickserv::Access::new(server, source, tokens, state),
+                "TOTP"       => nickserv::Totp::new(server, source, tokens, state),
+                "SET"        => nickserv::Set::new(server, source, tokens, state),
+                "LOGOUT"     => nickserv::Logout::new(server, source, tokens, state),
+                "SESSIONS"   => nickserv::Sessions::new(server, source, tokens, state),
+                "FORBID"     => nickserv::Forbid::new(server, source, tokens, state),
+                "SUSPEND"    => nickserv::Suspend::new(server, source, tokens, state),
+                "UNSUSPEND"  => nickserv::Unsuspend::new(server, source, tokens, state),
+                "LIST"       => nickserv::List::new(server, source, tokens, state),
+                "HISTORY"    => nickserv::History::new(server, source, tokens, state),
+                "EXPORT"     => nickserv::Export::new(server, source, tokens, state),
+                "CHANGENICK" => nickserv::ChangeNick::new(server, source, tokens, state),
+                "RELEASE"    => nickserv::Release::new(server, source, tokens, state),
+                _            => Err(format!("{} is not a valid command.", tokens[1])),
             }
         } else if tokens.len() > 1 && &upper_case(tokens[0])[..] == "CS" {
+            let cmd: String = upper_case(tokens[1]);
+            // VOICE and DEVOICE also grant access with `user channel password [+duration]`, so
+            // the shorter `channel [nick]` form sets the mode for the moment instead.
+            let momentary = tokens.len() < 5;
+            if let Some(msg) = chanserv::check_suspended(state, &cmd, &tokens) {
+                Err(msg)
+            } else {
+                match &cmd[..] {
+                    "VOICE" if momentary => {
+                        chanserv::SetStatus::new(server, source, tokens, state, true, 'v')
+                    },
+                    "DEVOICE" if momentary => {
+                        chanserv::SetStatus::new(server, source, tokens, state, false, 'v')
+                    },
+                    "REGISTER" => chanserv::Register::new(server, source, tokens, state),
+                    "ADMIN"    => chanserv::Admin::new(server, source, tokens, state),
+                    "OPER"     => chanserv::Oper::new(server, source, tokens, state),
+                    "VOICE"    => chanserv::Voice::new(server, source, tokens, state),
+                    "MODE"     => chanserv::Mode::new(server, source, tokens, state),
+                    "DEADMIN"  => chanserv::DeAdmin::new(server, source, tokens, state),
+                    "DEOPER"   => chanserv::DeOper::new(server, source, tokens, state),
+                    "DEVOICE"  => chanserv::DeVoice::new(server, source, tokens, state),
+                    "CHOWN"    => chanserv::ChangeOwner::new(server, source, tokens, state),
+                    "INFO"     => chanserv::Info::new(server, source, tokens, state),
+                    "LIST"     => chanserv::List::new(server, source, tokens, state),
+                    "STATUS"   => chanserv::Status::new(server, source, tokens, state),
+                    "MLOCK"    => chanserv::LockModes::new(server, source, tokens, state),
+                    "SET"      => chanserv::Set::new(server, source, tokens, state),
+                    "TOPIC"    => chanserv::Topic::new(server, source, tokens, state),
+                    "KICK"     => chanserv::Kick::new(server, source, tokens, state),
+                    "BAN"      => chanserv::Ban::new(server, source, tokens, state),
+                    "UNBAN"    => chanserv::Unban::new(server, source, tokens, state),
+                    "INVITE"   => chanserv::Invite::new(server, source, tokens, state),
+                    "QUIET"    => chanserv::Quiet::new(server, source, tokens, state),
+                    "OP" => {
+                        chanserv::SetStatus::new(server, source, tokens, state, true, 'o')
+                    },
+                    "DEOP" => {
+                        chanserv::SetStatus::new(server, source, tokens, state, false, 'o')
+                    },
+                    "SUSPEND"  => chanserv::Suspend::new(server, source, tokens, state),
+                    "UNSUSPEND" => chanserv::Unsuspend::new(server, source, tokens, state),
+                    "FORBID"   => chanserv::Forbid::new(server, source, tokens, state),
+                    "CLONE"    => chanserv::CloneChannel::new(server, source, tokens, state),
+                    "ROLE"     => chanserv::Roles::new(server, source, tokens, state),
+                    "ASSIGN"   => chanserv::Assign::new(server, source, tokens, state),
+                    _          => Err(format!("{} is not a valid command.", tokens[1])),
+                }
+            }
+        } else if tokens.len() > 1 && &upper_case(tokens[0])[..] == "HS" {
             let cmd: String = upper_case(tokens[1]);
             match &cmd[..] {
-                "REGISTER" => chanserv::Register::new(server, source, tokens, state),
-                "ADMIN"    => chanserv::Admin::new(server, source, tokens, state),
-                "OPER"     => chanserv::Oper::new(server, source, tokens, state),
-                "VOICE"    => chanserv::Voice::new(server, source, tokens, state),
-                "MODE"     => chanserv::Mode::new(server, source, tokens, state),
-                "DEADMIN"  => chanserv::DeAdmin::new(server, source, tokens, state),
-                "DEOPER"   => chanserv::DeOper::new(server, source, tokens, state),
-                "DEVOICE"  => chanserv::DeVoice::new(server, source, tokens, state),
-                "CHOWN"    => chanserv::ChangeOwner::new(server, source, tokens, state),
+                "REQUEST"  => hostserv::Request::new(server, source, tokens, state),
+                "ACTIVATE" => hostserv::Activate::new(server, source, tokens, state),
+                "REJECT"   => hostserv::Reject::new(server, source, tokens, state),
+                "WAITING"  => hostserv::Waiting::new(server, source, tokens, state),
                 _          => Err(format!("{} is not a valid command.", tokens[1])),
             }
+        } else if tokens.len() > 1 && &upper_case(tokens[0])[..] == "MS" {
+            let cmd: String = upper_case(tokens[1]);
+            match &cmd[..] {
+                "SEND"    => memoserv::Send::new(server, source, tokens, state),
+                "LIST"    => memoserv::List::new(server, source, tokens, state),
+                "READ"    => memoserv::Read::new(server, source, tokens, state),
+                "DEL"     => memoserv::Del::new(server, source, tokens, state),
+                "FORWARD" => memoserv::Forward::new(server, source, tokens, state),
+                _         => Err(format!("{} is not a valid command.", tokens[1])),
+            }
         } else if tokens.len() == 1 && &upper_case(tokens[0])[..] == "NS" {
-            Err("Commands: REGISTER, IDENTIFY, GHOST, RECLAIM, CHPASS".to_owned())
+            Err("Commands: REGISTER, IDENTIFY, GHOST, RECLAIM, CHPASS, CERT, \
+                 ACCESS, TOTP, SET, LOGOUT, SESSIONS, FORBID, SUSPEND, UNSUSPEND, LIST, \
+                 HISTORY, EXPORT, CHANGENICK, RELEASE".to_owned())
         } else if tokens.len() == 1 && &upper_case(tokens[0])[..] == "CS" {
             Err("Commands: REGISTER, ADMIN, OPER, VOICE, MODE, DEADMIN, DEOPER, DEVOICE, \
-                 CHOWN".to_owned())
+                 CHOWN, INFO, LIST, STATUS, MLOCK, SET, TOPIC, KICK, BAN, UNBAN, INVITE, QUIET, \
+                 OP, DEOP, SUSPEND, UNSUSPEND, FORBID, CLONE, ROLE, ASSIGN".to_owned())
+        } else if tokens.len() == 1 && &upper_case(tokens[0])[..] == "HS" {
+            Err("Commands: REQUEST, ACTIVATE, REJECT, WAITING".to_owned())
+        } else if tokens.len() == 1 && &upper_case(tokens[0])[..] == "MS" {
+            Err("Commands: SEND, LIST, READ, DEL, FORWARD".to_owned())
         } else {
-            Err("Commands must be prefixed by CS or NS.".to_owned())
+            Err("Commands must be prefixed by CS, HS, MS or NS.".to_owned())
         };
         if let Err(msg) = res {
-            try!(server.send_notice(source, &msg));
+            try!(send_reply(server, state, source, &msg));
         } else {
             try!(res.unwrap().do_func())
         }
@@ -77,34 +166,97 @@ pub fn process<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>, sou
         }
     } else if let ("001", _) = (command, args) {
         try!(start_up(server, state));
+    } else if let ("005", _) = (command, args) {
+        state.update_mode_spec(args);
     } else if let ("TOPIC", 2) = (command, args.len()) { // FIXME: use slice patterns when stable
-        if let Ok(mut channel) = Channel::load(args[0]) {
-            channel.topic = args[1].to_owned();
-            try!(channel.save());
+        if let Some(mut channel) = chanserv::load_active(state, args[0]) {
+            if &channel.topic[..] == args[1] {
+                // The topic didn't change, e.g. when our own TOPIC is echoed back.
+            } else if channel.settings.topiclock &&
+                      chanserv::effective_access(state, &channel, source) < AccessLevel::Oper {
+                try!(server.send_topic(args[0], &channel.topic));
+            } else {
+                channel.topic = args[1].to_owned();
+                channel.topic_setter = Some(source.to_owned());
+                channel.topic_time = Some(state.now());
+                try!(channel.save());
+            }
+        }
+    } else if let ("331", 3) = (command, args.len()) { // FIXME: use slice patterns when stable
+        if let Some(channel) = chanserv::load_active(state, args[1]) {
+            if channel.settings.keeptopic && !channel.topic.is_empty() {
+                try!(server.send_topic(args[1], &channel.topic));
+            }
         }
     } else if let ("JOIN", 1) = (command, args.len()) { // FIXME: use slice patterns when stable
-        if let Ok(channel) = Channel::load(args[0]) {
-            let mode = if &channel.owner[..] == source {
-                "+qa"
-            } else if channel.admins.contains(&source.to_owned()) {
-                "+a"
-            } else if channel.opers.contains(&source.to_owned()) {
-                "+o"
-            } else if channel.voice.contains(&source.to_owned()) {
-                "+v"
-            } else {
-                ""
+        try!(nickserv::access_identify(server, source, state));
+        try!(check_certfp(server, source, state));
+        if try!(chanserv::enforce_forbid(server, state, args[0], source)) {
+            // Closed channels are kept empty, so there is nothing to grant.
+        } else if let Some(channel) = chanserv::load_active(state, args[0]) {
+            if source == server.config().nickname() && channel.settings.keeptopic &&
+               !channel.topic.is_empty() {
+                // Ask for the current topic so that a 331 reply lets us restore it.
+                try!(server.send(Message::new(None, "TOPIC", Some(vec![args[0]]), None)));
+            }
+            let removed = try!(chanserv::enforce_restricted(server, state, &channel, source));
+            let mode = match channel.access_of(source) {
+                _ if removed => "",
+                AccessLevel::Owner => "+qa",
+                AccessLevel::Admin => "+a",
+                AccessLevel::Oper => "+o",
+                AccessLevel::Voice => "+v",
+                AccessLevel::None => "",
             };
             if state.is_identified(source) && mode.len() > 0 {
                 try!(server.send_samode(args[0], &mode, &source));
+            } else if state.is_recognized(source) && mode.len() > 0 {
+                try!(server.send_samode(args[0], "+v", &source));
+            }
+            if !removed {
+                try!(chanserv::welcome(server, state, &channel, source));
             }
         }
+    } else if let ("NICK", 1) = (command, args.len()) { // FIXME: use slice patterns when stable
+        state.change_nick(source, args[0]);
+        try!(nickserv::access_identify(server, args[0], state));
+        try!(check_certfp(server, args[0], state));
+        try!(nickserv::enforce_warning(server, args[0], state));
+    } else if let ("276", 3) = (command, args.len()) { // FIXME: use slice patterns when stable
+        if let Some(fingerprint) = args[2].split(" ").last() {
+            try!(nickserv::certfp_identify(server, args[1], fingerprint, state));
+        }
     } else if let ("QUIT", _) = (command, args) {
         state.remove(source);
-    } else if let ("MODE", 3) = (command, args.len()) { // FIXME: use slice patterns when stable
-        if args[1] == "+v" || args[1] == "-v" {
-            try!(democracy_process_hook(server, args[1], args[2], args[0], state));
-        } 
+    } else if let ("PART", true) = (command, args.len() > 0) {
+        state.part_channel(args[0], source);
+    } else if let ("KICK", true) = (command, args.len() > 1) {
+        state.part_channel(args[0], args[1]);
+    } else if let ("353", 4) = (command, args.len()) { // FIXME: use slice patterns when stable
+        let spec = state.get_mode_spec();
+        for name in args[3].split(' ').filter(|n| !n.is_empty()) {
+            let (modes, nick) = spec.prefix_modes(name);
+            state.set_prefix_modes(args[2], &nick, &modes);
+        }
+    } else if let ("366", true) = (command, args.len() > 1) {
+        try!(chanserv::finish_registration(server, state, args[1]));
+    } else if let ("MODE", true) = (command, args.len() > 1) {
+        let changes = mode::parse(&state.get_mode_spec(), args[1], &args[2..]);
+        for change in changes.iter().filter(|c| c.mode == 'v') {
+            if let Some(ref target) = change.param {
+                let msg = if change.adding { "+v" } else { "-v" };
+                try!(democracy_process_hook(server, msg, target, args[0], state));
+            }
+        }
+        for change in changes.iter().filter(|c| "qao".contains(c.mode)) {
+            if let Some(ref target) = change.param {
+                state.update_prefix_mode(args[0], target, change.mode, change.adding);
+            }
+        }
+        if source != server.config().nickname() {
+            try!(chanserv::enforce_mlock(server, state, args[0], &changes));
+            try!(chanserv::enforce_secureops(server, state, args[0], &changes));
+        }
     }
     Ok(())
 }
@@ -113,8 +265,51 @@ pub trait Functionality {
     fn do_func(&self) -> Result<()>;
 }
 
+pub fn config_option<'a, T: IrcRead, U: IrcWrite, V: FromStr>(server: &'a ServerExt<'a, T, U>,
+                                                              key: &str, default: V) -> V {
+    server.config().options.as_ref().and_then(|o| o.get(key)).and_then(|v| v.parse().ok())
+                                    .unwrap_or(default)
+}
+
+pub fn log_event<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>, msg: &str)
+    -> Result<()> {
+    let chan: String = config_option(server, "log-channel", String::new());
+    if chan.len() > 0 {
+        try!(server.send_privmsg(&chan, msg));
+    }
+    Ok(())
+}
+
+pub fn is_services_operator<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>,
+                                                         state: &State, nick: &str) -> bool {
+    server.config().is_owner(nick) && state.is_identified(nick)
+}
+
+/// Replies by NOTICE, or by PRIVMSG when the identified account has turned MSG on.
+pub fn send_reply<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>, state: &State,
+                                               nick: &str, msg: &str) -> Result<()> {
+    let privmsg = state.get_account(nick).and_then(|account| User::load(&account).ok())
+                       .map_or(false, |user| user.settings.use_privmsg);
+    if privmsg {
+        server.send_privmsg(nick, msg)
+    } else {
+        server.send_notice(nick, msg)
+    }
+}
+
+fn check_certfp<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>, nick: &str,
+                                             state: &State) -> Result<()> {
+    let interval: i64 = config_option(server, "whois-interval", 60);
+    if nick != server.config().nickname() && !state.is_identified(nick) && User::exists(nick) &&
+       state.request_whois(nick, interval) {
+        try!(server.send(Message::new(None, "WHOIS", Some(vec![nick]), None)));
+    }
+    Ok(())
+}
+
 fn start_up<T: IrcRead, U: IrcWrite>(server: &ServerExt<T, U>, state: &State) -> Result<()> {
     try!(server.send_oper(server.config().nickname(), server.config().get_option("oper-pass")));
+    try!(state.load_schedule());
     let mut chans: Vec<String> = Vec::new();
     for path in try!(walk_dir(Path::new("data/chanserv/"))) {
         let path_str = path.unwrap().path().into_os_string().into_string().unwrap();
@@ -140,12 +335,19 @@ fn start_up<T: IrcRead, U: IrcWrite>(server: &ServerExt<T, U>, state: &State) ->
         new_voting_booth(&chan, state);
         try!(server.send_samode(&chan, "+a", server.config().nickname()));
         let ch = try!(Channel::load(&chan));
-        if ch.topic.len() != 0 {
+        if ch.active_suspension(state.now()).is_some() {
+            continue
+        }
+        if ch.settings.keeptopic && ch.topic.len() != 0 {
             try!(server.send_topic(&chan, &ch.topic));
         }
         if ch.mode.len() != 0 {
             try!(server.send_samode(&chan, &ch.mode, ""));
         }
+        if let Some(ref lock) = ch.mlock {
+            let (modes, params) = mode::format(&lock.changes(&state.get_mode_spec()));
+            try!(server.send_samode(&chan, &modes, &params.join(" ")));
+        }
     }
     Ok(())
 }
@@ -259,14 +461,14 @@ pub fn democracy_process_hook<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt
                                                               state: &State) -> Result<()> {
     if Channel::exists(chan) {
         if msg == "+v" && state.is_identified(user) {
-            if let Ok(mut channel) = Channel::load(chan) {
+            if let Some(mut channel) = chanserv::load_active(state, chan) {
                 if !channel.voice.contains(&user.to_owned()) {
                     channel.voice.push(user.to_owned());
                     try!(channel.save());
                 }
             }
         } else if msg == "-v" {
-            if let Ok(mut channel) = Channel::load(chan) {
+            if let Some(mut channel) = chanserv::load_active(state, chan) {
                 channel.voice.retain(|u| &u[..] != user);
                 try!(channel.save());
             }
@@ -301,6 +503,7 @@ pub fn do_democracy<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>
             let id = democracy.propose("topic", &message[15..]);
             if let Some(id) = id {
                 try!(server.send_privmsg(chan, &format!("Proposal {} is live.", id)));
+                try!(schedule_expiry(server, state, chan, id));
             }
             return Ok(())
         }
@@ -328,6 +531,7 @@ pub fn do_democracy<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>
             let id = democracy.propose(proposal, parameter);
                 if let Some(id) = id {
                     try!(server.send_privmsg(chan, &format!("Proposal {} is live.", id)));
+                    try!(schedule_expiry(server, state, chan, id));
                 } else {
                     try!(server.send_privmsg(chan, 
                          &format!("{} is not a valid option for a proposal.", proposal)));
@@ -351,10 +555,16 @@ pub fn do_democracy<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>
                 state.get_online_voting_pop(chan)
             };
             try!(match democracy.get_result_of_vote(proposal_id, voting_pop) {
-                VotePassed(proposal) => proposal.enact(server, chan),
-                VoteFailed => server.send_privmsg(chan, 
-                              &format!("Failed to pass proposal {}.", proposal_id)),
-
+                VotePassed(proposal) => {
+                    try!(state.cancel_scheduled(&Task::ExpireProposal(chan.to_owned(),
+                                                                      proposal_id)));
+                    proposal.enact(server, chan)
+                },
+                VoteFailed => {
+                    try!(state.cancel_scheduled(&Task::ExpireProposal(chan.to_owned(),
+                                                                      proposal_id)));
+                    server.send_privmsg(chan, &format!("Failed to pass proposal {}.", proposal_id))
+                },
                 _ => Ok(())
             })
         }
@@ -362,6 +572,16 @@ pub fn do_democracy<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>
     Ok(())
 }
 
+#[cfg(feature = "democracy")]
+fn schedule_expiry<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>, state: &State,
+                                                chan: &str, id: u8) -> Result<()> {
+    let lifetime: i64 = config_option(server, "proposal-lifetime", 0);
+    if lifetime > 0 {
+        try!(state.schedule(state.now() + lifetime, Task::ExpireProposal(chan.to_owned(), id)));
+    }
+    Ok(())
+}
+
 #[cfg(not(feature = "democracy"))]
 pub fn do_democracy<'a, T: IrcRead, U: IrcWrite>(_: &'a ServerExt<'a, T, U>, _: &str, _: &str, 
                                                  _: &str, _: &State) -> Result<()> {
@@ -381,12 +601,21 @@ mod test {
     use std::io::Cursor;
     #[cfg(feature = "derp")] use std::path::Path;
     use data::channel::Channel;
+    use data::mode::{self, ModeLock, ModeSpec};
+    use data::scheduler::{FixedClock, Task};
+    use data::settings::Restriction;
     use data::state::State;
+    use data::user::User;
     use irc::client::conn::Connection;
     use irc::client::prelude::*;
     
     pub fn test_helper<F>(input: &str, state_hook: F) -> (String, State) 
         where F: FnOnce(&State) -> () {
+        test_helper_with_options(input, &[], state_hook)
+    }
+
+    pub fn test_helper_with_options<F>(input: &str, options: &[(&str, &str)], state_hook: F)
+        -> (String, State) where F: FnOnce(&State) -> () {
         let server = IrcServer::from_connection(Config {
                 owners: Some(vec!["test".to_owned()]),
                 nickname: Some("test".to_owned()),
@@ -394,6 +623,9 @@ mod test {
                 options: {
                     let mut map = HashMap::new();
                     map.insert("oper-pass".to_owned(), "test".to_owned());
+                    for &(key, value) in options.iter() {
+                        map.insert(key.to_owned(), value.to_owned());
+                    }
                     Some(map)
                 },
                 .. Default::default()
@@ -407,6 +639,9 @@ mod test {
         for message in server.iter() {
             let message = message.unwrap();
             println!("{:?}", message);
+            if let Some(ref prefix) = message.prefix {
+                state.update_hostmask(prefix);
+            }
             let mut args = Vec::new();
             args.extend(message.args.iter().map(|s| &s[..]));
             if let Some(ref suffix) = message.suffix {
@@ -422,7 +657,7 @@ mod test {
     #[test]
     fn commands_must_be_prefxed() {
         let (data, _) = test_helper(":test!test@test PRIVMSG test :IDENTIFY\r\n", |_| {});
-        assert_eq!(&data[..], "NOTICE test :Commands must be prefixed by CS or NS.\r\n")
+        assert_eq!(&data[..], "NOTICE test :Commands must be prefixed by CS, HS, MS or NS.\r\n")
     }
 
     #[test]
@@ -431,6 +666,22 @@ mod test {
         assert_eq!(&data[..], "");
     }
 
+    #[test]
+    fn fantasy_command() {
+        let mut ch = Channel::new("#test47", "test", "test103").unwrap();
+        ch.settings.fantasy = Some("!".to_owned());
+        assert!(ch.save().is_ok());
+        let (data, _) = test_helper(
+            ":test103!test@test PRIVMSG #test47 :!kick test104 spamming\r\n", |state| {
+            state.identify("test103");
+        });
+        let exp = "KICK #test47 test104 :spamming (test103)\r\n\
+                   NOTICE test103 :Kicked test104 from #test47.\r\n";
+        assert_eq!(&data[..], exp);
+        let (data, _) = test_helper(":test104!test@test PRIVMSG #test47 :!op\r\n", |_| {});
+        assert_eq!(&data[..], "NOTICE test104 :You need oper access on #test47 to do that.\r\n");
+    }
+
     #[test]
     fn non_command_message_in_query() {
         let (data, _) = test_helper(":test!test@test PRIVMSG test :CS line\r\n", |_| {});
@@ -500,6 +751,123 @@ mod test {
         assert_eq!(&ch.topic[..], "This is a topic.");
     }
 
+    #[test]
+    fn topic_lock_reverts() {
+        let mut ch = Channel::new("#test37", "test", "test84").unwrap();
+        ch.topic = "Locked topic".to_owned();
+        ch.settings.topiclock = true;
+        assert!(ch.save().is_ok());
+        let (data, _) = test_helper(":test85!test@test TOPIC #test37 :Vandalism\r\n", |_| {});
+        assert_eq!(&data[..], "TOPIC #test37 :Locked topic\r\n");
+        let (data, _) = test_helper(":test84!test@test TOPIC #test37 :New topic\r\n", |state| {
+            state.identify("test84");
+        });
+        assert_eq!(&data[..], "");
+        let ch = Channel::load("#test37").unwrap();
+        assert_eq!(&ch.topic[..], "New topic");
+        assert_eq!(ch.topic_setter, Some("test84".to_owned()));
+    }
+
+    #[test]
+    fn secure_ops() {
+        let mut ch = Channel::new("#test41", "test", "test89").unwrap();
+        ch.opers.push("test90".to_owned());
+        ch.settings.secureops = true;
+        assert!(ch.save().is_ok());
+        let (data, _) = test_helper(
+            ":test89!test@test MODE #test41 +oov test90 test91 test91\r\n", |state| {
+            state.identify("test89");
+            state.identify("test90");
+            state.identify("test91");
+        });
+        assert_eq!(&data[..], "SAMODE #test41 -o test91\r\n");
+    }
+
+    #[test]
+    fn restricted_join() {
+        let mut ch = Channel::new("#test42", "test", "test92").unwrap();
+        ch.settings.restricted = Restriction::Ban;
+        assert!(ch.save().is_ok());
+        let (data, _) = test_helper(":test93!user@bad.example.com JOIN :#test42\r\n", |_| {});
+        let exp = "SAMODE #test42 +b *!*@bad.example.com\r\n\
+                   KICK #test42 test93 :This channel is restricted to its access list.\r\n";
+        assert_eq!(&data[..], exp);
+        let ch = Channel::load("#test42").unwrap();
+        assert!(ch.bans().iter().any(|b| {
+            &b.mask[..] == "*!*@bad.example.com" && b.expires.is_some()
+        }));
+        let (data, _) = test_helper(":test92!test@test JOIN :#test42\r\n", |state| {
+            state.identify("test92");
+        });
+        assert_eq!(&data[..], "SAMODE #test42 +qa test92\r\n");
+    }
+
+    #[test]
+    fn restricted_join_grace() {
+        let mut ch = Channel::new("#test67", "test", "test126").unwrap();
+        ch.settings.restricted = Restriction::Kick;
+        assert!(ch.save().is_ok());
+        User::new("test127", "test", None).unwrap().save().unwrap();
+        let (data, state) = test_helper(":test127!test@test JOIN :#test67\r\n", |state| {
+            state.set_clock(Box::new(FixedClock(0)));
+        });
+        assert_eq!(&data[..], "WHOIS test127\r\n");
+        state.set_clock(Box::new(FixedClock(30)));
+        let jobs = state.take_due_jobs().unwrap();
+        assert_eq!(jobs.iter().map(|j| j.task.clone()).collect::<Vec<_>>(),
+                   vec![Task::Restrict("#test67".to_owned(), "test127".to_owned())]);
+        let (data, _) = test_helper(":test!test@test PRIVMSG #test :Hi there!\r\n", |state| {
+            state.set_clock(Box::new(FixedClock(30)));
+            state.schedule(30, Task::Restrict("#test67".to_owned(),
+                                              "test127".to_owned())).unwrap();
+        });
+        let exp = "KICK #test67 test127 :This channel is restricted to its access list.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn whois_throttled() {
+        User::new("test124", "test", None).unwrap().save().unwrap();
+        let (data, _) = test_helper(
+            ":test124!test@test JOIN :#unregistered\r\n\
+             :test124!test@test PART :#unregistered\r\n\
+             :test124!test@test JOIN :#unregistered\r\n", |_| {}
+        );
+        assert_eq!(&data[..], "WHOIS test124\r\n");
+    }
+
+    #[test]
+    fn entry_message_and_greet() {
+        let mut u = User::new("test94", "test", None).unwrap();
+        u.settings.greet = Some("Hello, everyone!".to_owned());
+        assert!(u.save().is_ok());
+        let mut ch = Channel::new("#test43", "test", "owner").unwrap();
+        ch.voice.push("test94".to_owned());
+        ch.settings.entrymsg = Some("Please read the rules.".to_owned());
+        ch.settings.greet = true;
+        assert!(ch.save().is_ok());
+        let (data, _) = test_helper(":test94!test@test JOIN :#test43\r\n", |state| {
+            state.identify("test94");
+        });
+        let exp = "SAMODE #test43 +v test94\r\n\
+                   NOTICE test94 :[#test43] Please read the rules.\r\n\
+                   PRIVMSG #test43 :[test94] Hello, everyone!\r\n";
+        assert_eq!(&data[..], exp);
+        let (data, _) = test_helper(":test95!test@test JOIN :#test43\r\n", |_| {});
+        assert_eq!(&data[..], "NOTICE test95 :[#test43] Please read the rules.\r\n");
+    }
+
+    #[test]
+    fn keep_topic_restores() {
+        let mut ch = Channel::new("#test38", "test", "owner").unwrap();
+        ch.topic = "Kept topic".to_owned();
+        assert!(ch.save().is_ok());
+        let (data, _) = test_helper(
+            ":server 331 test #test38 :No topic is set.\r\n", |_| {}
+        );
+        assert_eq!(&data[..], "TOPIC #test38 :Kept topic\r\n");
+    }
+
     #[cfg(not(feature = "democracy"))]
     #[test]
     fn voicing_identified_user() {
@@ -566,6 +934,17 @@ mod test {
         assert_eq!(&data[..], "");
     }
 
+    #[test]
+    fn mode_lock_enforced() {
+        let spec = ModeSpec::new();
+        let mut ch = Channel::new("#test36", "test", "owner").unwrap();
+        ch.mlock = Some(ModeLock::from_changes(&spec, &mode::parse(&spec, "+ntl-s", &["10"]))
+                                 .unwrap());
+        assert!(ch.save().is_ok());
+        let (data, _) = test_helper(":test83!test@test MODE #test36 -t+sl 20\r\n", |_| {});
+        assert_eq!(&data[..], "SAMODE #test36 +t-s+l 10\r\n");
+    }
+
     #[test]
     fn devoicing_user_on_unregistered_channel() {
         let (data, _) = test_helper(":test!test@test MODE #unregistered -v test\r\n", |_| {});
@@ -580,7 +959,9 @@ mod test {
     #[test]
     fn send_just_ns() {
         let (data, _) = test_helper(":test!test@test PRIVMSG test :NS\r\n", |_| {});
-        let exp = "NOTICE test :Commands: REGISTER, IDENTIFY, GHOST, RECLAIM, CHPASS\r\n";
+        let exp = "NOTICE test :Commands: REGISTER, IDENTIFY, GHOST, RECLAIM, CHPASS, CERT, \
+                   ACCESS, TOTP, SET, LOGOUT, SESSIONS, FORBID, SUSPEND, UNSUSPEND, LIST, \
+                   HISTORY, EXPORT, CHANGENICK, RELEASE\r\n";
         assert_eq!(&data[..], exp);
     }
 
@@ -588,7 +969,9 @@ mod test {
     fn send_just_cs() {
         let (data, _) = test_helper(":test!test@test PRIVMSG test :CS\r\n", |_| {});
         let exp = "NOTICE test :Commands: REGISTER, ADMIN, OPER, VOICE, MODE, DEADMIN, DEOPER, \
-                   DEVOICE, CHOWN\r\n";
+                   DEVOICE, CHOWN, INFO, LIST, STATUS, MLOCK, SET, TOPIC, KICK, BAN, UNBAN, \
+                   INVITE, QUIET, OP, DEOP, SUSPEND, UNSUSPEND, FORBID, CLONE, ROLE, \
+                   ASSIGN\r\n";
         assert_eq!(&data[..], exp);
     }
 
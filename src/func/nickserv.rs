@@ -1,11 +1,255 @@
-use super::Functionality;
+use super::{hostserv, memoserv};
+use super::{Functionality, config_option, is_services_operator, log_event, send_reply};
 use std::borrow::ToOwned;
 use std::io::Result;
-use data::BotResult;
+use data::{BotResult, Pattern, format_time, parse_duration};
+use data::export::{AccountExport, paginate};
+use data::forbid::ForbidList;
+use data::policy::Policy;
+use data::scheduler::Task;
+use data::throttle::ThrottleConfig;
+use data::totp;
 use data::state::State;
-use data::user::User;
+use data::settings::Setting;
+use data::suspension::Suspension;
+use data::user::{User, is_valid_access_mask, is_valid_certfp, is_valid_nickname};
 use irc::client::prelude::*;
 
+pub fn identify_user<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>, state: &State,
+                                                  nickname: &str) -> Result<()> {
+    try!(server.send_samode(nickname, "+r", ""));
+    state.identify(nickname);
+    if let Ok(mut user) = User::load(nickname) {
+        if let Some(hostmask) = state.get_hostmask(nickname) {
+            user.record_host(&hostmask, state.now());
+        }
+        let notice = user.lock_notice.take();
+        try!(user.save());
+        if let Some(ref vhost) = user.vhost {
+            try!(hostserv::apply_vhost(server, nickname, vhost));
+        }
+        if let Some(notice) = notice {
+            try!(send_reply(server, state, nickname, &notice));
+        }
+    }
+    memoserv::announce_memos(server, state, nickname)
+}
+
+pub fn certfp_identify<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>,
+                                                    nickname: &str, fingerprint: &str,
+                                                    state: &State) -> Result<()> {
+    state.set_certfp(nickname, fingerprint);
+    if state.is_identified(nickname) || !User::exists(nickname) {
+        return Ok(())
+    }
+    if let Ok(user) = User::load(nickname) {
+        if !user.has_certfp(fingerprint) || user.active_suspension(state.now()).is_some() {
+            return Ok(())
+        } else if user.has_totp() {
+            // A certificate alone doesn't satisfy two-factor authentication.
+            state.recognize(nickname);
+            try!(send_reply(server, state, nickname,
+                 "Certificate fingerprint accepted - you are now recognized. Identify with your \
+                  two-factor code to use protected commands."));
+        } else {
+            try!(identify_user(server, state, nickname));
+            try!(send_reply(server, state, nickname,
+                 "Certificate fingerprint accepted - you are now recognized."));
+        }
+    }
+    Ok(())
+}
+
+pub fn access_identify<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>,
+                                                    nickname: &str, state: &State) -> Result<()> {
+    if state.is_identified(nickname) || state.is_recognized(nickname) {
+        return Ok(())
+    }
+    let userhost = match state.get_hostmask(nickname) {
+        Some(userhost) => userhost,
+        None => return Ok(()),
+    };
+    if let Ok(user) = User::load(nickname) {
+        if !user.matches_access(&userhost) || user.active_suspension(state.now()).is_some() {
+            return Ok(())
+        } else if user.access_identifies && !user.has_totp() {
+            try!(identify_user(server, state, nickname));
+            try!(send_reply(server, state, nickname,
+                 "Your host is on the access list - you are now identified."));
+        } else {
+            state.recognize(nickname);
+            try!(send_reply(server, state, nickname,
+                 "Your host is on the access list - you are now recognized. Identify to use \
+                  protected commands."));
+        }
+    }
+    Ok(())
+}
+
+fn throttle_config<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>)
+    -> ThrottleConfig {
+    ThrottleConfig::new(config_option(server, "login-max-failures", 5),
+                        config_option(server, "login-backoff", 1),
+                        config_option(server, "login-lockout", 900))
+}
+
+fn policy<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>) -> Policy {
+    let mut policy = Policy::new(config_option(server, "password-min-length", 4),
+                                 config_option(server, "password-min-classes", 1),
+                                 &config_option(server, "forbidden-nicks", String::new()),
+                                 config_option(server, "email-verification", false));
+    let path: String = config_option(server, "common-passwords-file", String::new());
+    if !path.is_empty() {
+        let _ = policy.load_common_passwords(&path);
+    }
+    policy
+}
+
+fn throttle_keys(state: &State, requester: &str, account: &str) -> Vec<String> {
+    let mut keys = vec![format!("account:{}", account)];
+    if let Some(userhost) = state.get_hostmask(requester) {
+        if let Some(host) = userhost.split('@').nth(1) {
+            keys.push(format!("host:{}", host));
+        }
+    }
+    keys
+}
+
+fn check_throttle<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>, state: &State,
+                                               requester: &str, account: &str) -> Option<String> {
+    let config = throttle_config(server);
+    let now = state.now();
+    throttle_keys(state, requester, account).iter()
+        .filter_map(|key| state.throttle_wait(key, now, &config)).max()
+        .map(|wait| format!("Too many failed attempts. Try again in {} seconds.", wait))
+}
+
+fn login_failed<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>, state: &State,
+                                             requester: &str, user: &mut User) -> Result<()> {
+    let config = throttle_config(server);
+    let now = state.now();
+    let mask = format!("{}!{}", requester,
+                       state.get_hostmask(requester).unwrap_or("*@*".to_owned()));
+    for key in throttle_keys(state, requester, &user.nickname).iter() {
+        if state.throttle_fail(key, now, &config) {
+            try!(log_event(server, &format!("Locked {} for {} seconds after {} failed logins \
+                                             (last attempt by {}).", key, config.lockout,
+                                            config.max_failures, mask)));
+            if key.starts_with("account:") {
+                user.lock_notice = Some(format!("Your account was locked at {} after {} failed \
+                                                 login attempts (last attempt by {}).",
+                                                format_time(now), config.max_failures, mask));
+                try!(user.save());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn login_succeeded(state: &State, account: &str) {
+    state.throttle_clear(&format!("account:{}", account));
+}
+
+fn check_second_factor(state: &State, user: &mut User, code: &Option<String>)
+    -> Result<Option<&'static str>> {
+    if !user.has_totp() {
+        Ok(None)
+    } else if let &Some(ref code) = code {
+        if try!(user.check_code(code, state.now())) {
+            Ok(None)
+        } else {
+            Ok(Some("Two-factor code incorrect."))
+        }
+    } else {
+        Ok(Some("This account requires a two-factor code."))
+    }
+}
+
+/// Describes an active suspension. Only call this once the password and second factor have
+/// been checked, as it reveals the reason and who set it.
+fn suspension_message(state: &State, user: &User) -> Option<String> {
+    user.active_suspension(state.now()).map(|s| {
+        format!("This account is suspended: {}", s.describe())
+    })
+}
+
+/// Removes whoever is using a nick, either by KILL or by renaming them to a guest nick,
+/// depending on the `reclaim-mode` option.
+fn displace<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>, state: &State,
+                                         nickname: &str, reason: &str) -> Result<()> {
+    let mode: String = config_option(server, "reclaim-mode", "kill".to_owned());
+    if &mode[..] == "guest" {
+        let prefix: String = config_option(server, "guest-prefix", "Guest".to_owned());
+        let mut guest = state.next_guest_nick(&prefix);
+        while state.get_hostmask(&guest).is_some() || User::exists(&guest) {
+            guest = state.next_guest_nick(&prefix);
+        }
+        try!(send_reply(server, state, nickname, &format!(
+            "This nickname is registered. Your nick has been changed to {}.", guest
+        )));
+        server.send_sanick(nickname, &guest)
+    } else {
+        server.send_kill(nickname, reason)
+    }
+}
+
+/// Prevents anyone from taking a nick for a while, using the method named by `nick-hold`.
+fn hold_nick<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>, nickname: &str)
+    -> Result<()> {
+    let duration: String = config_option(server, "nick-hold-duration", "60".to_owned());
+    let method: String = config_option(server, "nick-hold", String::new());
+    match &method[..] {
+        "svshold" => server.send(Message::new(None, "SVSHOLD", Some(vec![nickname, &duration[..]]),
+                                              Some("Held by services"))),
+        "nickdelay" => server.send(Message::new(None, "NICKDELAY",
+                                                Some(vec![&duration[..], nickname]), None)),
+        _ => Ok(()),
+    }
+}
+
+fn release_nick<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>, nickname: &str)
+    -> Result<bool> {
+    let method: String = config_option(server, "nick-hold", String::new());
+    let msg = match &method[..] {
+        "svshold" => Message::new(None, "SVSHOLD", Some(vec![nickname]), None),
+        "nickdelay" => Message::new(None, "NICKDELAY", Some(vec!["0", nickname]), None),
+        _ => return Ok(false),
+    };
+    try!(server.send(msg));
+    Ok(true)
+}
+
+pub fn enforce_warning<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>,
+                                                    nickname: &str, state: &State) -> Result<()> {
+    if state.is_identified(nickname) || state.is_recognized(nickname) {
+        return Ok(())
+    }
+    if let Ok(user) = User::load(nickname) {
+        if user.settings.enforce {
+            try!(send_reply(server, state, nickname, &format!(
+                "This nickname is registered and protected. If it is yours, identify within {} \
+                 seconds. Otherwise, please choose a different nickname.",
+                user.settings.enforce_timeout
+            )));
+            let task = Task::Enforce(nickname.to_owned());
+            try!(state.cancel_scheduled(&task));
+            try!(state.schedule(state.now() + user.settings.enforce_timeout as i64, task));
+        }
+    }
+    Ok(())
+}
+
+/// Removes whoever is still using a protected nick without having identified once its
+/// ENFORCE timeout has passed.
+pub fn enforce_nick<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>, state: &State,
+                                                 nickname: &str) -> Result<()> {
+    if state.is_identified(nickname) || state.is_recognized(nickname) ||
+       state.get_hostmask(nickname).is_none() {
+        return Ok(())
+    }
+    displace(server, state, nickname, "Nickname enforcement")
+}
+
 pub struct Register<'a, T: IrcRead, U: IrcWrite> {
     server: &'a ServerExt<'a, T, U>,
     state: &'a State,
@@ -36,20 +280,26 @@ impl<'a, T: IrcRead, U: IrcWrite> Register<'a, T, U> {
 
 impl<'a, T: IrcRead, U: IrcWrite> Functionality for Register<'a, T, U> {
     fn do_func(&self) -> Result<()> {
-        let user = try!(
+        let mut user = try!(
             User::new(&self.nickname, &self.password, self.email.as_ref().map(|s| &s[..]))
         );
+        user.registered = Some(self.state.now());
         let msg = if User::exists(&self.nickname) {
             format!("Nickname {} is already registered!", user.nickname)
-        } else if user.save().is_ok() {;
-            try!(self.server.send_samode(&self.nickname, "+r", ""));
-            self.state.identify(&self.nickname);
+        } else if ForbidList::is_forbidden("nicks", &self.nickname) {
+            format!("Nickname {} is forbidden and may not be registered.", user.nickname)
+        } else if let Err(msg) = policy(self.server).check_registration(
+            &self.nickname, &self.password, self.email.as_ref().map(|s| &s[..])
+        ) {
+            msg
+        } else if user.save().is_ok() {
+            try!(identify_user(self.server, self.state, &self.nickname));
             format!("Nickname {} has been registered. Don't forget your password!\r\n{}",
                     user.nickname, "You're now identified.")
         } else {
             format!("Failed to register {} due to an I/O issue.", user.nickname)
         };
-        self.server.send_notice(&self.nickname, &msg)
+        send_reply(self.server, self.state, &self.nickname, &msg)
     }
 }
 
@@ -58,19 +308,21 @@ pub struct Identify<'a, T: IrcRead, U: IrcWrite> {
     state: &'a State,
     nickname: String,
     password: String,
+    code: Option<String>,
 }
 
 impl<'a, T: IrcRead, U: IrcWrite> Identify<'a, T, U> {
     pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
         -> BotResult<Box<Functionality + 'a>> {
-        if args.len() != 3 {
-            return Err("Syntax: NS IDENTIFY password".to_owned())
+        if args.len() != 3 && args.len() != 4 {
+            return Err("Syntax: NS IDENTIFY password [code]".to_owned())
         }
         Ok(Box::new(Identify {
             server: server,
             state: state,
             nickname: user.to_owned(),
             password: args[2].to_owned(),
+            code: args.get(3).map(|s| s.to_string()),
         }))
     }
 }
@@ -78,40 +330,58 @@ impl<'a, T: IrcRead, U: IrcWrite> Identify<'a, T, U> {
 impl<'a, T: IrcRead, U: IrcWrite> Functionality for Identify<'a, T, U> {
     fn do_func(&self) -> Result<()> {
         let msg = if !User::exists(&self.nickname) {
-            "Your nick isn't registered."
-        } else if let Ok(user) = User::load(&self.nickname) {
-            if try!(user.is_password(&self.password)) {
-                try!(self.server.send_samode(&self.nickname, "+r", ""));
-                self.state.identify(&self.nickname);
-                "Password accepted - you are now recognized."
+            "Your nick isn't registered.".to_owned()
+        } else if let Some(msg) = check_throttle(self.server, self.state, &self.nickname,
+                                                 &self.nickname) {
+            msg
+        } else if let Ok(mut user) = User::load(&self.nickname) {
+            let suspension = suspension_message(self.state, &user);
+            if !try!(user.is_password(&self.password)) {
+                try!(login_failed(self.server, self.state, &self.nickname, &mut user));
+                "Password incorrect.".to_owned()
+            } else if let Some(err) = try!(check_second_factor(self.state, &mut user, &self.code)) {
+                if self.code.is_some() {
+                    try!(login_failed(self.server, self.state, &self.nickname, &mut user));
+                }
+                err.to_owned()
+            } else if let Some(msg) = suspension {
+                msg
             } else {
-                "Password incorrect."
+                login_succeeded(self.state, &self.nickname);
+                try!(identify_user(self.server, self.state, &self.nickname));
+                "Password accepted - you are now recognized.".to_owned()
             }
         } else {
-            "Failed to identify due to an I/O issue."
+            "Failed to identify due to an I/O issue.".to_owned()
         };
-        self.server.send_notice(&self.nickname, msg)
+        send_reply(self.server, self.state, &self.nickname, &msg)
     }
 }
 
 pub struct Ghost<'a, T: IrcRead, U: IrcWrite> {
     server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
     current_nick: String,
     nickname: String,
     password: String,
+    code: Option<String>,
 }
 
 impl<'a, T: IrcRead, U: IrcWrite> Ghost<'a, T, U> {
-    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>)
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
         -> BotResult<Box<Functionality + 'a>> {
-        if args.len() != 4 {
-            return Err("Syntax: NS GHOST nickname password".to_owned())
+        if args.len() != 4 && args.len() != 5 {
+            return Err("Syntax: NS GHOST nickname password [code]".to_owned())
+        } else if !is_valid_nickname(args[2]) {
+            return Err(format!("{} is not a valid nickname.", args[2]))
         }
         Ok(Box::new(Ghost {
             server: server,
+            state: state,
             current_nick: user.to_owned(),
             nickname: args[2].to_owned(),
             password: args[3].to_owned(),
+            code: args.get(4).map(|s| s.to_string()),
         }))
     }
 }
@@ -119,20 +389,34 @@ impl<'a, T: IrcRead, U: IrcWrite> Ghost<'a, T, U> {
 impl<'a, T: IrcRead, U: IrcWrite> Functionality for Ghost<'a, T, U> {
     fn do_func(&self) -> Result<()> {
         let msg = if !User::exists(&self.nickname) {
-            "That nick isn't registered, and therefore cannot be ghosted."
-        } else if let Ok(user) = User::load(&self.nickname) {
-            if try!(user.is_password(&self.password)) {
-                try!(self.server.send_kill(&self.nickname,
-                     &format!("Ghosted by {}", &self.current_nick)));
-                try!(self.server.send_notice(&self.nickname, "User has been ghosted."));
-                return Ok(());
+            "That nick isn't registered, and therefore cannot be ghosted.".to_owned()
+        } else if let Some(msg) = check_throttle(self.server, self.state, &self.current_nick,
+                                                 &self.nickname) {
+            msg
+        } else if let Ok(mut user) = User::load(&self.nickname) {
+            let suspension = suspension_message(self.state, &user);
+            if !try!(user.is_password(&self.password)) {
+                try!(login_failed(self.server, self.state, &self.current_nick, &mut user));
+                "Password incorrect.".to_owned()
+            } else if let Some(err) = try!(check_second_factor(self.state, &mut user, &self.code)) {
+                if self.code.is_some() {
+                    try!(login_failed(self.server, self.state, &self.current_nick, &mut user));
+                }
+                err.to_owned()
+            } else if let Some(msg) = suspension {
+                msg
             } else {
-                "Password incorrect."
+                login_succeeded(self.state, &self.nickname);
+                try!(displace(self.server, self.state, &self.nickname,
+                              &format!("Ghosted by {}", &self.current_nick)));
+                try!(hold_nick(self.server, &self.nickname));
+                try!(self.server.send_notice(&self.current_nick, "User has been ghosted."));
+                return Ok(());
             }
         } else {
-            "Failed to ghost nick due to an I/O issue."
+            "Failed to ghost nick due to an I/O issue.".to_owned()
         };
-        self.server.send_notice(&self.current_nick, msg)
+        send_reply(self.server, self.state, &self.current_nick, &msg)
     }
 }
 
@@ -142,13 +426,16 @@ pub struct Reclaim<'a, T: IrcRead, U: IrcWrite> {
     current_nick: String,
     nickname: String,
     password: String,
+    code: Option<String>,
 }
 
 impl<'a, T: IrcRead, U: IrcWrite> Reclaim<'a, T, U> {
     pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
         -> BotResult<Box<Functionality + 'a>> {
-        if args.len() != 4 {
-            return Err("Syntax: NS RECLAIM nickname password".to_owned())
+        if args.len() != 4 && args.len() != 5 {
+            return Err("Syntax: NS RECLAIM nickname password [code]".to_owned())
+        } else if !is_valid_nickname(args[2]) {
+            return Err(format!("{} is not a valid nickname.", args[2]))
         }
         Ok(Box::new(Reclaim {
             server: server,
@@ -156,6 +443,7 @@ impl<'a, T: IrcRead, U: IrcWrite> Reclaim<'a, T, U> {
             current_nick: user.to_owned(),
             nickname: args[2].to_owned(),
             password: args[3].to_owned(),
+            code: args.get(4).map(|s| s.to_string()),
         }))
     }
 }
@@ -163,45 +451,61 @@ impl<'a, T: IrcRead, U: IrcWrite> Reclaim<'a, T, U> {
 impl<'a, T: IrcRead, U: IrcWrite> Functionality for Reclaim<'a, T, U> {
     fn do_func(&self) -> Result<()> {
         let msg = if !User::exists(&self.nickname) {
-            "That nick isn't registered, and therefore cannot be reclaimed."
-        } else if let Ok(user) = User::load(&self.nickname) {
-            if try!(user.is_password(&self.password)) {
-                try!(self.server.send_kill(&self.nickname,
-                     &format!("Reclaimed by {}", self.current_nick)));
+            "That nick isn't registered, and therefore cannot be reclaimed.".to_owned()
+        } else if let Some(msg) = check_throttle(self.server, self.state, &self.current_nick,
+                                                 &self.nickname) {
+            msg
+        } else if let Ok(mut user) = User::load(&self.nickname) {
+            let suspension = suspension_message(self.state, &user);
+            if !try!(user.is_password(&self.password)) {
+                try!(login_failed(self.server, self.state, &self.current_nick, &mut user));
+                "Password incorrect.".to_owned()
+            } else if let Some(err) = try!(check_second_factor(self.state, &mut user, &self.code)) {
+                if self.code.is_some() {
+                    try!(login_failed(self.server, self.state, &self.current_nick, &mut user));
+                }
+                err.to_owned()
+            } else if let Some(msg) = suspension {
+                msg
+            } else {
+                login_succeeded(self.state, &self.nickname);
+                try!(displace(self.server, self.state, &self.nickname,
+                              &format!("Reclaimed by {}", self.current_nick)));
                 try!(self.server.send_sanick(&self.current_nick, &self.nickname));
-                try!(self.server.send_samode(&self.nickname, "+r", ""));
-                self.state.identify(&self.nickname);
-                try!(self.server.send_notice(&self.nickname,
+                try!(identify_user(self.server, self.state, &self.nickname));
+                try!(send_reply(self.server, self.state, &self.nickname,
                                            "Password accepted - you are now recognized."));
                 return Ok(());
-            } else {
-                "Password incorrect."
             }
         } else {
-            "Failed to reclaim nick due to an I/O issue."
+            "Failed to reclaim nick due to an I/O issue.".to_owned()
         };
-        self.server.send_notice(&self.current_nick, msg)
+        send_reply(self.server, self.state, &self.current_nick, &msg)
     }
 }
 
 pub struct ChangePassword<'a, T: IrcRead, U: IrcWrite> {
     server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
     user: String,
     password: String,
     new_password: String,
+    code: Option<String>,
 }
 
 impl<'a, T: IrcRead, U: IrcWrite> ChangePassword<'a, T, U> {
-    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>)
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
         -> BotResult<Box<Functionality + 'a>> {
-        if args.len() != 4 {
-            return Err("Syntax: NS CHPASS old_password new_password".to_owned())
+        if args.len() != 4 && args.len() != 5 {
+            return Err("Syntax: NS CHPASS old_password new_password [code]".to_owned())
         }
         Ok(Box::new(ChangePassword {
             server: server,
+            state: state,
             user: user.to_owned(),
             password: args[2].to_owned(),
             new_password: args[3].to_owned(),
+            code: args.get(4).map(|s| s.to_string()),
         }))
     }
 }
@@ -210,184 +514,1631 @@ impl<'a, T: IrcRead, U: IrcWrite> Functionality for ChangePassword<'a, T, U> {
     fn do_func(&self) -> Result<()> {
         let msg = if !User::exists(&self.user) {
             "This nick isn't registered, and therefore doesn't have a password to change."
+                .to_owned()
+        } else if let Some(msg) = check_throttle(self.server, self.state, &self.user, &self.user) {
+            msg
         } else if let Ok(mut user) = User::load(&self.user) {
-            if try!(user.is_password(&self.password)) {
+            if !try!(user.is_password(&self.password)) {
+                try!(login_failed(self.server, self.state, &self.user, &mut user));
+                "Password incorrect.".to_owned()
+            } else if let Some(err) = try!(check_second_factor(self.state, &mut user, &self.code)) {
+                if self.code.is_some() {
+                    try!(login_failed(self.server, self.state, &self.user, &mut user));
+                }
+                err.to_owned()
+            } else if let Err(msg) = policy(self.server).check_password(&self.user,
+                                                                        &self.new_password) {
+                login_succeeded(self.state, &self.user);
+                msg
+            } else {
+                login_succeeded(self.state, &self.user);
                 try!(user.update_password(&self.new_password));
                 try!(user.save());
-                "Your password has been changed. Don't forget it!"
-            } else {
-                "Password incorrect."
+                "Your password has been changed. Don't forget it!".to_owned()
             }
         } else {
-            "Failed to change password due to an I/O issue."
+            "Failed to change password due to an I/O issue.".to_owned()
         };
-        self.server.send_notice(&self.user, msg)
+        send_reply(self.server, self.state, &self.user, &msg)
     }
 }
 
-#[cfg(test)]
-mod test {
-    use std::fs::remove_file;
-    use std::path::Path;
-    use data::user::User;
-    use func::test::test_helper;
+pub struct Cert<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    action: String,
+    fingerprint: Option<String>,
+}
 
-    #[test]
-    fn register_succeeded() {
-        let _ = remove_file(Path::new("data/nickserv/test4.json"));
-        let (data, state) = test_helper(
-            ":test4!test@test PRIVMSG test :NS REGISTER test\r\n", |_| {}
-        );
-        assert!(state.is_identified("test4"));
-        let exp = "SAMODE test4 +r\r\n\
-                   NOTICE test4 :Nickname test4 has been registered. \
-                   Don't forget your password!\r\n\
-                   NOTICE test4 :You're now identified.\r\n";
-        assert_eq!(&data[..], exp);
+impl<'a, T: IrcRead, U: IrcWrite> Cert<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() < 3 || args.len() > 4 {
+            return Err("Syntax: NS CERT ADD|DEL|LIST [fingerprint]".to_owned())
+        }
+        let action = super::upper_case(args[2]);
+        if &action[..] != "ADD" && &action[..] != "DEL" && &action[..] != "LIST" {
+            return Err("Syntax: NS CERT ADD|DEL|LIST [fingerprint]".to_owned())
+        } else if &action[..] == "DEL" && args.len() != 4 {
+            return Err("Syntax: NS CERT DEL fingerprint".to_owned())
+        }
+        Ok(Box::new(Cert {
+            server: server,
+            state: state,
+            user: user.to_owned(),
+            action: action,
+            fingerprint: if args.len() == 4 {
+                Some(args[3].to_owned())
+            } else {
+                state.get_certfp(user)
+            },
+        }))
     }
+}
 
-    #[test]
-    fn register_failed_user_exists() {
-        let u = User::new("test", "test", None).unwrap();
-        u.save().unwrap();
-        let (data, state) = test_helper(
-            ":test!test@test PRIVMSG test :NS REGISTER test\r\n", |_| {}
-        );
-        assert!(!state.is_identified("test"));
-        assert_eq!(&data[..], "NOTICE test :Nickname test is already registered!\r\n");
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Cert<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let msg = if !self.state.is_identified(&self.user) {
+            "You must be identified to do that.".to_owned()
+        } else if let Ok(mut user) = User::load(&self.user) {
+            match (&self.action[..], &self.fingerprint) {
+                ("LIST", _) if user.certfps.is_empty() => {
+                    "Your certificate fingerprint list is empty.".to_owned()
+                },
+                ("LIST", _) => {
+                    format!("Certificate fingerprints: {}", user.certfps.join(", "))
+                },
+                (_, &None) => {
+                    "You aren't using a client certificate, so you must specify a fingerprint."
+                        .to_owned()
+                },
+                ("ADD", &Some(ref fp)) if !is_valid_certfp(fp) => {
+                    format!("{} is not a valid certificate fingerprint.", fp)
+                },
+                ("ADD", &Some(ref fp)) => {
+                    if user.add_certfp(fp) {
+                        try!(user.save());
+                        format!("{} has been added to your certificate fingerprint list.", fp)
+                    } else {
+                        format!("{} is already on your certificate fingerprint list.", fp)
+                    }
+                },
+                (_, &Some(ref fp)) => {
+                    if user.remove_certfp(fp) {
+                        try!(user.save());
+                        format!("{} has been removed from your certificate fingerprint list.", fp)
+                    } else {
+                        format!("{} is not on your certificate fingerprint list.", fp)
+                    }
+                },
+            }
+        } else {
+            "Failed to update certificate fingerprints due to an I/O issue.".to_owned()
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
     }
+}
 
-    #[test]
-    fn identify_succeeded() {
-        let u = User::new("test5", "test", None).unwrap();
-        u.save().unwrap();
-        let (data, state) = test_helper(
-            ":test5!test@test PRIVMSG test :NS IDENTIFY test\r\n", |_| {}
-        );
-        assert!(state.is_identified("test5"));
-        let exp = "SAMODE test5 +r\r\n\
-                   NOTICE test5 :Password accepted - you are now recognized.\r\n";
-        assert_eq!(&data[..], exp);
+pub struct Access<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    action: String,
+    parameter: Option<String>,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Access<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        let action = if args.len() > 2 { super::upper_case(args[2]) } else { String::new() };
+        match (&action[..], args.len()) {
+            ("LIST", 3) | ("ADD", 4) | ("DEL", 4) => (),
+            ("MODE", 4) if &super::upper_case(args[3])[..] == "IDENTIFY" ||
+                           &super::upper_case(args[3])[..] == "RECOGNIZE" => (),
+            _ => return Err("Syntax: NS ACCESS ADD|DEL|LIST [user@host] or \
+                             NS ACCESS MODE IDENTIFY|RECOGNIZE".to_owned())
+        }
+        Ok(Box::new(Access {
+            server: server,
+            state: state,
+            user: user.to_owned(),
+            action: action,
+            parameter: if args.len() == 4 { Some(args[3].to_owned()) } else { None },
+        }))
     }
+}
 
-    #[test]
-    fn identify_failed_password_incorrect() {
-        let u = User::new("test9", "test", None).unwrap();
-        u.save().unwrap();
-        let (data, state) = test_helper(
-            ":test9!test@test PRIVMSG test :NS IDENTIFY tset\r\n", |_| {}
-        );
-        assert!(!state.is_identified("test9"));
-        assert_eq!(&data[..], "NOTICE test9 :Password incorrect.\r\n");
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Access<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let msg = if !self.state.is_identified(&self.user) {
+            "You must be identified to do that.".to_owned()
+        } else if let Ok(mut user) = User::load(&self.user) {
+            let param = self.parameter.as_ref().map(|s| &s[..]).unwrap_or("");
+            match &self.action[..] {
+                "LIST" if user.access.is_empty() => "Your access list is empty.".to_owned(),
+                "LIST" => format!("Access list: {}", user.access.join(", ")),
+                "ADD" if !is_valid_access_mask(param) => {
+                    format!("{} is not a valid user@host mask.", param)
+                },
+                "ADD" => if user.add_access(param) {
+                    try!(user.save());
+                    format!("{} has been added to your access list.", param)
+                } else {
+                    format!("{} is already on your access list.", param)
+                },
+                "DEL" => if user.remove_access(param) {
+                    try!(user.save());
+                    format!("{} has been removed from your access list.", param)
+                } else {
+                    format!("{} is not on your access list.", param)
+                },
+                _ => {
+                    user.access_identifies = &super::upper_case(param)[..] == "IDENTIFY";
+                    try!(user.save());
+                    if user.access_identifies {
+                        "Matching your access list will now identify you.".to_owned()
+                    } else {
+                        "Matching your access list will now only recognize you.".to_owned()
+                    }
+                },
+            }
+        } else {
+            "Failed to update your access list due to an I/O issue.".to_owned()
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
     }
+}
 
-    #[test]
-    fn identify_failed_nickname_unregistered() {
-        let (data, state) = test_helper(
-            ":unregistered!test@test PRIVMSG test :NS IDENTIFY test\r\n", |_| {}
-        );
-        assert!(!state.is_identified("unregistered"));
-        assert_eq!(&data[..], "NOTICE unregistered :Your nick isn't registered.\r\n");
+pub struct Totp<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    enable: bool,
+    code: Option<String>,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Totp<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        let action = if args.len() > 2 { super::upper_case(args[2]) } else { String::new() };
+        match (&action[..], args.len()) {
+            ("ENABLE", 3) | ("DISABLE", 4) => (),
+            _ => return Err("Syntax: NS TOTP ENABLE or NS TOTP DISABLE code".to_owned())
+        }
+        Ok(Box::new(Totp {
+            server: server,
+            state: state,
+            user: user.to_owned(),
+            enable: &action[..] == "ENABLE",
+            code: args.get(3).map(|s| s.to_string()),
+        }))
     }
+}
 
-    #[test]
-    fn ghost_succeeded() {
-        let u = User::new("test6", "test", None).unwrap();
-        u.save().unwrap();
-        let (data, _) = test_helper(
-            ":test!test@test PRIVMSG test :NS GHOST test6 test\r\n", |_| {}
-        );
-        let exp = "KILL test6 :Ghosted by test\r\nNOTICE test6 :User has been ghosted.\r\n";
-        assert_eq!(&data[..], exp);
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Totp<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let msg = if !self.state.is_identified(&self.user) {
+            "You must be identified to do that.".to_owned()
+        } else if let Ok(mut user) = User::load(&self.user) {
+            if self.enable && user.has_totp() {
+                "Two-factor authentication is already enabled.".to_owned()
+            } else if self.enable {
+                let secret = totp::generate_secret();
+                let codes = totp::generate_recovery_codes(8);
+                try!(user.enable_totp(&secret, &codes));
+                try!(user.save());
+                let uri = totp::otpauth_uri(self.server.config().nickname(), &self.user, &secret);
+                format!("Two-factor authentication is now enabled. Your secret is {}.\r\n\
+                         Authenticator URI: {}\r\n\
+                         Recovery codes (each works once, keep them safe): {}\r\n\
+                         From now on, IDENTIFY, GHOST, RECLAIM and CHPASS require a code.",
+                        secret, uri, codes.join(" "))
+            } else if !user.has_totp() {
+                "Two-factor authentication is not enabled.".to_owned()
+            } else if try!(check_second_factor(self.state, &mut user, &self.code)).is_some() {
+                "Two-factor code incorrect.".to_owned()
+            } else {
+                user.disable_totp();
+                try!(user.save());
+                "Two-factor authentication is now disabled.".to_owned()
+            }
+        } else {
+            "Failed to update two-factor authentication due to an I/O issue.".to_owned()
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
     }
+}
 
+pub struct Set<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    setting: Option<Setting>,
+}
 
-    #[test]
-    fn ghost_failed_password_incorrect() {
-        let u = User::new("test8", "test", None).unwrap();
-        u.save().unwrap();
-        let (data, _) = test_helper(
-            ":test!test@test PRIVMSG test :NS GHOST test8 tset\r\n", |_| {}
-        );
-        assert_eq!(&data[..], "NOTICE test :Password incorrect.\r\n");
+impl<'a, T: IrcRead, U: IrcWrite> Set<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        Ok(Box::new(Set {
+            server: server,
+            state: state,
+            user: user.to_owned(),
+            setting: if args.len() > 2 { Some(try!(Setting::parse(&args[2..]))) } else { None },
+        }))
     }
+}
 
-    #[test]
-    fn ghost_failed_nickname_unregistered() {
-        let (data, _) = test_helper(
-            ":test!test@test PRIVMSG test :NS GHOST unregistered test\r\n", |_| {}
-        );
-        let exp = "NOTICE test :That nick isn't registered, and therefore cannot be ghosted.\r\n";
-        assert_eq!(&data[..], exp);
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Set<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let msg = if !self.state.is_identified(&self.user) {
+            "You must be identified to do that.".to_owned()
+        } else if let Ok(mut user) = User::load(&self.user) {
+            match self.setting {
+                Some(ref setting) => {
+                    user.settings.apply(setting.clone());
+                    try!(user.save());
+                    format!("Settings updated: {}", user.settings.display().join(", "))
+                },
+                None => format!("Settings: {}", user.settings.display().join(", ")),
+            }
+        } else {
+            "Failed to update settings due to an I/O issue.".to_owned()
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
     }
+}
 
-    #[test]
-    fn reclaim_succeeded() {
-        let u = User::new("test11", "test", None).unwrap();
-        u.save().unwrap();
-        let (data, state) = test_helper(
-            ":test!test@test PRIVMSG test :NS RECLAIM test11 test\r\n", |_| {}
-        );
-        assert!(state.is_identified("test11"));
-        let exp = "KILL test11 :Reclaimed by test\r\n\
-                   SANICK test test11\r\n\
-                   SAMODE test11 +r\r\n\
-                   NOTICE test11 :Password accepted - you are now recognized.\r\n";
-        assert_eq!(&data[..], exp);
+pub struct Logout<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Logout<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() != 2 {
+            return Err("Syntax: NS LOGOUT".to_owned())
+        }
+        Ok(Box::new(Logout { server: server, state: state, user: user.to_owned() }))
     }
+}
 
-    #[test]
-    fn reclaim_failed_password_incorrect() {
-        let u = User::new("test10", "test", None).unwrap();
-        u.save().unwrap();
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Logout<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let msg = if let Some(account) = self.state.logout(&self.user) {
+            try!(self.server.send_samode(&self.user, "-r", ""));
+            format!("You have been logged out of {}.", account)
+        } else {
+            "You are not identified.".to_owned()
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+pub struct Sessions<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    target: Option<String>,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Sessions<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        let target = match args.len() {
+            2 => None,
+            4 if &super::upper_case(args[2])[..] == "KILL" => Some(args[3].to_owned()),
+            _ => return Err("Syntax: NS SESSIONS [KILL nickname]".to_owned()),
+        };
+        Ok(Box::new(Sessions {
+            server: server, state: state, user: user.to_owned(), target: target
+        }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Sessions<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let msg = match (self.state.get_account(&self.user), &self.target) {
+            (None, _) => "You must be identified to do that.".to_owned(),
+            (Some(account), &None) => {
+                format!("Sessions for {}: {}", account,
+                        self.state.get_sessions(&account).join(", "))
+            },
+            (Some(account), &Some(ref target)) => {
+                if self.state.get_account(target).as_ref() != Some(&account) {
+                    format!("{} is not identified to {}.", target, account)
+                } else {
+                    self.state.logout(target);
+                    try!(self.server.send_samode(target, "-r", ""));
+                    if target != &self.user {
+                        let notice = format!("Your session was logged out by {}.", self.user);
+                        try!(send_reply(self.server, self.state, target, &notice));
+                    }
+                    format!("{} has been logged out of {}.", target, account)
+                }
+            },
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+enum ForbidAction {
+    Add(String),
+    Del(String),
+    List,
+}
+
+pub struct Forbid<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    action: ForbidAction,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Forbid<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        let action = match (args.len(), args.get(2).map(|s| super::upper_case(s))) {
+            (3, Some(ref action)) if &action[..] == "LIST" => ForbidAction::List,
+            (4, Some(ref action)) if &action[..] == "DEL" => ForbidAction::Del(args[3].to_owned()),
+            (3, _) => ForbidAction::Add(args[2].to_owned()),
+            _ => return Err("Syntax: NS FORBID pattern | DEL pattern | LIST".to_owned()),
+        };
+        Ok(Box::new(Forbid { server: server, state: state, user: user.to_owned(), action: action }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Forbid<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let msg = if !is_services_operator(self.server, self.state, &self.user) {
+            "You must be a services operator to do that.".to_owned()
+        } else if let Ok(mut list) = ForbidList::load("nicks") {
+            match self.action {
+                ForbidAction::List if list.patterns.is_empty() => {
+                    "No nicks are forbidden.".to_owned()
+                },
+                ForbidAction::List => format!("Forbidden nicks: {}", list.patterns.join(", ")),
+                ForbidAction::Add(ref pattern) if list.add(pattern) => {
+                    try!(list.save());
+                    try!(log_event(self.server,
+                         &format!("{} forbade nicks matching {}.", self.user, pattern)));
+                    format!("Nicks matching {} are now forbidden.", pattern)
+                },
+                ForbidAction::Add(ref pattern) => format!("{} is already forbidden.", pattern),
+                ForbidAction::Del(ref pattern) if list.remove(pattern) => {
+                    try!(list.save());
+                    try!(log_event(self.server,
+                         &format!("{} removed the forbid on {}.", self.user, pattern)));
+                    format!("Nicks matching {} are no longer forbidden.", pattern)
+                },
+                ForbidAction::Del(ref pattern) => format!("{} is not forbidden.", pattern),
+            }
+        } else {
+            "Failed to update forbidden nicks due to an I/O issue.".to_owned()
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+pub struct Suspend<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    account: String,
+    duration: Option<i64>,
+    reason: String,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Suspend<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() < 5 {
+            return Err("Syntax: NS SUSPEND account duration reason".to_owned())
+        } else if !is_valid_nickname(args[2]) {
+            return Err(format!("{} is not a valid nickname.", args[2]))
+        }
+        Ok(Box::new(Suspend {
+            server: server,
+            state: state,
+            user: user.to_owned(),
+            account: args[2].to_owned(),
+            duration: try!(parse_duration(args[3])),
+            reason: args[4..].join(" "),
+        }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Suspend<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let msg = if !is_services_operator(self.server, self.state, &self.user) {
+            "You must be a services operator to do that.".to_owned()
+        } else if !User::exists(&self.account) {
+            format!("{} is not registered.", self.account)
+        } else if let Ok(mut user) = User::load(&self.account) {
+            let suspension = Suspension::new(&self.user, &self.reason, self.state.now(),
+                                             self.duration);
+            let description = suspension.describe();
+            user.suspension = Some(suspension);
+            try!(user.save());
+            for session in self.state.get_sessions(&self.account).iter() {
+                self.state.logout(session);
+                try!(self.server.send_samode(session, "-r", ""));
+                try!(send_reply(self.server, self.state, session,
+                     &format!("This account has been suspended: {}", description)));
+            }
+            try!(log_event(self.server,
+                 &format!("{} suspended {}: {}", self.user, self.account, description)));
+            format!("{} has been suspended.", self.account)
+        } else {
+            "Failed to suspend account due to an I/O issue.".to_owned()
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+pub struct Unsuspend<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    account: String,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Unsuspend<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() != 3 {
+            return Err("Syntax: NS UNSUSPEND account".to_owned())
+        } else if !is_valid_nickname(args[2]) {
+            return Err(format!("{} is not a valid nickname.", args[2]))
+        }
+        Ok(Box::new(Unsuspend {
+            server: server, state: state, user: user.to_owned(), account: args[2].to_owned()
+        }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Unsuspend<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let msg = if !is_services_operator(self.server, self.state, &self.user) {
+            "You must be a services operator to do that.".to_owned()
+        } else if let Ok(mut user) = User::load(&self.account) {
+            if user.suspension.take().is_some() {
+                try!(user.save());
+                try!(log_event(self.server,
+                     &format!("{} unsuspended {}.", self.user, self.account)));
+                format!("{} is no longer suspended.", self.account)
+            } else {
+                format!("{} is not suspended.", self.account)
+            }
+        } else {
+            format!("{} is not registered.", self.account)
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+pub struct List<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    pattern: Pattern,
+    raw_pattern: String,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> List<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() != 3 {
+            return Err("Syntax: NS LIST pattern".to_owned())
+        }
+        Ok(Box::new(List {
+            server: server,
+            state: state,
+            user: user.to_owned(),
+            pattern: try!(Pattern::parse(args[2])),
+            raw_pattern: args[2].to_owned(),
+        }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for List<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let operator = is_services_operator(self.server, self.state, &self.user);
+        let max_results = config_option(self.server, "list-max-results", 50);
+        let mut matches = 0;
+        for nickname in User::list().unwrap_or(Vec::new()).iter() {
+            let user = match User::load(nickname) {
+                Ok(user) => user,
+                Err(_) => continue,
+            };
+            let line = if operator {
+                let (email, host) = (user.email_for(&self.user, operator), user.last_host());
+                let fields = [Some(&nickname[..]), email, host];
+                if !fields.iter().any(|f| f.map_or(false, |f| self.pattern.matches(f))) {
+                    continue
+                }
+                format!("{} (email: {}, last host: {})", nickname, email.unwrap_or("none"),
+                        host.unwrap_or("unknown"))
+            } else if !user.settings.private && self.pattern.matches(nickname) {
+                nickname.clone()
+            } else {
+                continue
+            };
+            matches += 1;
+            if matches <= max_results {
+                try!(send_reply(self.server, self.state, &self.user, &line));
+            }
+        }
+        let msg = if matches == 0 {
+            format!("No accounts match {}.", self.raw_pattern)
+        } else if matches > max_results {
+            format!("End of list - showing {} of {} matches.", max_results, matches)
+        } else {
+            "End of list.".to_owned()
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+pub struct History<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    account: String,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> History<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() > 3 {
+            return Err("Syntax: NS HISTORY [account]".to_owned())
+        } else if args.len() == 3 && !is_valid_nickname(args[2]) {
+            return Err(format!("{} is not a valid nickname.", args[2]))
+        }
+        Ok(Box::new(History {
+            server: server,
+            state: state,
+            user: user.to_owned(),
+            account: args.get(2).unwrap_or(&user).to_string(),
+        }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for History<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let msg = if self.account == self.user && !self.state.is_identified(&self.user) {
+            "You must be identified to do that.".to_owned()
+        } else if self.account != self.user &&
+                  !is_services_operator(self.server, self.state, &self.user) {
+            "You must be a services operator to do that.".to_owned()
+        } else if let Ok(user) = User::load(&self.account) {
+            if user.history.is_empty() {
+                format!("No hosts have been recorded for {}.", self.account)
+            } else {
+                let hosts: Vec<_> = user.history.iter().rev().map(|r| {
+                    format!("{} ({})", r.hostmask, format_time(r.time))
+                }).collect();
+                format!("Hosts used to identify to {}: {}", self.account, hosts.join(", "))
+            }
+        } else {
+            format!("{} is not registered.", self.account)
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+pub struct Export<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Export<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() != 2 {
+            return Err("Syntax: NS EXPORT".to_owned())
+        }
+        Ok(Box::new(Export { server: server, state: state, user: user.to_owned() }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Export<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let msg = if !self.state.is_identified(&self.user) {
+            "You must be identified to do that.".to_owned()
+        } else if let Ok(user) = User::load(&self.user) {
+            let export = AccountExport::new(&user, self.state.get_votes_by(&self.user),
+                                            self.state.get_games_of(&self.user));
+            let pages = paginate(&export.to_json(), config_option(self.server,
+                                                                  "export-page-size", 400));
+            try!(send_reply(self.server, self.state, &self.user, &format!(
+                "Account data for {} follows in {} parts.", self.user, pages.len()
+            )));
+            for page in pages.iter() {
+                try!(send_reply(self.server, self.state, &self.user, page));
+            }
+            "End of account data.".to_owned()
+        } else {
+            "Failed to export account data due to an I/O issue.".to_owned()
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+pub struct ChangeNick<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    user: String,
+    new_nick: String,
+    password: String,
+    code: Option<String>,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> ChangeNick<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() != 4 && args.len() != 5 {
+            return Err("Syntax: NS CHANGENICK new_nickname password [code]".to_owned())
+        } else if !is_valid_nickname(args[2]) {
+            return Err(format!("{} is not a valid nickname.", args[2]))
+        }
+        Ok(Box::new(ChangeNick {
+            server: server,
+            state: state,
+            user: user.to_owned(),
+            new_nick: args[2].to_owned(),
+            password: args[3].to_owned(),
+            code: args.get(4).map(|s| s.to_string()),
+        }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for ChangeNick<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let msg = if !self.state.is_identified(&self.user) {
+            "You must be identified to do that.".to_owned()
+        } else if User::exists(&self.new_nick) {
+            format!("Nickname {} is already registered!", self.new_nick)
+        } else if ForbidList::is_forbidden("nicks", &self.new_nick) {
+            format!("Nickname {} is forbidden and may not be registered.", self.new_nick)
+        } else if let Err(msg) = policy(self.server).check_nickname(&self.new_nick) {
+            msg
+        } else if let Some(msg) = check_throttle(self.server, self.state, &self.user, &self.user) {
+            msg
+        } else if let Ok(mut user) = User::load(&self.user) {
+            let suspension = suspension_message(self.state, &user);
+            if !try!(user.is_password(&self.password)) {
+                try!(login_failed(self.server, self.state, &self.user, &mut user));
+                "Password incorrect.".to_owned()
+            } else if let Some(err) = try!(check_second_factor(self.state, &mut user, &self.code)) {
+                if self.code.is_some() {
+                    try!(login_failed(self.server, self.state, &self.user, &mut user));
+                }
+                err.to_owned()
+            } else if let Some(msg) = suspension {
+                msg
+            } else {
+                match user.rename(&self.new_nick) {
+                    Ok(failed) => {
+                        login_succeeded(self.state, &self.user);
+                        self.state.rename_account(&self.user, &self.new_nick);
+                        try!(log_event(self.server, &format!(
+                            "{} renamed their account to {}.", self.user, self.new_nick
+                        )));
+                        let mut msg = format!("Your account has been renamed to {}.",
+                                              self.new_nick);
+                        if !failed.is_empty() {
+                            msg.push_str(&format!(" Your access in {} could not be updated due \
+                                                   to an I/O issue.", failed.join(", ")));
+                        }
+                        msg.push_str(&format!(" Change your nick to {} to use it.",
+                                              self.new_nick));
+                        msg
+                    },
+                    Err(_) => if User::exists(&self.user) && !User::exists(&self.new_nick) {
+                        "Failed to rename your account due to an I/O issue. No changes were \
+                         made.".to_owned()
+                    } else {
+                        "Failed to rename your account due to an I/O issue, and it could not \
+                         be fully restored. Please contact a services operator.".to_owned()
+                    },
+                }
+            }
+        } else {
+            "Failed to rename your account due to an I/O issue.".to_owned()
+        };
+        send_reply(self.server, self.state, &self.user, &msg)
+    }
+}
+
+pub struct Release<'a, T: IrcRead, U: IrcWrite> {
+    server: &'a ServerExt<'a, T, U>,
+    state: &'a State,
+    current_nick: String,
+    nickname: String,
+    password: String,
+    code: Option<String>,
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Release<'a, T, U> {
+    pub fn new(server: &'a ServerExt<'a, T, U>, user: &str, args: Vec<&str>, state: &'a State)
+        -> BotResult<Box<Functionality + 'a>> {
+        if args.len() != 4 && args.len() != 5 {
+            return Err("Syntax: NS RELEASE nickname password [code]".to_owned())
+        } else if !is_valid_nickname(args[2]) {
+            return Err(format!("{} is not a valid nickname.", args[2]))
+        }
+        Ok(Box::new(Release {
+            server: server,
+            state: state,
+            current_nick: user.to_owned(),
+            nickname: args[2].to_owned(),
+            password: args[3].to_owned(),
+            code: args.get(4).map(|s| s.to_string()),
+        }))
+    }
+}
+
+impl<'a, T: IrcRead, U: IrcWrite> Functionality for Release<'a, T, U> {
+    fn do_func(&self) -> Result<()> {
+        let msg = if !User::exists(&self.nickname) {
+            "That nick isn't registered, and therefore cannot be released.".to_owned()
+        } else if let Some(msg) = check_throttle(self.server, self.state, &self.current_nick,
+                                                 &self.nickname) {
+            msg
+        } else if let Ok(mut user) = User::load(&self.nickname) {
+            let suspension = suspension_message(self.state, &user);
+            if !try!(user.is_password(&self.password)) {
+                try!(login_failed(self.server, self.state, &self.current_nick, &mut user));
+                "Password incorrect.".to_owned()
+            } else if let Some(err) = try!(check_second_factor(self.state, &mut user, &self.code)) {
+                if self.code.is_some() {
+                    try!(login_failed(self.server, self.state, &self.current_nick, &mut user));
+                }
+                err.to_owned()
+            } else if let Some(msg) = suspension {
+                msg
+            } else if try!(release_nick(self.server, &self.nickname)) {
+                login_succeeded(self.state, &self.nickname);
+                format!("{} has been released.", self.nickname)
+            } else {
+                login_succeeded(self.state, &self.nickname);
+                "Nicks are not held on this network.".to_owned()
+            }
+        } else {
+            "Failed to release nick due to an I/O issue.".to_owned()
+        };
+        send_reply(self.server, self.state, &self.current_nick, &msg)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::ToOwned;
+    use std::fs::remove_file;
+    use std::path::Path;
+    use data::{now, totp};
+    use data::suspension::Suspension;
+    use data::throttle::ThrottleConfig;
+    use data::user::User;
+    use func::test::{test_helper, test_helper_with_options};
+
+    #[test]
+    fn register_succeeded() {
+        let _ = remove_file(Path::new("data/nickserv/test4.json"));
+        let (data, state) = test_helper(
+            ":test4!test@test PRIVMSG test :NS REGISTER test\r\n", |_| {}
+        );
+        assert!(state.is_identified("test4"));
+        let exp = "SAMODE test4 +r\r\n\
+                   NOTICE test4 :Nickname test4 has been registered. \
+                   Don't forget your password!\r\n\
+                   NOTICE test4 :You're now identified.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn register_failed_user_exists() {
+        let u = User::new("test", "test", None).unwrap();
+        u.save().unwrap();
+        let (data, state) = test_helper(
+            ":test!test@test PRIVMSG test :NS REGISTER test\r\n", |_| {}
+        );
+        assert!(!state.is_identified("test"));
+        assert_eq!(&data[..], "NOTICE test :Nickname test is already registered!\r\n");
+    }
+
+    #[test]
+    fn register_failed_policy() {
+        let _ = remove_file(Path::new("data/nickserv/test39.json"));
+        let (data, state) = test_helper(
+            ":test39!test@test PRIVMSG test :NS REGISTER abc\r\n", |_| {}
+        );
+        assert!(!state.is_identified("test39"));
+        assert!(!User::exists("test39"));
+        assert_eq!(&data[..], "NOTICE test39 :Passwords must be at least 4 characters long.\r\n");
+    }
+
+    #[test]
+    fn identify_succeeded() {
+        let u = User::new("test5", "test", None).unwrap();
+        u.save().unwrap();
+        let (data, state) = test_helper(
+            ":test5!test@test PRIVMSG test :NS IDENTIFY test\r\n", |_| {}
+        );
+        assert!(state.is_identified("test5"));
+        let exp = "SAMODE test5 +r\r\n\
+                   NOTICE test5 :Password accepted - you are now recognized.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn identify_failed_password_incorrect() {
+        let u = User::new("test9", "test", None).unwrap();
+        u.save().unwrap();
+        let (data, state) = test_helper(
+            ":test9!test@test PRIVMSG test :NS IDENTIFY tset\r\n", |_| {}
+        );
+        assert!(!state.is_identified("test9"));
+        assert_eq!(&data[..], "NOTICE test9 :Password incorrect.\r\n");
+    }
+
+    #[test]
+    fn identify_failed_nickname_unregistered() {
+        let (data, state) = test_helper(
+            ":unregistered!test@test PRIVMSG test :NS IDENTIFY test\r\n", |_| {}
+        );
+        assert!(!state.is_identified("unregistered"));
+        assert_eq!(&data[..], "NOTICE unregistered :Your nick isn't registered.\r\n");
+    }
+
+    #[test]
+    fn ghost_succeeded() {
+        let u = User::new("test6", "test", None).unwrap();
+        u.save().unwrap();
+        let (data, _) = test_helper(
+            ":test!test@test PRIVMSG test :NS GHOST test6 test\r\n", |_| {}
+        );
+        let exp = "KILL test6 :Ghosted by test\r\nNOTICE test :User has been ghosted.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+
+    #[test]
+    fn ghost_failed_password_incorrect() {
+        let u = User::new("test8", "test", None).unwrap();
+        u.save().unwrap();
+        let (data, _) = test_helper(
+            ":test!test@test PRIVMSG test :NS GHOST test8 tset\r\n", |_| {}
+        );
+        assert_eq!(&data[..], "NOTICE test :Password incorrect.\r\n");
+    }
+
+    #[test]
+    fn ghost_failed_nickname_unregistered() {
+        let (data, _) = test_helper(
+            ":test!test@test PRIVMSG test :NS GHOST unregistered test\r\n", |_| {}
+        );
+        let exp = "NOTICE test :That nick isn't registered, and therefore cannot be ghosted.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn ghost_failed_invalid_nickname() {
+        let (data, _) = test_helper(
+            ":test!test@test PRIVMSG test :NS GHOST ../chanserv/#test66 test\r\n", |_| {}
+        );
+        assert_eq!(&data[..], "NOTICE test :../chanserv/#test66 is not a valid nickname.\r\n");
+    }
+
+    #[test]
+    fn reclaim_succeeded() {
+        let u = User::new("test11", "test", None).unwrap();
+        u.save().unwrap();
+        let (data, state) = test_helper(
+            ":test!test@test PRIVMSG test :NS RECLAIM test11 test\r\n", |_| {}
+        );
+        assert!(state.is_identified("test11"));
+        let exp = "KILL test11 :Reclaimed by test\r\n\
+                   SANICK test test11\r\n\
+                   SAMODE test11 +r\r\n\
+                   NOTICE test11 :Password accepted - you are now recognized.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn reclaim_failed_password_incorrect() {
+        let u = User::new("test10", "test", None).unwrap();
+        u.save().unwrap();
+        let (data, state) = test_helper(
+            ":test!test@test PRIVMSG test :NS RECLAIM test10 tset\r\n", |_| {}
+        );
+        assert!(!state.is_identified("test10"));
+        assert_eq!(&data[..], "NOTICE test :Password incorrect.\r\n");
+    }
+
+    #[test]
+    fn reclaim_failed_nickname_unregistered() {
+        let (data, state) = test_helper(
+            ":test!test@test PRIVMSG test :NS RECLAIM unregistered test\r\n", |_| {}
+        );
+        assert!(!state.is_identified("unregistered"));
+        let exp = "NOTICE test :That nick isn't registered, and therefore cannot be reclaimed.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn chpass_succeeded() {
+        let u = User::new("test13", "test", None).unwrap();
+        u.save().unwrap();
+        let (data, _) = test_helper(
+            ":test13!test@test PRIVMSG test :NS CHPASS test blahblah\r\n", |_| {}
+        );
+        let u = User::load("test13").unwrap();
+        assert!(u.is_password("blahblah").unwrap());
+        let exp = "NOTICE test13 :Your password has been changed. Don't forget it!\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn chpass_failed_password_incorrect() {
+        let u = User::new("test12", "test", None).unwrap();
+        u.save().unwrap();
+        let (data, _) = test_helper(
+            ":test12!test@test PRIVMSG test :NS CHPASS tset blahblah\r\n", |_| {}
+        );
+        let exp = "NOTICE test12 :Password incorrect.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn chpass_failed_policy() {
+        let u = User::new("test40", "test", None).unwrap();
+        u.save().unwrap();
+        let (data, _) = test_helper(
+            ":test40!test@test PRIVMSG test :NS CHPASS test password\r\n", |_| {}
+        );
+        assert!(User::load("test40").unwrap().is_password("test").unwrap());
+        let exp = "NOTICE test40 :That password is too common. Please choose another.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn chpass_failed_nickname_unregistered() {
+        let (data, _) = test_helper(
+            ":unregistered!test@test PRIVMSG test :NS CHPASS blah blahblah\r\n", |_| {}
+        );
+        let exp = "NOTICE unregistered :This nick isn't registered, and therefore doesn't have a \
+                   password to change.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn cert_add_succeeded() {
+        let u = User::new("test14", "test", None).unwrap();
+        u.save().unwrap();
+        let (data, _) = test_helper(
+            ":test14!test@test PRIVMSG test :NS CERT ADD 0123456789abcdef0123456789abcdef\r\n",
+            |state| { state.identify("test14"); }
+        );
+        assert!(User::load("test14").unwrap().has_certfp("0123456789abcdef0123456789abcdef"));
+        let exp = "NOTICE test14 :0123456789abcdef0123456789abcdef has been added to your \
+                   certificate fingerprint list.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn cert_add_failed_not_identified() {
+        let (data, _) = test_helper(
+            ":test!test@test PRIVMSG test :NS CERT ADD 0123456789abcdef0123456789abcdef\r\n",
+            |_| {}
+        );
+        assert_eq!(&data[..], "NOTICE test :You must be identified to do that.\r\n");
+    }
+
+    #[test]
+    fn cert_list() {
+        let mut u = User::new("test15", "test", None).unwrap();
+        u.add_certfp("0123456789abcdef0123456789abcdef");
+        u.save().unwrap();
+        let (data, _) = test_helper(
+            ":test15!test@test PRIVMSG test :NS CERT LIST\r\n", |state| {
+            state.identify("test15");
+        });
+        let exp = "NOTICE test15 :Certificate fingerprints: 0123456789abcdef0123456789abcdef\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn certfp_identify_succeeded() {
+        let mut u = User::new("test16", "test", None).unwrap();
+        u.add_certfp("0123456789abcdef0123456789abcdef");
+        u.save().unwrap();
         let (data, state) = test_helper(
-            ":test!test@test PRIVMSG test :NS RECLAIM test10 tset\r\n", |_| {}
+            ":server 276 test test16 :has client certificate fingerprint \
+             0123456789abcdef0123456789abcdef\r\n", |_| {}
         );
-        assert!(!state.is_identified("test10"));
-        assert_eq!(&data[..], "NOTICE test :Password incorrect.\r\n");
+        assert!(state.is_identified("test16"));
+        let exp = "SAMODE test16 +r\r\n\
+                   NOTICE test16 :Certificate fingerprint accepted - you are now recognized.\r\n";
+        assert_eq!(&data[..], exp);
     }
 
     #[test]
-    fn reclaim_failed_nickname_unregistered() {
+    fn certfp_recognizes_with_totp() {
+        let mut u = User::new("test116", "test", None).unwrap();
+        u.add_certfp("0123456789abcdef0123456789abcdef");
+        u.enable_totp("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ", &[]).unwrap();
+        u.save().unwrap();
         let (data, state) = test_helper(
-            ":test!test@test PRIVMSG test :NS RECLAIM unregistered test\r\n", |_| {}
+            ":server 276 test test116 :has client certificate fingerprint \
+             0123456789abcdef0123456789abcdef\r\n", |_| {}
         );
-        assert!(!state.is_identified("unregistered"));
-        let exp = "NOTICE test :That nick isn't registered, and therefore cannot be reclaimed.\r\n";
+        assert!(!state.is_identified("test116"));
+        assert!(state.is_recognized("test116"));
+        let exp = "NOTICE test116 :Certificate fingerprint accepted - you are now recognized. \
+                   Identify with your two-factor code to use protected commands.\r\n";
         assert_eq!(&data[..], exp);
     }
 
     #[test]
-    fn chpass_succeeded() {
-        let u = User::new("test13", "test", None).unwrap();
+    fn certfp_identify_failed_unknown_fingerprint() {
+        let u = User::new("test17", "test", None).unwrap();
+        u.save().unwrap();
+        let (data, state) = test_helper(
+            ":server 276 test test17 :has client certificate fingerprint \
+             0123456789abcdef0123456789abcdef\r\n", |_| {}
+        );
+        assert!(!state.is_identified("test17"));
+        assert_eq!(&data[..], "");
+    }
+
+    #[test]
+    fn access_add_succeeded() {
+        let u = User::new("test18", "test", None).unwrap();
         u.save().unwrap();
         let (data, _) = test_helper(
-            ":test13!test@test PRIVMSG test :NS CHPASS test blahblah\r\n", |_| {}
+            ":test18!test@test PRIVMSG test :NS ACCESS ADD *@*.example.com\r\n", |state| {
+            state.identify("test18");
+        });
+        assert!(User::load("test18").unwrap().matches_access("user@host.example.com"));
+        let exp = "NOTICE test18 :*@*.example.com has been added to your access list.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn access_add_failed_invalid_mask() {
+        let u = User::new("test19", "test", None).unwrap();
+        u.save().unwrap();
+        let (data, _) = test_helper(
+            ":test19!test@test PRIVMSG test :NS ACCESS ADD *@*\r\n", |state| {
+            state.identify("test19");
+        });
+        assert_eq!(&data[..], "NOTICE test19 :*@* is not a valid user@host mask.\r\n");
+    }
+
+    #[test]
+    fn access_recognizes_on_join() {
+        let mut u = User::new("test20", "test", None).unwrap();
+        u.add_access("test@*.example.com");
+        u.save().unwrap();
+        let (data, state) = test_helper(
+            ":test20!test@host.example.com JOIN :#unregistered\r\n", |_| {}
         );
-        let u = User::load("test13").unwrap();
-        assert!(u.is_password("blahblah").unwrap());
-        let exp = "NOTICE test13 :Your password has been changed. Don't forget it!\r\n";
+        assert!(state.is_recognized("test20"));
+        assert!(!state.is_identified("test20"));
+        let exp = "NOTICE test20 :Your host is on the access list - you are now recognized. \
+                   Identify to use protected commands.\r\n\
+                   WHOIS test20\r\n";
         assert_eq!(&data[..], exp);
     }
 
     #[test]
-    fn chpass_failed_password_incorrect() {
-        let u = User::new("test12", "test", None).unwrap();
+    fn access_identifies_on_nick() {
+        let mut u = User::new("test21", "test", None).unwrap();
+        u.add_access("test@*.example.com");
+        u.access_identifies = true;
+        u.save().unwrap();
+        let (data, state) = test_helper(
+            ":guest!test@host.example.com NICK :test21\r\n", |_| {}
+        );
+        assert!(state.is_identified("test21"));
+        let exp = "SAMODE test21 +r\r\n\
+                   NOTICE test21 :Your host is on the access list - you are now identified.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn access_recognizes_with_totp() {
+        let mut u = User::new("test117", "test", None).unwrap();
+        u.add_access("test@*.example.com");
+        u.access_identifies = true;
+        u.enable_totp("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ", &[]).unwrap();
+        u.save().unwrap();
+        let (data, state) = test_helper(
+            ":guest!test@host.example.com NICK :test117\r\n", |_| {}
+        );
+        assert!(!state.is_identified("test117"));
+        let exp = "NOTICE test117 :Your host is on the access list - you are now recognized. \
+                   Identify to use protected commands.\r\n\
+                   WHOIS test117\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn totp_enable_succeeded() {
+        let u = User::new("test24", "test", None).unwrap();
         u.save().unwrap();
         let (data, _) = test_helper(
-            ":test12!test@test PRIVMSG test :NS CHPASS tset blahblah\r\n", |_| {}
+            ":test24!test@test PRIVMSG test :NS TOTP ENABLE\r\n", |state| {
+            state.identify("test24");
+        });
+        let u = User::load("test24").unwrap();
+        assert!(u.has_totp());
+        assert_eq!(u.recovery_codes.len(), 8);
+        let exp = format!("NOTICE test24 :Two-factor authentication is now enabled. Your secret \
+                           is {}.\r\n", u.totp_secret.unwrap());
+        assert!(data.starts_with(&exp));
+    }
+
+    #[test]
+    fn identify_failed_missing_code() {
+        let mut u = User::new("test25", "test", None).unwrap();
+        u.enable_totp("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ", &[]).unwrap();
+        u.save().unwrap();
+        let (data, state) = test_helper(
+            ":test25!test@test PRIVMSG test :NS IDENTIFY test\r\n", |_| {}
         );
-        let exp = "NOTICE test12 :Password incorrect.\r\n";
+        assert!(!state.is_identified("test25"));
+        assert_eq!(&data[..], "NOTICE test25 :This account requires a two-factor code.\r\n");
+    }
+
+    #[test]
+    fn identify_with_code_succeeded() {
+        let mut u = User::new("test26", "test", None).unwrap();
+        u.enable_totp("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ", &[]).unwrap();
+        u.save().unwrap();
+        let code = totp::code_at("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ", now()).unwrap();
+        let (data, state) = test_helper(
+            &format!(":test26!test@test PRIVMSG test :NS IDENTIFY test {}\r\n", code), |_| {}
+        );
+        assert!(state.is_identified("test26"));
+        let exp = "SAMODE test26 +r\r\n\
+                   NOTICE test26 :Password accepted - you are now recognized.\r\n";
         assert_eq!(&data[..], exp);
     }
 
     #[test]
-    fn chpass_failed_nickname_unregistered() {
+    fn ghost_failed_code_incorrect() {
+        let mut u = User::new("test27", "test", None).unwrap();
+        u.enable_totp("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ", &["recovery".to_owned()]).unwrap();
+        u.save().unwrap();
         let (data, _) = test_helper(
-            ":unregistered!test@test PRIVMSG test :NS CHPASS blah blahblah\r\n", |_| {}
+            ":test!test@test PRIVMSG test :NS GHOST test27 test wrong\r\n", |_| {}
         );
-        let exp = "NOTICE unregistered :This nick isn't registered, and therefore doesn't have a \
-                   password to change.\r\n";
+        assert_eq!(&data[..], "NOTICE test :Two-factor code incorrect.\r\n");
+    }
+
+    #[test]
+    fn identify_failed_throttled() {
+        let u = User::new("test28", "test", None).unwrap();
+        u.save().unwrap();
+        let (data, state) = test_helper(
+            ":test28!test@test PRIVMSG test :NS IDENTIFY test\r\n", |state| {
+            state.throttle_fail("account:test28", now(), &ThrottleConfig::new(1, 1, 900));
+        });
+        assert!(!state.is_identified("test28"));
+        assert!(data.starts_with("NOTICE test28 :Too many failed attempts. Try again in "));
+    }
+
+    #[test]
+    fn identify_delivers_lock_notice() {
+        let mut u = User::new("test29", "test", None).unwrap();
+        u.lock_notice = Some("Your account was locked.".to_owned());
+        u.save().unwrap();
+        let (data, _) = test_helper(
+            ":test29!test@test PRIVMSG test :NS IDENTIFY test\r\n", |_| {}
+        );
+        assert!(User::load("test29").unwrap().lock_notice.is_none());
+        let exp = "SAMODE test29 +r\r\n\
+                   NOTICE test29 :Your account was locked.\r\n\
+                   NOTICE test29 :Password accepted - you are now recognized.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn set_list() {
+        let u = User::new("test30", "test", None).unwrap();
+        u.save().unwrap();
+        let (data, _) = test_helper(
+            ":test30!test@test PRIVMSG test :NS SET\r\n", |state| {
+            state.identify("test30");
+        });
+        let exp = "NOTICE test30 :Settings: HIDEMAIL OFF, ENFORCE OFF (60 seconds), MSG OFF, \
+                   LANGUAGE en, PRIVATE OFF\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn set_msg() {
+        let u = User::new("test31", "test", None).unwrap();
+        u.save().unwrap();
+        let (data, _) = test_helper(
+            ":test31!test@test PRIVMSG test :NS SET MSG ON\r\n", |state| {
+            state.identify("test31");
+        });
+        assert!(User::load("test31").unwrap().settings.use_privmsg);
+        let exp = "PRIVMSG test31 :Settings updated: HIDEMAIL OFF, ENFORCE OFF (60 seconds), \
+                   MSG ON, LANGUAGE en, PRIVATE OFF\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn set_failed_invalid_option() {
+        let (data, _) = test_helper(
+            ":test!test@test PRIVMSG test :NS SET PRIVATE maybe\r\n", |_| {}
+        );
+        assert_eq!(&data[..], "NOTICE test :maybe is not a valid value. Use ON or OFF.\r\n");
+    }
+
+    #[test]
+    fn enforce_warning_on_nick() {
+        let mut u = User::new("test32", "test", None).unwrap();
+        u.settings.enforce = true;
+        u.save().unwrap();
+        let (data, _) = test_helper(":guest!test@test NICK :test32\r\n", |_| {});
+        let exp = "WHOIS test32\r\n\
+                   NOTICE test32 :This nickname is registered and protected. If it is yours, \
+                   identify within 60 seconds. Otherwise, please choose a different nickname.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn logout() {
+        let (data, state) = test_helper(
+            ":test33!test@test PRIVMSG test :NS LOGOUT\r\n", |state| {
+            state.identify("test33");
+        });
+        assert!(!state.is_identified("test33"));
+        let exp = "SAMODE test33 -r\r\n\
+                   NOTICE test33 :You have been logged out of test33.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn logout_failed_not_identified() {
+        let (data, _) = test_helper(":test34!test@test PRIVMSG test :NS LOGOUT\r\n", |_| {});
+        assert_eq!(&data[..], "NOTICE test34 :You are not identified.\r\n");
+    }
+
+    #[test]
+    fn sessions_follow_nick_changes() {
+        let (_, state) = test_helper(":test35!test@test NICK :test35_away\r\n", |state| {
+            state.identify("test35");
+        });
+        assert!(!state.is_identified("test35_away"));
+        assert_eq!(state.get_account("test35_away"), Some("test35".to_owned()));
+        assert_eq!(state.get_sessions("test35"), vec!["test35_away".to_owned()]);
+    }
+
+    #[test]
+    fn sessions_list() {
+        let (data, _) = test_helper(
+            ":test36!test@test PRIVMSG test :NS SESSIONS\r\n", |state| {
+            state.identify("test36");
+            state.change_nick("test36", "test36_away");
+            state.identify("test36");
+        });
+        assert_eq!(&data[..], "NOTICE test36 :Sessions for test36: test36, test36_away\r\n");
+    }
+
+    #[test]
+    fn sessions_kill() {
+        let (data, state) = test_helper(
+            ":test37!test@test PRIVMSG test :NS SESSIONS KILL test37_old\r\n", |state| {
+            state.identify("test37");
+            state.change_nick("test37", "test37_old");
+            state.identify("test37");
+        });
+        assert!(state.get_account("test37_old").is_none());
+        assert!(state.is_identified("test37"));
+        let exp = "SAMODE test37_old -r\r\n\
+                   NOTICE test37_old :Your session was logged out by test37.\r\n\
+                   NOTICE test37 :test37_old has been logged out of test37.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn sessions_kill_failed_other_account() {
+        let (data, _) = test_helper(
+            ":test38!test@test PRIVMSG test :NS SESSIONS KILL test37\r\n", |state| {
+            state.identify("test38");
+            state.identify("test37");
+        });
+        assert_eq!(&data[..], "NOTICE test38 :test37 is not identified to test38.\r\n");
+    }
+
+    #[test]
+    fn forbid_and_register() {
+        let _ = remove_file(Path::new("data/nickserv/test41.json"));
+        let (data, _) = test_helper(
+            ":test!test@test PRIVMSG test :NS FORBID test41*\r\n", |state| {
+            state.identify("test");
+        });
+        assert_eq!(&data[..], "NOTICE test :Nicks matching test41* are now forbidden.\r\n");
+        let (data, _) = test_helper(
+            ":test41!test@test PRIVMSG test :NS REGISTER hunter2\r\n", |_| {}
+        );
+        assert!(!User::exists("test41"));
+        let exp = "NOTICE test41 :Nickname test41 is forbidden and may not be registered.\r\n";
+        assert_eq!(&data[..], exp);
+        let (data, _) = test_helper(
+            ":test!test@test PRIVMSG test :NS FORBID DEL test41*\r\n", |state| {
+            state.identify("test");
+        });
+        assert_eq!(&data[..], "NOTICE test :Nicks matching test41* are no longer forbidden.\r\n");
+    }
+
+    #[test]
+    fn forbid_failed_not_operator() {
+        let (data, _) = test_helper(
+            ":test42!test@test PRIVMSG test :NS FORBID *\r\n", |state| {
+            state.identify("test42");
+        });
+        assert_eq!(&data[..], "NOTICE test42 :You must be a services operator to do that.\r\n");
+    }
+
+    #[test]
+    fn suspend() {
+        let u = User::new("test43", "test", None).unwrap();
+        u.save().unwrap();
+        let (data, state) = test_helper(
+            ":test!test@test PRIVMSG test :NS SUSPEND test43 0 spamming\r\n", |state| {
+            state.identify("test");
+            state.identify("test43");
+        });
+        assert!(!state.is_identified("test43"));
+        assert!(User::load("test43").unwrap().active_suspension(now()).is_some());
+        let exp = "SAMODE test43 -r\r\n\
+                   NOTICE test43 :This account has been suspended: spamming (by test, \
+                   permanently)\r\n\
+                   NOTICE test :test43 has been suspended.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn identify_failed_suspended() {
+        let mut u = User::new("test44", "test", None).unwrap();
+        u.suspension = Some(Suspension::new("test", "spamming", now(), None));
+        u.save().unwrap();
+        let (data, state) = test_helper(
+            ":test44!test@test PRIVMSG test :NS IDENTIFY test\r\n", |_| {}
+        );
+        assert!(!state.is_identified("test44"));
+        let exp = "NOTICE test44 :This account is suspended: spamming (by test, permanently)\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn identify_suspended_hides_reason() {
+        let mut u = User::new("test125", "test", None).unwrap();
+        u.suspension = Some(Suspension::new("test", "spamming", now(), None));
+        u.save().unwrap();
+        let (data, _) = test_helper(
+            ":test125!test@test PRIVMSG test :NS IDENTIFY wrong\r\n", |_| {}
+        );
+        assert_eq!(&data[..], "NOTICE test125 :Password incorrect.\r\n");
+    }
+
+    #[test]
+    fn reclaim_failed_suspended() {
+        let mut u = User::new("test121", "test", None).unwrap();
+        u.suspension = Some(Suspension::new("test", "spamming", now(), None));
+        u.save().unwrap();
+        let (data, state) = test_helper(
+            ":test!test@test PRIVMSG test :NS RECLAIM test121 test\r\n", |_| {}
+        );
+        assert!(!state.is_identified("test121"));
+        let exp = "NOTICE test :This account is suspended: spamming (by test, permanently)\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn unsuspend() {
+        let mut u = User::new("test45", "test", None).unwrap();
+        u.suspension = Some(Suspension::new("test", "spamming", now(), Some(60)));
+        u.save().unwrap();
+        let (data, _) = test_helper(
+            ":test!test@test PRIVMSG test :NS UNSUSPEND test45\r\n", |state| {
+            state.identify("test");
+        });
+        assert!(User::load("test45").unwrap().suspension.is_none());
+        assert_eq!(&data[..], "NOTICE test :test45 is no longer suspended.\r\n");
+    }
+
+    #[test]
+    fn list_operator() {
+        let u = User::new("test46", "test", Some("test46@abuse.example.com")).unwrap();
+        u.save().unwrap();
+        let (data, _) = test_helper(
+            ":test!test@test PRIVMSG test :NS LIST *@abuse.example.com\r\n", |state| {
+            state.identify("test");
+        });
+        let exp = "NOTICE test :test46 (email: test46@abuse.example.com, last host: unknown)\r\n\
+                   NOTICE test :End of list.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn list_operator_sees_hidden_email() {
+        let mut u = User::new("test120", "test", Some("test120@hidden.example.com")).unwrap();
+        u.settings.hide_email = true;
+        u.save().unwrap();
+        let (data, _) = test_helper(
+            ":test!test@test PRIVMSG test :NS LIST test120\r\n\
+             :test!test@test PRIVMSG test :NS LIST *@hidden.example.com\r\n", |state| {
+            state.identify("test");
+        });
+        let exp = "NOTICE test :test120 (email: test120@hidden.example.com, last host: unknown)\r\n\
+                   NOTICE test :End of list.\r\n\
+                   NOTICE test :test120 (email: test120@hidden.example.com, last host: unknown)\r\n\
+                   NOTICE test :End of list.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn list_hides_private_accounts() {
+        let mut u = User::new("test47", "test", None).unwrap();
+        u.settings.private = true;
+        u.save().unwrap();
+        let u = User::new("test47a", "test", None).unwrap();
+        u.save().unwrap();
+        let (data, _) = test_helper(
+            ":test48!test@test PRIVMSG test :NS LIST /^test47/\r\n", |_| {}
+        );
+        assert_eq!(&data[..], "NOTICE test48 :test47a\r\nNOTICE test48 :End of list.\r\n");
+    }
+
+    #[test]
+    fn history() {
+        let u = User::new("test49", "test", None).unwrap();
+        u.save().unwrap();
+        let (_, _) = test_helper(
+            ":test49!user@history.example.com PRIVMSG test :NS IDENTIFY test\r\n", |_| {}
+        );
+        assert_eq!(User::load("test49").unwrap().last_host(), Some("user@history.example.com"));
+        let (data, _) = test_helper(
+            ":test49!test@test PRIVMSG test :NS HISTORY\r\n", |state| {
+            state.identify("test49");
+        });
+        let exp = "NOTICE test49 :Hosts used to identify to test49: user@history.example.com (";
+        assert!(data.starts_with(exp));
+    }
+
+    #[test]
+    fn history_failed_not_operator() {
+        let (data, _) = test_helper(
+            ":test48!test@test PRIVMSG test :NS HISTORY test49\r\n", |state| {
+            state.identify("test48");
+        });
+        assert_eq!(&data[..], "NOTICE test48 :You must be a services operator to do that.\r\n");
+    }
+
+    #[test]
+    fn export() {
+        let u = User::new("test64", "test", Some("test64@example.com")).unwrap();
+        u.save().unwrap();
+        let (data, _) = test_helper(
+            ":test64!test@test PRIVMSG test :NS EXPORT\r\n", |state| {
+            state.identify("test64");
+        });
+        let lines: Vec<_> = data.lines().collect();
+        assert!(lines[0].starts_with("NOTICE test64 :Account data for test64 follows in "));
+        assert_eq!(lines[lines.len() - 1], "NOTICE test64 :End of account data.");
+        let json: String = lines[1..lines.len() - 1].iter().map(|l| &l[15..]).collect();
+        assert!(json.starts_with("{\"nickname\":\"test64\",\"email\":\"test64@example.com\""));
+        assert!(!json.contains(&u.password));
+    }
+
+    #[test]
+    fn change_nick() {
+        let _ = remove_file(Path::new("data/nickserv/test66_new.json"));
+        let u = User::new("test66", "test", None).unwrap();
+        u.save().unwrap();
+        let (data, state) = test_helper(
+            ":test66!test@test PRIVMSG test :NS CHANGENICK test66_new test\r\n\
+             :test66!test@test NICK :test66_new\r\n", |state| {
+            state.identify("test66");
+        });
+        assert!(!User::exists("test66"));
+        assert!(User::exists("test66_new"));
+        assert!(state.is_identified("test66_new"));
+        let exp = "NOTICE test66 :Your account has been renamed to test66_new. Change your nick \
+                   to test66_new to use it.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn change_nick_failed_registered() {
+        let u = User::new("test67", "test", None).unwrap();
+        u.save().unwrap();
+        let (data, _) = test_helper(
+            ":test66!test@test PRIVMSG test :NS CHANGENICK test67 test\r\n", |state| {
+            state.identify("test66");
+        });
+        assert_eq!(&data[..], "NOTICE test66 :Nickname test67 is already registered!\r\n");
+    }
+
+    #[test]
+    fn change_nick_failed_invalid() {
+        let (data, _) = test_helper(
+            ":test66!test@test PRIVMSG test :NS CHANGENICK ../test66 test\r\n", |state| {
+            state.identify("test66");
+        });
+        assert_eq!(&data[..], "NOTICE test66 :../test66 is not a valid nickname.\r\n");
+    }
+
+    #[test]
+    fn ghost_guest_mode() {
+        let u = User::new("test68", "test", None).unwrap();
+        u.save().unwrap();
+        let (data, _) = test_helper_with_options(
+            ":test!test@test PRIVMSG test :NS GHOST test68 test\r\n",
+            &[("reclaim-mode", "guest"), ("nick-hold", "svshold")], |_| {}
+        );
+        let exp = "NOTICE test68 :This nickname is registered. Your nick has been changed to \
+                   Guest1.\r\n\
+                   SANICK test68 Guest1\r\n\
+                   SVSHOLD test68 60 :Held by services\r\n\
+                   NOTICE test :User has been ghosted.\r\n";
         assert_eq!(&data[..], exp);
     }
+
+    #[test]
+    fn ghost_guest_mode_skips_used_nick() {
+        let u = User::new("test122", "test", None).unwrap();
+        u.save().unwrap();
+        let (data, _) = test_helper_with_options(
+            ":test!test@test PRIVMSG test :NS GHOST test122 test\r\n",
+            &[("reclaim-mode", "guest")], |state| {
+            state.update_hostmask("Guest1!test@test");
+        });
+        let exp = "NOTICE test122 :This nickname is registered. Your nick has been changed to \
+                   Guest2.\r\n\
+                   SANICK test122 Guest2\r\n\
+                   NOTICE test :User has been ghosted.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn reclaim_guest_mode() {
+        let u = User::new("test69", "test", None).unwrap();
+        u.save().unwrap();
+        let (data, state) = test_helper_with_options(
+            ":test!test@test PRIVMSG test :NS RECLAIM test69 test\r\n",
+            &[("reclaim-mode", "guest"), ("guest-prefix", "Visitor")], |_| {}
+        );
+        assert!(state.is_identified("test69"));
+        let exp = "NOTICE test69 :This nickname is registered. Your nick has been changed to \
+                   Visitor1.\r\n\
+                   SANICK test69 Visitor1\r\n\
+                   SANICK test test69\r\n\
+                   SAMODE test69 +r\r\n\
+                   NOTICE test69 :Password accepted - you are now recognized.\r\n";
+        assert_eq!(&data[..], exp);
+    }
+
+    #[test]
+    fn release() {
+        let u = User::new("test70", "test", None).unwrap();
+        u.save().unwrap();
+        let (data, _) = test_helper_with_options(
+            ":test!test@test PRIVMSG test :NS RELEASE test70 test\r\n",
+            &[("nick-hold", "nickdelay")], |_| {}
+        );
+        assert_eq!(&data[..], "NICKDELAY 0 test70\r\nNOTICE test :test70 has been released.\r\n");
+    }
 }
@@ -0,0 +1,115 @@
+use super::{chanserv, config_option, nickserv};
+use std::io::Result;
+use data::channel::Channel;
+use data::scheduler::Task;
+use data::state::State;
+use irc::client::prelude::*;
+
+/// Runs every job that has come due. This is driven by incoming messages, so the server's
+/// regular PINGs bound how late a job can run. A job that fails is logged and tried again
+/// later, so that one bad job can't take the bot down or stop the others.
+pub fn run_due<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>, state: &State)
+    -> Result<()> {
+    let jobs = match state.take_due_jobs() {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            println!("Failed to take due jobs: {}", e);
+            return Ok(())
+        },
+    };
+    for job in jobs.into_iter() {
+        if let Err(e) = run_job(server, state, &job.task) {
+            println!("Scheduled job {} failed, retrying later: {}", job.id, e);
+            let retry: i64 = config_option(server, "job-retry-interval", 60);
+            // The job stays queued in memory even if the schedule can't be saved right now.
+            let _ = state.schedule(state.now() + retry, job.task);
+        }
+    }
+    Ok(())
+}
+
+fn run_job<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>, state: &State,
+                                        task: &Task) -> Result<()> {
+    match *task {
+        Task::Unban(ref channel, ref mask) => {
+            if let Ok(mut chan) = Channel::load(channel) {
+                chan.remove_ban(mask);
+                try!(chan.save());
+            }
+            try!(server.send_samode(channel, "-b", mask));
+        },
+        Task::RemoveVoice(ref channel, ref account) => {
+            if let Ok(mut chan) = Channel::load(channel) {
+                chan.voice.retain(|u| u != account);
+                try!(chan.save());
+            }
+            for session in state.get_sessions(account).iter() {
+                try!(server.send_samode(channel, "-v", session));
+            }
+        },
+        Task::ExpireProposal(ref channel, id) => {
+            try!(expire_proposal(server, state, channel, id));
+        },
+        Task::Enforce(ref nickname) => {
+            try!(nickserv::enforce_nick(server, state, nickname));
+        },
+        Task::Restrict(ref channel, ref nickname) => {
+            try!(chanserv::enforce_restricted_grace(server, state, channel, nickname));
+        },
+    }
+    Ok(())
+}
+
+#[cfg(feature = "democracy")]
+fn expire_proposal<'a, T: IrcRead, U: IrcWrite>(server: &'a ServerExt<'a, T, U>, state: &State,
+                                                channel: &str, id: u8) -> Result<()> {
+    let expired = state.get_votes().get_mut(channel).map_or(false, |d| d.expire(id));
+    if expired {
+        try!(server.send_privmsg(channel, &format!("Proposal {} has expired.", id)));
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "democracy"))]
+fn expire_proposal<'a, T: IrcRead, U: IrcWrite>(_: &'a ServerExt<'a, T, U>, _: &State, _: &str,
+                                                _: u8) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::ToOwned;
+    use data::channel::{Ban, Channel};
+    use data::scheduler::{FixedClock, Task};
+    use func::test::test_helper;
+
+    #[test]
+    fn enforce_when_due() {
+        let (data, _) = test_helper(":test118!test@test PRIVMSG #test :Hi there!\r\n", |state| {
+            state.set_clock(Box::new(FixedClock(60)));
+            state.schedule(60, Task::Enforce("test118".to_owned())).unwrap();
+            state.schedule(60, Task::Enforce("test119".to_owned())).unwrap();
+        });
+        assert_eq!(&data[..], "KILL test118 :Nickname enforcement\r\n");
+    }
+
+    #[test]
+    fn unban_when_due() {
+        let mut ch = Channel::new("#test48", "test", "test").unwrap();
+        ch.add_ban(Ban::new("*!*@spam.example.com", "test", None, 0, Some(60)));
+        ch.save().unwrap();
+        let (data, _) = test_helper(":test!test@test PRIVMSG #test :Hi there!\r\n", |state| {
+            state.set_clock(Box::new(FixedClock(30)));
+            state.schedule(60, Task::Unban("#test48".to_owned(),
+                                           "*!*@spam.example.com".to_owned())).unwrap();
+        });
+        assert_eq!(&data[..], "");
+        let (data, _) = test_helper(":test!test@test PRIVMSG #test :Hi there!\r\n", |state| {
+            state.set_clock(Box::new(FixedClock(60)));
+            state.schedule(60, Task::Unban("#test48".to_owned(),
+                                           "*!*@spam.example.com".to_owned())).unwrap();
+        });
+        assert_eq!(&data[..], "SAMODE #test48 -b *!*@spam.example.com\r\n");
+        assert!(Channel::load("#test48").unwrap().bans().is_empty());
+    }
+}
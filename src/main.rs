@@ -2,7 +2,9 @@
 extern crate irc;
 extern crate openssl;
 #[cfg(feature = "resistance")] extern crate rand;
+extern crate regex;
 extern crate rustc_serialize;
+extern crate time;
 
 #[cfg(not(test))] use data::state::State;
 #[cfg(not(test))] use irc::client::prelude::*;
@@ -17,6 +19,9 @@ fn main() {
     for message in server.iter() {
         let message = message.unwrap();
         print!("{}", message.into_string());
+        if let Some(ref prefix) = message.prefix {
+            state.update_hostmask(prefix);
+        }
         let mut args = Vec::new();
         args.extend(message.args.iter().map(|s| &s[..]));
         if let Some(ref suffix) = message.suffix {